@@ -1,21 +1,20 @@
-use std::{env, path::PathBuf};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 fn main() {
     println!("cargo:rerun-if-changed=kernel/src");
+    println!("cargo:rerun-if-changed=kernel/fonts");
+    println!("cargo:rerun-if-changed=kernel/images");
     println!("cargo:rerun-if-changed=kernel/Cargo.toml");
-
-    let kernel_path = env::vars_os()
-        .find_map(|(k, v)| {
-            let key = k.to_string_lossy();
-            if key.starts_with("CARGO_BIN_FILE_KERNEL_") {
-                Some(PathBuf::from(v))
-            } else {
-                None
-            }
-        })
-        .expect("kernel artifact path env var not found");
+    println!("cargo:rerun-if-changed=kernel/.cargo/config.toml");
+    println!("cargo:rerun-if-changed=kernel/x86_64-kernel.json");
+    println!("cargo:rerun-if-env-changed=KERNEL_RAMDISK");
 
     let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR not set"));
+    let kernel_path = build_kernel(&out_dir);
     let bios_path = out_dir.join("phils-rust-os-bios.img");
 
     let mut boot_config = bootloader::BootConfig::default();
@@ -25,8 +24,45 @@ fn main() {
 
     let mut bios = bootloader::BiosBoot::new(&kernel_path);
     bios.set_boot_config(&boot_config);
+    // Optional file handed to the kernel in memory, e.g. a PSF font for the console.
+    let ramdisk_path = env::var_os("KERNEL_RAMDISK").map(PathBuf::from);
+    if let Some(ramdisk_path) = &ramdisk_path {
+        println!("cargo:rerun-if-changed={}", ramdisk_path.display());
+        bios.set_ramdisk(ramdisk_path);
+    }
     bios.create_disk_image(&bios_path)
         .expect("failed to create BIOS disk image");
 
     println!("cargo:rustc-env=BIOS_IMAGE={}", bios_path.display());
 }
+
+/// Builds the kernel for its hard-float target, set up in `kernel/.cargo/config.toml`. That
+/// target needs `-Zbuild-std`, which cargo cannot combine with an artifact dependency, so this
+/// runs cargo on the kernel directly.
+fn build_kernel(out_dir: &Path) -> PathBuf {
+    let kernel_dir =
+        PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").expect("no manifest dir")).join("kernel");
+    // A target directory of its own, since the one this build runs in is locked.
+    let target_dir = out_dir.join("kernel");
+    let profile = env::var("PROFILE").expect("PROFILE not set");
+
+    let mut cargo = Command::new(env::var_os("CARGO").unwrap_or_else(|| "cargo".into()));
+    cargo
+        .current_dir(&kernel_dir)
+        .arg("build")
+        .arg("--target-dir")
+        .arg(&target_dir)
+        // Meant for the launcher, not the kernel.
+        .env_remove("CARGO_ENCODED_RUSTFLAGS")
+        .env_remove("RUSTC_WORKSPACE_WRAPPER");
+    if profile == "release" {
+        cargo.arg("--release");
+    }
+    let status = cargo.status().expect("failed to run cargo for the kernel");
+    assert!(status.success(), "building the kernel failed");
+
+    target_dir
+        .join("x86_64-kernel")
+        .join(profile)
+        .join("kernel")
+}
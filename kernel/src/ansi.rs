@@ -0,0 +1,680 @@
+use crate::cp437;
+use crate::vga_text_mode::VGAColorCode;
+
+const MAX_PARAMS: usize = 8;
+
+/// Widest line `TabStops` can track.
+pub const MAX_TAB_COLUMNS: usize = 256;
+
+/// Default distance between tab stops.
+pub const DEFAULT_TAB_WIDTH: usize = 8;
+
+/// ANSI colour index (0-7 normal, 8-15 bright) to the matching VGA palette entry.
+const ANSI_TO_VGA: [VGAColorCode; 16] = [
+    VGAColorCode::Black,
+    VGAColorCode::Red,
+    VGAColorCode::Green,
+    VGAColorCode::Brown,
+    VGAColorCode::Blue,
+    VGAColorCode::Magenta,
+    VGAColorCode::Cyan,
+    VGAColorCode::LightGray,
+    VGAColorCode::DarkGray,
+    VGAColorCode::LightRed,
+    VGAColorCode::LightGreen,
+    VGAColorCode::Yellow,
+    VGAColorCode::LightBlue,
+    VGAColorCode::Pink,
+    VGAColorCode::LightCyan,
+    VGAColorCode::White,
+];
+
+/// The 16 ANSI colours as the VGA palette shows them, to find the closest one to an RGB value.
+const ANSI_RGB: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xAA, 0x00, 0x00),
+    (0x00, 0xAA, 0x00),
+    (0xAA, 0x55, 0x00),
+    (0x00, 0x00, 0xAA),
+    (0xAA, 0x00, 0xAA),
+    (0x00, 0xAA, 0xAA),
+    (0xAA, 0xAA, 0xAA),
+    (0x55, 0x55, 0x55),
+    (0xFF, 0x55, 0x55),
+    (0x55, 0xFF, 0x55),
+    (0xFF, 0xFF, 0x55),
+    (0x55, 0x55, 0xFF),
+    (0xFF, 0x55, 0xFF),
+    (0x55, 0xFF, 0xFF),
+    (0xFF, 0xFF, 0xFF),
+];
+
+/// ANSI colour index nearest to entry `n` of the xterm 256-colour palette.
+fn indexed_to_ansi(n: u16) -> u8 {
+    match n {
+        0..=15 => n as u8,
+        // 6x6x6 colour cube.
+        16..=231 => {
+            let level = |v: u16| if v == 0 { 0 } else { 55 + v * 40 };
+            let n = n - 16;
+            rgb_to_ansi(level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        // Grey ramp.
+        _ => {
+            let grey = 8 + (n.min(255) - 232) * 10;
+            rgb_to_ansi(grey, grey, grey)
+        }
+    }
+}
+
+/// ANSI colour index nearest to an RGB colour.
+fn rgb_to_ansi(r: u16, g: u16, b: u16) -> u8 {
+    let distance = |&(pr, pg, pb): &(u8, u8, u8)| {
+        let d = |value: u16, palette: u8| (i32::from(value.min(255)) - i32::from(palette)).pow(2);
+        d(r, pr) + d(g, pg) + d(b, pb)
+    };
+    (0..ANSI_RGB.len())
+        .min_by_key(|&i| distance(&ANSI_RGB[i]))
+        .unwrap_or(0) as u8
+}
+
+/// What a text console has to provide for `AnsiParser` to drive it.
+/// Columns and rows are zero based; the scroll region is inclusive on both ends.
+pub trait AnsiScreen {
+    fn size(&self) -> (usize, usize);
+    fn cursor(&self) -> (usize, usize);
+    fn set_cursor(&mut self, col: usize, row: usize);
+    fn default_colors(&self) -> (VGAColorCode, VGAColorCode);
+    fn set_colors(&mut self, foreground: VGAColorCode, background: VGAColorCode);
+    fn set_blink(&mut self, blink: bool);
+    /// Blanks columns `start_col..end_col` of `row` in the current background colour.
+    fn erase(&mut self, row: usize, start_col: usize, end_col: usize);
+    fn set_scroll_region(&mut self, top: usize, bottom: usize);
+    /// Scrolls the scroll region up by `lines`, blanking the lines exposed at its bottom.
+    fn scroll_up(&mut self, lines: usize);
+    /// Scrolls the scroll region down by `lines`, blanking the lines exposed at its top.
+    fn scroll_down(&mut self, lines: usize);
+    /// The console's tab stops, used for horizontal tabs and the sequences that set and clear them.
+    fn tab_stops(&mut self) -> &mut TabStops;
+    /// A code page 437 glyph, to be written at the cursor.
+    fn print(&mut self, byte: u8);
+    /// A C0 control byte such as newline or backspace.
+    fn control(&mut self, byte: u8);
+}
+
+/// Set of columns a horizontal tab can stop at, one bit per column.
+#[derive(Debug, Clone, Copy)]
+pub struct TabStops {
+    stops: [u64; MAX_TAB_COLUMNS / 64],
+}
+
+impl TabStops {
+    /// Tab stops every `width` columns, starting at column `width`.
+    pub const fn new(width: usize) -> TabStops {
+        let mut tabs = TabStops {
+            stops: [0; MAX_TAB_COLUMNS / 64],
+        };
+        tabs.set_width(width);
+        tabs
+    }
+
+    /// Replaces all tab stops with ones every `width` columns. A width of 0 clears them all.
+    pub const fn set_width(&mut self, width: usize) {
+        self.stops = [0; MAX_TAB_COLUMNS / 64];
+        if width == 0 {
+            return;
+        }
+        let mut col = width;
+        while col < MAX_TAB_COLUMNS {
+            self.stops[col / 64] |= 1 << (col % 64);
+            col += width;
+        }
+    }
+
+    pub fn set(&mut self, col: usize) {
+        if col < MAX_TAB_COLUMNS {
+            self.stops[col / 64] |= 1 << (col % 64);
+        }
+    }
+
+    pub fn clear(&mut self, col: usize) {
+        if col < MAX_TAB_COLUMNS {
+            self.stops[col / 64] &= !(1 << (col % 64));
+        }
+    }
+
+    pub fn clear_all(&mut self) {
+        self.stops = [0; MAX_TAB_COLUMNS / 64];
+    }
+
+    pub fn is_set(&self, col: usize) -> bool {
+        col < MAX_TAB_COLUMNS && self.stops[col / 64] & (1 << (col % 64)) != 0
+    }
+
+    /// The first tab stop after `col`, or the last column if there is none before `cols`.
+    pub fn next(&self, col: usize, cols: usize) -> usize {
+        (col + 1..cols)
+            .find(|&stop| self.is_set(stop))
+            .unwrap_or(cols - 1)
+    }
+}
+
+impl Default for TabStops {
+    fn default() -> Self {
+        Self::new(DEFAULT_TAB_WIDTH)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+}
+
+/// Byte-at-a-time VT100 parser. Handles cursor movement, erase line/screen, SGR colours and blink,
+/// save/restore cursor, scroll regions and tab stops; anything else is swallowed.
+#[derive(Debug, Clone, Copy)]
+pub struct AnsiParser {
+    state: State,
+    params: [u16; MAX_PARAMS],
+    param_count: usize,
+    private: bool,
+    saved_cursor: (usize, usize),
+    foreground: Option<u8>,
+    background: Option<u8>,
+    bold: bool,
+    blink: bool,
+    reverse: bool,
+}
+
+impl AnsiParser {
+    pub const fn new() -> AnsiParser {
+        AnsiParser {
+            state: State::Ground,
+            params: [0; MAX_PARAMS],
+            param_count: 0,
+            private: false,
+            saved_cursor: (0, 0),
+            foreground: None,
+            background: None,
+            bold: false,
+            blink: false,
+            reverse: false,
+        }
+    }
+
+    pub fn feed<S: AnsiScreen>(&mut self, screen: &mut S, byte: u8) {
+        match self.state {
+            State::Ground => match byte {
+                0x1b => self.state = State::Escape,
+                b'\t' => {
+                    let (col, row) = screen.cursor();
+                    let (cols, _) = screen.size();
+                    let stop = screen.tab_stops().next(col, cols);
+                    screen.set_cursor(stop, row);
+                }
+                0x00..=0x1f | 0x7f => screen.control(byte),
+                _ => screen.print(byte),
+            },
+            State::Escape => {
+                self.state = State::Ground;
+                match byte {
+                    b'[' => {
+                        self.state = State::Csi;
+                        self.params = [0; MAX_PARAMS];
+                        self.param_count = 0;
+                        self.private = false;
+                    }
+                    b'7' => self.saved_cursor = screen.cursor(),
+                    b'H' => {
+                        let (col, _) = screen.cursor();
+                        screen.tab_stops().set(col);
+                    }
+                    b'8' => self.restore_cursor(screen),
+                    b'c' => self.reset(screen),
+                    _ => {}
+                }
+            }
+            State::Csi => match byte {
+                b'0'..=b'9' => {
+                    if self.param_count == 0 {
+                        self.param_count = 1;
+                    }
+                    let param = &mut self.params[self.param_count - 1];
+                    *param = param
+                        .saturating_mul(10)
+                        .saturating_add(u16::from(byte - b'0'));
+                }
+                b';' => {
+                    if self.param_count == 0 {
+                        self.param_count = 1;
+                    }
+                    if self.param_count < MAX_PARAMS {
+                        self.param_count += 1;
+                    }
+                }
+                b'?' | b'<' | b'=' | b'>' => self.private = true,
+                0x1b => self.state = State::Escape,
+                0x40..=0x7e => {
+                    self.state = State::Ground;
+                    self.dispatch_csi(screen, byte);
+                }
+                // Intermediate bytes are not used by any sequence we handle.
+                _ => {}
+            },
+        }
+    }
+
+    /// Feeds a decoded character. ASCII goes through `feed`; anything else is translated to its
+    /// code page 437 glyph and printed, unless it turns up in the middle of an escape sequence.
+    pub fn feed_char<S: AnsiScreen>(&mut self, screen: &mut S, c: char) {
+        if c.is_ascii() {
+            self.feed(screen, c as u8);
+        } else if self.state == State::Ground {
+            screen.print(cp437::from_char(c));
+        }
+    }
+
+    /// Parameter `index`, with missing or zero values replaced by `default`.
+    fn param(&self, index: usize, default: usize) -> usize {
+        match self.params[..self.param_count].get(index) {
+            Some(&value) if value != 0 => value as usize,
+            _ => default,
+        }
+    }
+
+    fn dispatch_csi<S: AnsiScreen>(&mut self, screen: &mut S, final_byte: u8) {
+        // DEC private modes (e.g. `?25l`) are not supported.
+        if self.private {
+            return;
+        }
+
+        let (col, row) = screen.cursor();
+        let (cols, rows) = screen.size();
+        let n = self.param(0, 1);
+        match final_byte {
+            b'A' => screen.set_cursor(col, row.saturating_sub(n)),
+            b'B' => screen.set_cursor(col, (row + n).min(rows - 1)),
+            b'C' => screen.set_cursor((col + n).min(cols - 1), row),
+            b'D' => screen.set_cursor(col.saturating_sub(n), row),
+            b'E' => screen.set_cursor(0, (row + n).min(rows - 1)),
+            b'F' => screen.set_cursor(0, row.saturating_sub(n)),
+            b'G' => screen.set_cursor((n - 1).min(cols - 1), row),
+            b'd' => screen.set_cursor(col, (n - 1).min(rows - 1)),
+            b'H' | b'f' => {
+                let target_row = (self.param(0, 1) - 1).min(rows - 1);
+                let target_col = (self.param(1, 1) - 1).min(cols - 1);
+                screen.set_cursor(target_col, target_row);
+            }
+            b'J' => match self.param(0, 0) {
+                0 => {
+                    screen.erase(row, col, cols);
+                    for r in row + 1..rows {
+                        screen.erase(r, 0, cols);
+                    }
+                }
+                1 => {
+                    for r in 0..row {
+                        screen.erase(r, 0, cols);
+                    }
+                    screen.erase(row, 0, (col + 1).min(cols));
+                }
+                _ => {
+                    for r in 0..rows {
+                        screen.erase(r, 0, cols);
+                    }
+                }
+            },
+            b'K' => match self.param(0, 0) {
+                0 => screen.erase(row, col, cols),
+                1 => screen.erase(row, 0, (col + 1).min(cols)),
+                _ => screen.erase(row, 0, cols),
+            },
+            b'm' => self.select_graphic_rendition(screen),
+            b's' => self.saved_cursor = (col, row),
+            b'u' => self.restore_cursor(screen),
+            b'r' => {
+                let top = self.param(0, 1) - 1;
+                let bottom = self.param(1, rows) - 1;
+                if top < bottom && bottom < rows {
+                    screen.set_scroll_region(top, bottom);
+                    screen.set_cursor(0, 0);
+                }
+            }
+            b'g' => match self.param(0, 0) {
+                0 => screen.tab_stops().clear(col),
+                3 => screen.tab_stops().clear_all(),
+                _ => {}
+            },
+            b'S' => screen.scroll_up(n),
+            b'T' => screen.scroll_down(n),
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition<S: AnsiScreen>(&mut self, screen: &mut S) {
+        // `ESC[m` is the same as `ESC[0m`.
+        let count = self.param_count.max(1);
+        let mut index = 0;
+        while index < count {
+            let code = self.params[index];
+            index += 1;
+            match code {
+                0 => {
+                    self.foreground = None;
+                    self.background = None;
+                    self.bold = false;
+                    self.blink = false;
+                    self.reverse = false;
+                }
+                1 => self.bold = true,
+                22 => self.bold = false,
+                5 => self.blink = true,
+                25 => self.blink = false,
+                7 => self.reverse = true,
+                27 => self.reverse = false,
+                code @ 30..=37 => self.foreground = Some(code as u8 - 30),
+                39 => self.foreground = None,
+                code @ 40..=47 => self.background = Some(code as u8 - 40),
+                49 => self.background = None,
+                code @ 90..=97 => self.foreground = Some(code as u8 - 90 + 8),
+                code @ 100..=107 => self.background = Some(code as u8 - 100 + 8),
+                // `38;5;n` and `38;2;r;g;b` (48 for the background): their sub-parameters are
+                // consumed here so they are never taken for codes of their own.
+                38 | 48 => {
+                    let rest = &self.params[index..count];
+                    let (color, used) = match rest.first() {
+                        Some(5) => (rest.get(1).map(|&n| indexed_to_ansi(n)), 2),
+                        Some(2) => match rest.get(1..4) {
+                            Some(&[r, g, b]) => (Some(rgb_to_ansi(r, g, b)), 4),
+                            _ => (None, rest.len()),
+                        },
+                        _ => (None, rest.len()),
+                    };
+                    index += used.min(rest.len());
+                    if let Some(color) = color {
+                        if code == 38 {
+                            self.foreground = Some(color);
+                        } else {
+                            self.background = Some(color);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.apply_colors(screen);
+    }
+
+    fn apply_colors<S: AnsiScreen>(&self, screen: &mut S) {
+        let (default_foreground, default_background) = screen.default_colors();
+        let mut foreground = match self.foreground {
+            // Bold is rendered as the bright variant, like the VGA BIOS does.
+            Some(index) if self.bold && index < 8 => ANSI_TO_VGA[index as usize + 8],
+            Some(index) => ANSI_TO_VGA[index as usize],
+            None => default_foreground,
+        };
+        let mut background = self
+            .background
+            .map_or(default_background, |index| ANSI_TO_VGA[index as usize]);
+        if self.reverse {
+            core::mem::swap(&mut foreground, &mut background);
+        }
+        screen.set_colors(foreground, background);
+        screen.set_blink(self.blink);
+    }
+
+    fn restore_cursor<S: AnsiScreen>(&self, screen: &mut S) {
+        let (cols, rows) = screen.size();
+        let (col, row) = self.saved_cursor;
+        screen.set_cursor(col.min(cols - 1), row.min(rows - 1));
+    }
+
+    fn reset<S: AnsiScreen>(&mut self, screen: &mut S) {
+        let (cols, rows) = screen.size();
+        *self = AnsiParser::new();
+        self.apply_colors(screen);
+        screen.set_scroll_region(0, rows - 1);
+        screen.tab_stops().set_width(DEFAULT_TAB_WIDTH);
+        for row in 0..rows {
+            screen.erase(row, 0, cols);
+        }
+        screen.set_cursor(0, 0);
+    }
+}
+
+impl Default for AnsiParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use VGAColorCode::*;
+
+    const DEFAULT_COLORS: (VGAColorCode, VGAColorCode) = (LightGray, Black);
+
+    /// Records what the parser asks of it.
+    struct MockScreen {
+        size: (usize, usize),
+        cursor: (usize, usize),
+        colors: (VGAColorCode, VGAColorCode),
+        blink: bool,
+        scroll_region: (usize, usize),
+        /// Lines scrolled by each call, up positive and down negative.
+        scrolls: Vec<isize>,
+        erased: Vec<(usize, usize, usize)>,
+        printed: Vec<u8>,
+        controls: Vec<u8>,
+        tab_stops: TabStops,
+    }
+
+    impl MockScreen {
+        fn new() -> MockScreen {
+            MockScreen {
+                size: (80, 25),
+                cursor: (0, 0),
+                colors: DEFAULT_COLORS,
+                blink: false,
+                scroll_region: (0, 24),
+                scrolls: Vec::new(),
+                erased: Vec::new(),
+                printed: Vec::new(),
+                controls: Vec::new(),
+                tab_stops: TabStops::default(),
+            }
+        }
+    }
+
+    impl AnsiScreen for MockScreen {
+        fn size(&self) -> (usize, usize) {
+            self.size
+        }
+        fn cursor(&self) -> (usize, usize) {
+            self.cursor
+        }
+        fn set_cursor(&mut self, col: usize, row: usize) {
+            self.cursor = (col, row);
+        }
+        fn default_colors(&self) -> (VGAColorCode, VGAColorCode) {
+            DEFAULT_COLORS
+        }
+        fn set_colors(&mut self, foreground: VGAColorCode, background: VGAColorCode) {
+            self.colors = (foreground, background);
+        }
+        fn set_blink(&mut self, blink: bool) {
+            self.blink = blink;
+        }
+        fn erase(&mut self, row: usize, start_col: usize, end_col: usize) {
+            self.erased.push((row, start_col, end_col));
+        }
+        fn set_scroll_region(&mut self, top: usize, bottom: usize) {
+            self.scroll_region = (top, bottom);
+        }
+        fn scroll_up(&mut self, lines: usize) {
+            self.scrolls.push(lines as isize);
+        }
+        fn scroll_down(&mut self, lines: usize) {
+            self.scrolls.push(-(lines as isize));
+        }
+        fn tab_stops(&mut self) -> &mut TabStops {
+            &mut self.tab_stops
+        }
+        fn print(&mut self, byte: u8) {
+            self.printed.push(byte);
+            self.cursor.0 = (self.cursor.0 + 1).min(self.size.0 - 1);
+        }
+        fn control(&mut self, byte: u8) {
+            self.controls.push(byte);
+        }
+    }
+
+    /// A fresh screen and parser after feeding them `input`.
+    fn run(input: &str) -> (MockScreen, AnsiParser) {
+        let mut screen = MockScreen::new();
+        let mut parser = AnsiParser::new();
+        feed(&mut screen, &mut parser, input);
+        (screen, parser)
+    }
+
+    fn feed(screen: &mut MockScreen, parser: &mut AnsiParser, input: &str) {
+        for c in input.chars() {
+            parser.feed_char(screen, c);
+        }
+    }
+
+    #[test]
+    fn text_and_controls() {
+        let (screen, _) = run("hi\x1b[1mX\n\x08é");
+        assert_eq!(screen.printed, [b'h', b'i', b'X', cp437::from_char('é')]);
+        assert_eq!(screen.controls, b"\n\x08");
+    }
+
+    #[test]
+    fn basic_sgr() {
+        assert_eq!(run("\x1b[31;44m").0.colors, (Red, Blue));
+        assert_eq!(run("\x1b[1;31m").0.colors, (LightRed, Black));
+        assert_eq!(run("\x1b[93;101m").0.colors, (Yellow, LightRed));
+        assert_eq!(run("\x1b[31;44;7m").0.colors, (Blue, Red));
+        assert_eq!(run("\x1b[31;44;5m\x1b[m").0.colors, DEFAULT_COLORS);
+        assert!(run("\x1b[5m").0.blink);
+        assert!(!run("\x1b[5;25m").0.blink);
+    }
+
+    #[test]
+    fn sgr_extended_colour_parameters_are_one_unit() {
+        // The 5 and 1 after 38 are not blink and bold.
+        let (screen, _) = run("\x1b[38;5;1m");
+        assert_eq!(screen.colors, (Red, Black));
+        assert!(!screen.blink);
+
+        // Codes after the extended colour still apply.
+        let (screen, _) = run("\x1b[38;5;5;1m");
+        assert_eq!(screen.colors, (Pink, Black));
+        assert!(!screen.blink);
+        let (screen, _) = run("\x1b[48;2;0;0;170;5m");
+        assert_eq!(screen.colors, (LightGray, Blue));
+        assert!(screen.blink);
+
+        // A sequence cut short swallows the rest rather than misreading it.
+        let (screen, _) = run("\x1b[38;2;1;5m");
+        assert_eq!(screen.colors, DEFAULT_COLORS);
+        assert!(!screen.blink);
+    }
+
+    #[test]
+    fn sgr_256_colours() {
+        assert_eq!(run("\x1b[38;5;12m").0.colors, (LightBlue, Black));
+        // Corners of the colour cube.
+        assert_eq!(run("\x1b[38;5;16m").0.colors, (Black, Black));
+        assert_eq!(run("\x1b[38;5;231m").0.colors, (White, Black));
+        assert_eq!(run("\x1b[48;5;46m").0.colors, (LightGray, Green));
+        // Ends of the grey ramp.
+        assert_eq!(run("\x1b[38;5;232m").0.colors, (Black, Black));
+        assert_eq!(run("\x1b[38;5;255m").0.colors, (White, Black));
+    }
+
+    #[test]
+    fn sgr_true_colour() {
+        assert_eq!(run("\x1b[38;2;255;255;85m").0.colors, (Yellow, Black));
+        assert_eq!(run("\x1b[38;2;170;85;0m").0.colors, (Brown, Black));
+        assert_eq!(run("\x1b[48;2;0;160;170m").0.colors, (LightGray, Cyan));
+    }
+
+    #[test]
+    fn cursor_movement() {
+        assert_eq!(run("\x1b[10;20H").0.cursor, (19, 9));
+        assert_eq!(run("\x1b[10;20H\x1b[3A\x1b[2D").0.cursor, (17, 6));
+        assert_eq!(run("\x1b[99;99H").0.cursor, (79, 24));
+        assert_eq!(run("\x1b[5;5H\x1b[9F").0.cursor, (0, 0));
+        assert_eq!(run("\x1b[H\x1b[3C\x1b[2B").0.cursor, (3, 2));
+        assert_eq!(run("abc\t").0.cursor, (8, 0));
+    }
+
+    #[test]
+    fn erase() {
+        let (screen, _) = run("\x1b[3;5H\x1b[K");
+        assert_eq!(screen.erased, [(2, 4, 80)]);
+        let (screen, _) = run("\x1b[3;5H\x1b[1K");
+        assert_eq!(screen.erased, [(2, 0, 5)]);
+        let (screen, _) = run("\x1b[2J");
+        assert_eq!(screen.erased.len(), 25);
+    }
+
+    #[test]
+    fn scroll_regions() {
+        let (screen, _) = run("\x1b[10;10H\x1b[5;20r");
+        assert_eq!(screen.scroll_region, (4, 19));
+        // Setting a region homes the cursor.
+        assert_eq!(screen.cursor, (0, 0));
+
+        let (screen, _) = run("\x1b[5;20r\x1b[r");
+        assert_eq!(screen.scroll_region, (0, 24));
+
+        // Empty, inverted and oversized regions are ignored.
+        for region in ["\x1b[20;5r", "\x1b[7;7r", "\x1b[5;26r"] {
+            let (screen, _) = run(region);
+            assert_eq!(screen.scroll_region, (0, 24), "{region:?}");
+        }
+
+        let (screen, _) = run("\x1b[S\x1b[3S\x1b[2T");
+        assert_eq!(screen.scrolls, [1, 3, -2]);
+    }
+
+    #[test]
+    fn save_and_restore_cursor() {
+        let (screen, _) = run("\x1b[4;7H\x1b7\x1b[20;60H\x1b8");
+        assert_eq!(screen.cursor, (6, 3));
+        let (screen, _) = run("\x1b[4;7H\x1b[s\x1b[20;60H\x1b[u");
+        assert_eq!(screen.cursor, (6, 3));
+
+        // A position saved on a bigger screen is clamped to the current one.
+        let (mut screen, mut parser) = run("\x1b[20;60H\x1b7");
+        screen.size = (40, 10);
+        feed(&mut screen, &mut parser, "\x1b8");
+        assert_eq!(screen.cursor, (39, 9));
+    }
+
+    #[test]
+    fn tab_stops() {
+        let (screen, _) = run("\x1b[3g\x1b[1;5H\x1bH\x1b[1;1H\t");
+        assert_eq!(screen.cursor, (4, 0));
+        // Past the last stop, tabs go to the last column.
+        let (screen, _) = run("\x1b[3g\t");
+        assert_eq!(screen.cursor, (79, 0));
+    }
+
+    #[test]
+    fn reset() {
+        let (screen, parser) = run("\x1b[31;5m\x1b[5;20r\x1b[3g\x1b[9;9H\x1bc");
+        assert_eq!(screen.colors, DEFAULT_COLORS);
+        assert!(!screen.blink);
+        assert_eq!(screen.scroll_region, (0, 24));
+        assert_eq!(screen.cursor, (0, 0));
+        assert!(screen.tab_stops.is_set(DEFAULT_TAB_WIDTH));
+        assert_eq!(parser.saved_cursor, (0, 0));
+    }
+}
@@ -0,0 +1,224 @@
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use pc_keyboard::DecodedKey;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::framebuffer_console::FRAMEBUFFER_CONSOLES;
+use crate::serial;
+use crate::tui::TextCanvas;
+use crate::vga_text_mode::BUFFER_HEIGHT;
+use crate::vga_text_mode_terminal::VGA_TEXT_MODE_TERMINALS;
+
+/// Number of virtual consoles, switched between with Alt+F1..Alt+F6.
+pub const VIRTUAL_CONSOLES: usize = 6;
+
+/// The console `print!`/`println!` write to.
+pub const KERNEL_CONSOLE: usize = 0;
+
+/// Keys buffered per console before further ones are dropped.
+const INPUT_QUEUE_LEN: usize = 64;
+
+static ACTIVE_CONSOLE: AtomicUsize = AtomicUsize::new(KERNEL_CONSOLE);
+
+static MIRROR_TO_SERIAL: AtomicBool = AtomicBool::new(false);
+
+static INPUT_QUEUES: [Mutex<InputQueue>; VIRTUAL_CONSOLES] =
+    [const { Mutex::new(InputQueue::new()) }; VIRTUAL_CONSOLES];
+
+/// Keys typed while a console had focus, waiting to be read by whoever owns that console.
+struct InputQueue {
+    keys: [Option<DecodedKey>; INPUT_QUEUE_LEN],
+    head: usize,
+    len: usize,
+}
+
+impl InputQueue {
+    const fn new() -> InputQueue {
+        InputQueue {
+            keys: [None; INPUT_QUEUE_LEN],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, key: DecodedKey) {
+        if self.len == INPUT_QUEUE_LEN {
+            return;
+        }
+        self.keys[(self.head + self.len) % INPUT_QUEUE_LEN] = Some(key);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<DecodedKey> {
+        if self.len == 0 {
+            return None;
+        }
+        let key = self.keys[self.head].take();
+        self.head = (self.head + 1) % INPUT_QUEUE_LEN;
+        self.len -= 1;
+        key
+    }
+}
+
+/// Index of the console currently on screen and receiving keyboard input.
+pub fn active() -> usize {
+    ACTIVE_CONSOLE.load(Ordering::Relaxed)
+}
+
+/// Backend of `print!`/`println!`.
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    print_to(KERNEL_CONSOLE, args);
+    if MIRROR_TO_SERIAL.load(Ordering::Relaxed) {
+        serial::_print(args);
+    }
+}
+
+/// Makes `print!`/`println!` copy everything they write to the serial port as well, so a log
+/// of the serial line shows what appeared on screen.
+pub fn set_mirror_to_serial(mirror: bool) {
+    MIRROR_TO_SERIAL.store(mirror, Ordering::Relaxed);
+}
+
+/// Writes to virtual console `console`, whether or not it is the one on screen. Output goes to the
+/// framebuffer console once it has been set up, and to the legacy VGA text buffer otherwise.
+pub fn print_to(console: usize, args: fmt::Arguments) {
+    without_interrupts(|| {
+        let mut framebuffer_console = FRAMEBUFFER_CONSOLES[console].lock();
+        if framebuffer_console.is_enabled() {
+            let _ = framebuffer_console.write_fmt(args);
+            return;
+        }
+        drop(framebuffer_console);
+        let _ = VGA_TEXT_MODE_TERMINALS[console].lock().write_fmt(args);
+    });
+}
+
+/// Like `print_to`, but gives up rather than wait when the console is in use, e.g. because the
+/// code that was printing to it panicked. Returns whether anything was printed.
+pub fn try_print_to(console: usize, args: fmt::Arguments) -> bool {
+    without_interrupts(|| {
+        let Some(mut framebuffer_console) = FRAMEBUFFER_CONSOLES[console].try_lock() else {
+            return false;
+        };
+        if framebuffer_console.is_enabled() {
+            let _ = framebuffer_console.write_fmt(args);
+            return true;
+        }
+        drop(framebuffer_console);
+        match VGA_TEXT_MODE_TERMINALS[console].try_lock() {
+            Some(mut terminal) => terminal.write_fmt(args).is_ok(),
+            None => false,
+        }
+    })
+}
+
+/// Puts virtual console `console` on screen and sends keyboard input to it from now on.
+pub fn switch_to(console: usize) {
+    if console >= VIRTUAL_CONSOLES {
+        return;
+    }
+
+    without_interrupts(|| {
+        let previous = ACTIVE_CONSOLE.swap(console, Ordering::Relaxed);
+        if previous == console {
+            return;
+        }
+
+        // The framebuffer moves along with the focus. If the active console had lent it out
+        // (e.g. to the demo), it stays lent and comes back to the new console on restore.
+        let framebuffer = FRAMEBUFFER_CONSOLES[previous].lock().take_framebuffer();
+        let mut framebuffer_console = FRAMEBUFFER_CONSOLES[console].lock();
+        if framebuffer_console.is_enabled() {
+            if let Some(framebuffer) = framebuffer {
+                framebuffer_console.show(framebuffer);
+            }
+            return;
+        }
+        drop(framebuffer_console);
+
+        VGA_TEXT_MODE_TERMINALS[previous].lock().set_active(false);
+        VGA_TEXT_MODE_TERMINALS[console].lock().set_active(true);
+    });
+}
+
+/// Queues a key for the active console. Called from the keyboard interrupt.
+pub fn push_key(key: DecodedKey) {
+    INPUT_QUEUES[active()].lock().push(key);
+}
+
+/// Takes the next key typed while `console` had focus, if any.
+pub fn read_key(console: usize) -> Option<DecodedKey> {
+    without_interrupts(|| INPUT_QUEUES[console].lock().pop())
+}
+
+/// Waits for the next key typed while `console` had focus.
+pub fn wait_key(console: usize) -> DecodedKey {
+    loop {
+        if let Some(key) = read_key(console) {
+            return key;
+        }
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Runs `f` on the character grid of virtual console `console`, for drawing with `tui`. Like
+/// `print_to`, this is the framebuffer console once it has been set up and the VGA terminal
+/// otherwise.
+pub fn with_canvas<R>(console: usize, f: impl FnOnce(&mut dyn TextCanvas) -> R) -> R {
+    without_interrupts(|| {
+        let mut framebuffer_console = FRAMEBUFFER_CONSOLES[console].lock();
+        if framebuffer_console.is_enabled() {
+            let result = f(&mut *framebuffer_console);
+            framebuffer_console.present();
+            return result;
+        }
+        drop(framebuffer_console);
+        f(&mut *VGA_TEXT_MODE_TERMINALS[console].lock())
+    })
+}
+
+/// Pages the visible console back through its scrollback history.
+pub fn scroll_view_up() {
+    without_interrupts(|| {
+        let mut console = FRAMEBUFFER_CONSOLES[active()].lock();
+        if console.is_enabled() {
+            let page = console.rows() - 1;
+            console.scroll_view_up(page);
+            return;
+        }
+        drop(console);
+        VGA_TEXT_MODE_TERMINALS[active()]
+            .lock()
+            .scroll_view_up(BUFFER_HEIGHT - 1);
+    });
+}
+
+/// Pages the visible console forward towards the live screen.
+pub fn scroll_view_down() {
+    without_interrupts(|| {
+        let mut console = FRAMEBUFFER_CONSOLES[active()].lock();
+        if console.is_enabled() {
+            let page = console.rows() - 1;
+            console.scroll_view_down(page);
+            return;
+        }
+        drop(console);
+        VGA_TEXT_MODE_TERMINALS[active()]
+            .lock()
+            .scroll_view_down(BUFFER_HEIGHT - 1);
+    });
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
@@ -0,0 +1,65 @@
+/// Glyph drawn for characters code page 437 has no equivalent for.
+pub const REPLACEMENT_GLYPH: u8 = 0xFE; // ■
+
+/// What code page 437 shows for bytes 0x00..0x20. The consoles treat these bytes as control
+/// codes, so the glyphs can only be reached through `from_char`.
+const LOW_GLYPHS: [char; 32] = [
+    '\0', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼', //
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+/// Code page 437 bytes 0x80..=0xFF.
+const HIGH_GLYPHS: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Characters that are not in code page 437 but look close enough to one of its glyphs.
+const LOOKALIKES: [(char, u8); 8] = [
+    ('β', 0xE1),
+    ('μ', 0xE6),
+    ('∅', 0xED),
+    ('ϕ', 0xED),
+    ('∈', 0xEE),
+    ('⌂', 0x7F),
+    ('━', 0xC4),
+    ('┃', 0xB3),
+];
+
+/// The code page 437 byte that displays `c`, or `REPLACEMENT_GLYPH` if there is none.
+/// Printable ASCII maps to itself.
+pub fn from_char(c: char) -> u8 {
+    match c {
+        ' '..='~' => c as u8,
+        _ => lookup(c).unwrap_or(REPLACEMENT_GLYPH),
+    }
+}
+
+fn lookup(c: char) -> Option<u8> {
+    if let Some(index) = HIGH_GLYPHS.iter().position(|&glyph| glyph == c) {
+        return Some(0x80 + index as u8);
+    }
+    if let Some(index) = LOW_GLYPHS[1..].iter().position(|&glyph| glyph == c) {
+        return Some(1 + index as u8);
+    }
+    LOOKALIKES
+        .iter()
+        .find(|&&(lookalike, _)| lookalike == c)
+        .map(|&(_, glyph)| glyph)
+}
+
+/// The character code page 437 byte `glyph` displays as.
+pub fn to_char(glyph: u8) -> char {
+    match glyph {
+        0x00..=0x1F => LOW_GLYPHS[glyph as usize],
+        0x7F => '⌂',
+        0x80..=0xFF => HIGH_GLYPHS[glyph as usize - 0x80],
+        _ => glyph as char,
+    }
+}
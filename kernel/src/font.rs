@@ -0,0 +1,269 @@
+use crate::cp437;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x02;
+const PSF1_MODE_HAS_SEQUENCES: u8 = 0x04;
+const PSF1_HEADER_SIZE: usize = 4;
+const PSF1_SEPARATOR: u16 = 0xFFFF;
+const PSF1_START_SEQUENCE: u16 = 0xFFFE;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_HEADER_SIZE: usize = 32;
+const PSF2_SEPARATOR: u8 = 0xFF;
+const PSF2_START_SEQUENCE: u8 = 0xFE;
+
+/// The VGA ROM font in code page 437 order: 256 glyphs, one byte per row, most significant bit on the left.
+static VGA_8X16: &[u8; 256 * 16] = include_bytes!("../fonts/vga-8x16.bin");
+
+/// Spleen by Frederic Cambus, BSD 2-clause (see `fonts/LICENSE.spleen`).
+static SPLEEN_12X24: &[u8] = include_bytes!("../fonts/spleen-12x24.psfu");
+static SPLEEN_16X32: &[u8] = include_bytes!("../fonts/spleen-16x32.psfu");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontError {
+    /// Neither a PSF1 nor a PSF2 header.
+    UnknownFormat,
+    /// The header promises more glyph data than the file holds.
+    Truncated,
+    /// A glyph size of zero, or a PSF2 glyph size that does not match its width and height.
+    BadGlyphSize,
+    /// A PSF2 header that declares no glyphs at all.
+    NoGlyphs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnicodeTable {
+    None,
+    Psf1(&'static [u8]),
+    Psf2(&'static [u8]),
+}
+
+/// A bitmap font. Glyph rows are padded to whole bytes with the leftmost pixel in the most
+/// significant bit, which is how both the VGA ROM font and PSF files store them.
+#[derive(Debug, Clone, Copy)]
+pub struct Font {
+    glyphs: &'static [u8],
+    glyph_count: usize,
+    width: usize,
+    height: usize,
+    /// Glyph index to draw for each code page 437 byte.
+    cp437_glyphs: [u16; 256],
+}
+
+impl Font {
+    /// The built-in 8x16 VGA font.
+    pub const fn vga() -> Font {
+        let mut cp437_glyphs = [0; 256];
+        let mut byte = 0;
+        while byte < 256 {
+            cp437_glyphs[byte] = byte as u16;
+            byte += 1;
+        }
+        Font {
+            glyphs: VGA_8X16,
+            glyph_count: 256,
+            width: 8,
+            height: 16,
+            cp437_glyphs,
+        }
+    }
+
+    /// Picks the largest built-in font that still leaves room for 100 columns and 30 rows.
+    pub fn for_resolution(width: usize, height: usize) -> Font {
+        for data in [SPLEEN_16X32, SPLEEN_12X24] {
+            let Ok(font) = Font::parse(data) else {
+                continue;
+            };
+            if width / font.width >= 100 && height / font.height >= 30 {
+                return font;
+            }
+        }
+        Font::vga()
+    }
+
+    /// Reads a PSF1 or PSF2 font. Characters are looked up through the file's Unicode table if
+    /// it has one; otherwise the glyphs are assumed to be in code page 437 order.
+    pub fn parse(data: &'static [u8]) -> Result<Font, FontError> {
+        if data.starts_with(&PSF2_MAGIC) {
+            Font::parse_psf2(data)
+        } else if data.starts_with(&PSF1_MAGIC) {
+            Font::parse_psf1(data)
+        } else {
+            Err(FontError::UnknownFormat)
+        }
+    }
+
+    fn parse_psf1(data: &'static [u8]) -> Result<Font, FontError> {
+        if data.len() < PSF1_HEADER_SIZE {
+            return Err(FontError::Truncated);
+        }
+        let mode = data[2];
+        let height = data[3] as usize;
+        if height == 0 {
+            return Err(FontError::BadGlyphSize);
+        }
+        let glyph_count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+
+        let glyphs_end = PSF1_HEADER_SIZE + glyph_count * height;
+        let glyphs = data
+            .get(PSF1_HEADER_SIZE..glyphs_end)
+            .ok_or(FontError::Truncated)?;
+        let table = if mode & (PSF1_MODE_HAS_TABLE | PSF1_MODE_HAS_SEQUENCES) != 0 {
+            UnicodeTable::Psf1(&data[glyphs_end..])
+        } else {
+            UnicodeTable::None
+        };
+
+        Font::with_table(glyphs, glyph_count, 8, height, table)
+    }
+
+    fn parse_psf2(data: &'static [u8]) -> Result<Font, FontError> {
+        let field = |index: usize| -> Result<usize, FontError> {
+            let bytes = data
+                .get(4 * index..4 * index + 4)
+                .ok_or(FontError::Truncated)?;
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+        };
+        let header_size = field(2)?;
+        let flags = field(3)? as u32;
+        let glyph_count = field(4)?;
+        let glyph_size = field(5)?;
+        let height = field(6)?;
+        let width = field(7)?;
+        if glyph_count == 0 {
+            return Err(FontError::NoGlyphs);
+        }
+        if header_size < PSF2_HEADER_SIZE
+            || width == 0
+            || height == 0
+            || glyph_size != width.div_ceil(8) * height
+        {
+            return Err(FontError::BadGlyphSize);
+        }
+
+        let glyphs_end = glyph_count
+            .checked_mul(glyph_size)
+            .and_then(|size| size.checked_add(header_size))
+            .ok_or(FontError::Truncated)?;
+        let glyphs = data
+            .get(header_size..glyphs_end)
+            .ok_or(FontError::Truncated)?;
+        let table = if flags & PSF2_HAS_UNICODE_TABLE != 0 {
+            UnicodeTable::Psf2(&data[glyphs_end..])
+        } else {
+            UnicodeTable::None
+        };
+
+        Font::with_table(glyphs, glyph_count, width, height, table)
+    }
+
+    fn with_table(
+        glyphs: &'static [u8],
+        glyph_count: usize,
+        width: usize,
+        height: usize,
+        table: UnicodeTable,
+    ) -> Result<Font, FontError> {
+        // `glyph` clamps to the last glyph, so there has to be one.
+        if glyph_count == 0 {
+            return Err(FontError::NoGlyphs);
+        }
+        if glyphs.len() < glyph_count * width.div_ceil(8) * height {
+            return Err(FontError::Truncated);
+        }
+
+        let mut found = [None; 256];
+        let mut map = |glyph: usize, c: char| {
+            // Only exact matches; lookalikes would shadow the real glyph.
+            let byte = cp437::from_char(c);
+            if cp437::to_char(byte) == c && found[byte as usize].is_none() {
+                found[byte as usize] = Some(glyph as u16);
+            }
+        };
+
+        match table {
+            UnicodeTable::None => {
+                for (byte, glyph) in found.iter_mut().take(glyph_count).enumerate() {
+                    *glyph = Some(byte as u16);
+                }
+            }
+            UnicodeTable::Psf1(table) => {
+                let mut entries = table
+                    .as_chunks::<2>()
+                    .0
+                    .iter()
+                    .map(|&pair| u16::from_le_bytes(pair));
+                for glyph in 0..glyph_count {
+                    let mut in_sequence = false;
+                    for value in entries.by_ref() {
+                        match value {
+                            PSF1_SEPARATOR => break,
+                            PSF1_START_SEQUENCE => in_sequence = true,
+                            _ if in_sequence => {}
+                            _ => {
+                                if let Some(c) = char::from_u32(u32::from(value)) {
+                                    map(glyph, c);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            UnicodeTable::Psf2(table) => {
+                let mut entries = table.split(|&byte| byte == PSF2_SEPARATOR);
+                for glyph in 0..glyph_count {
+                    let Some(entry) = entries.next() else {
+                        break;
+                    };
+                    // Combining sequences after the first start marker are skipped.
+                    let singles = entry
+                        .split(|&byte| byte == PSF2_START_SEQUENCE)
+                        .next()
+                        .unwrap_or(&[]);
+                    if let Ok(singles) = core::str::from_utf8(singles) {
+                        for c in singles.chars() {
+                            map(glyph, c);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Cell byte 0 is the console's empty cell, so it needs to come out blank.
+        if found[0].is_none() {
+            found[0] = found[b' ' as usize];
+        }
+        let fallback = found[cp437::REPLACEMENT_GLYPH as usize]
+            .or(found[b'?' as usize])
+            .unwrap_or(0);
+
+        Ok(Font {
+            glyphs,
+            glyph_count,
+            width,
+            height,
+            cp437_glyphs: found.map(|glyph| glyph.unwrap_or(fallback)),
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn bytes_per_row(&self) -> usize {
+        self.width.div_ceil(8)
+    }
+
+    /// Bitmap of the glyph that displays code page 437 byte `byte`, `height` rows of `bytes_per_row` bytes.
+    pub fn glyph(&self, byte: u8) -> &'static [u8] {
+        let size = self.bytes_per_row() * self.height;
+        let index = (self.cp437_glyphs[byte as usize] as usize).min(self.glyph_count - 1);
+        &self.glyphs[index * size..][..size]
+    }
+}
@@ -0,0 +1,146 @@
+//! The x87 FPU and SIMD units.
+//!
+//! The kernel is built for its own target (`x86_64-kernel.json`), which is
+//! `x86_64-unknown-none` with hardware floating point, so the compiler uses SSE for `f32` math
+//! and for vectorised loops and copies anywhere in the kernel. The bootloader leaves SSE off,
+//! so `init` has to run on every CPU before any other kernel code.
+
+use core::arch::asm;
+use core::arch::x86_64::{__cpuid_count, CpuidResult};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use x86_64::registers::xcontrol::{XCr0, XCr0Flags};
+
+/// Bytes `FpuState` has room for. x87, SSE and AVX state take 832 bytes with XSAVE, so AVX is
+/// only turned on when the CPU's layout fits.
+pub const STATE_SIZE: usize = 1024;
+
+static XSAVE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The SIMD extensions the CPU reports and `init` turned on.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Features {
+    pub sse: bool,
+    pub sse2: bool,
+    pub fxsr: bool,
+    pub xsave: bool,
+    pub avx: bool,
+}
+
+impl Features {
+    /// What CPUID leaf 1 says the processor supports.
+    pub fn detect() -> Features {
+        let CpuidResult { ecx, edx, .. } = cpuid(1, 0);
+        Features {
+            fxsr: edx & (1 << 24) != 0,
+            sse: edx & (1 << 25) != 0,
+            sse2: edx & (1 << 26) != 0,
+            xsave: ecx & (1 << 26) != 0,
+            avx: ecx & (1 << 28) != 0,
+        }
+    }
+}
+
+fn cpuid(leaf: u32, sub_leaf: u32) -> CpuidResult {
+    __cpuid_count(leaf, sub_leaf)
+}
+
+/// Turns on the x87 FPU and SSE, and AVX when the CPU has it, and returns what was enabled.
+/// Every 64-bit CPU has SSE2, which is what the kernel is compiled for.
+///
+/// CR0, CR4 and XCR0 are per CPU, so each CPU has to call this first thing. It doesn't log,
+/// since the logger is compiled with SSE too.
+pub fn init() -> Features {
+    let mut features = Features::detect();
+
+    unsafe {
+        // x87 errors through exceptions rather than the legacy IRQ 13, no emulation, and
+        // `wait` honours the task-switched flag.
+        Cr0::update(|cr0| {
+            cr0.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            cr0.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+        // FXSAVE/FXRSTOR and unmasked SIMD exceptions are supported by the OS.
+        Cr4::update(|cr4| {
+            cr4.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE);
+        });
+        asm!("fninit", options(nomem, nostack));
+    }
+
+    features.avx &= features.xsave;
+    if features.xsave {
+        unsafe { Cr4::update(|cr4| cr4.insert(Cr4Flags::OSXSAVE)) };
+        let mut xcr0 = XCr0Flags::X87 | XCr0Flags::SSE;
+        if features.avx {
+            xcr0 |= XCr0Flags::AVX;
+        }
+        unsafe { XCr0::write(xcr0) };
+
+        // EBX of leaf 0xD is the save area size for what XCR0 has on now.
+        if cpuid(0xD, 0).ebx as usize > STATE_SIZE {
+            features.avx = false;
+            unsafe { XCr0::write(XCr0Flags::X87 | XCr0Flags::SSE) };
+        }
+        XSAVE_ENABLED.store(true, Ordering::Relaxed);
+    }
+
+    features
+}
+
+/// Room for the x87, SSE and (with XSAVE) AVX registers.
+#[repr(C, align(64))]
+pub struct FpuState {
+    area: [u8; STATE_SIZE],
+}
+
+impl FpuState {
+    pub const fn new() -> FpuState {
+        FpuState {
+            area: [0; STATE_SIZE],
+        }
+    }
+
+    /// Stores the current FPU and SIMD registers. Only valid once `init` has run.
+    pub fn save(&mut self) {
+        let area = self.area.as_mut_ptr();
+        unsafe {
+            if XSAVE_ENABLED.load(Ordering::Relaxed) {
+                asm!("xsave64 [{}]", in(reg) area, in("eax") u32::MAX, in("edx") u32::MAX,
+                    options(nostack));
+            } else {
+                asm!("fxsave64 [{}]", in(reg) area, options(nostack));
+            }
+        }
+    }
+
+    /// Loads the registers saved by `save`.
+    pub fn restore(&self) {
+        let area = self.area.as_ptr();
+        unsafe {
+            if XSAVE_ENABLED.load(Ordering::Relaxed) {
+                asm!("xrstor64 [{}]", in(reg) area, in("eax") u32::MAX, in("edx") u32::MAX,
+                    options(nostack, readonly));
+            } else {
+                asm!("fxrstor64 [{}]", in(reg) area, options(nostack, readonly));
+            }
+        }
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs `f` with the interrupted code's FPU and SIMD registers saved around it. The
+/// `x86-interrupt` ABI only saves the XMM registers a handler clobbers, not the x87 state,
+/// MXCSR or the upper halves of the AVX registers, so the interrupt handlers go through this.
+pub fn with_saved_state<R>(f: impl FnOnce() -> R) -> R {
+    let mut state = FpuState::new();
+    state.save();
+    let result = f();
+    state.restore();
+    result
+}
@@ -1,95 +1,357 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use bootloader_api::info::{FrameBuffer, FrameBufferInfo, PixelFormat};
-use libm::{cosf, powf, sinf, sqrtf};
+use libm::{cosf, sinf};
+use pc_keyboard::{DecodedKey, KeyCode};
+use spin::Mutex;
 
-use crate::interupts;
+use crate::math::Vec3;
+use crate::sdf::{Axis, Light, Material, Object, Op, Scene, Shape};
+use crate::{console, interupts, mouse, parallel, screenshot};
 
-const MAX_BACKBUFFER_BYTES: usize = 1280 * 720 * 4;
+/// Size of the backbuffer, enough for 1920x1440 at 4 bytes per pixel.
+pub const MAX_BACKBUFFER_BYTES: usize = 1920 * 1440 * 4;
 static mut BACKBUFFER: [u8; MAX_BACKBUFFER_BYTES] = [0; MAX_BACKBUFFER_BYTES];
 
-#[derive(Clone, Copy)]
-struct Color {
-    r: u8,
-    g: u8,
-    b: u8,
+/// The first `len` bytes of the backbuffer, which frames are drawn into before being copied to
+/// the framebuffer. `None` if it is not that big.
+///
+/// # Safety
+///
+/// There is only one backbuffer and it goes with the framebuffer: only whoever holds the
+/// framebuffer may call this, and they have to be done with the slice before handing the
+/// framebuffer on.
+pub unsafe fn backbuffer(len: usize) -> Option<&'static mut [u8]> {
+    if len > MAX_BACKBUFFER_BYTES {
+        return None;
+    }
+    let ptr = core::ptr::addr_of_mut!(BACKBUFFER) as *mut u8;
+    Some(unsafe { core::slice::from_raw_parts_mut(ptr, len) })
+}
+
+/// `info` with the rows that don't fit in the backbuffer cut off.
+pub fn clip_to_backbuffer(mut info: FrameBufferInfo) -> FrameBufferInfo {
+    let row_bytes = (info.stride * info.bytes_per_pixel).max(1);
+    info.height = info.height.min(MAX_BACKBUFFER_BYTES / row_bytes);
+    info.byte_len = info.byte_len.min(info.height * row_bytes);
+    info
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
 }
 
 impl Color {
-    const fn rgb(r: u8, g: u8, b: u8) -> Self {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
         Self { r, g, b }
     }
 }
 
-#[derive(Clone, Copy)]
-struct Vec3 {
-    x: f32,
-    y: f32,
-    z: f32,
+/// A colour with coverage: `a` of 255 is opaque, 0 fully transparent. Channels are not
+/// premultiplied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
 }
 
-impl Vec3 {
-    const fn new(x: f32, y: f32, z: f32) -> Self {
-        Self { x, y, z }
+impl Rgba {
+    pub const TRANSPARENT: Rgba = Rgba::new(0, 0, 0, 0);
+
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
     }
 
-    fn add(self, rhs: Self) -> Self {
-        Self::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
+    pub const fn opaque(color: Color) -> Self {
+        Self::new(color.r, color.g, color.b, 255)
     }
 
-    fn sub(self, rhs: Self) -> Self {
-        Self::new(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+    pub const fn rgb(self) -> Color {
+        Color::rgb(self.r, self.g, self.b)
     }
 
-    fn mul(self, s: f32) -> Self {
-        Self::new(self.x * s, self.y * s, self.z * s)
+    /// Source-over compositing of this colour on top of `dst`.
+    pub fn over(self, dst: Color) -> Color {
+        match self.a {
+            0 => dst,
+            255 => self.rgb(),
+            a => Color::rgb(
+                blend_channel(self.r, dst.r, a),
+                blend_channel(self.g, dst.g, a),
+                blend_channel(self.b, dst.b, a),
+            ),
+        }
     }
+}
 
-    fn dot(self, rhs: Self) -> f32 {
-        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+impl From<Color> for Rgba {
+    fn from(color: Color) -> Self {
+        Self::opaque(color)
     }
+}
 
-    fn len(self) -> f32 {
-        sqrtf(self.dot(self))
+/// `src * a + dst * (255 - a)`, divided by 255 with rounding.
+fn blend_channel(src: u8, dst: u8, a: u8) -> u8 {
+    let a = u32::from(a);
+    let x = u32::from(src) * a + u32::from(dst) * (255 - a) + 128;
+    ((x + (x >> 8)) >> 8) as u8
+}
+
+/// Rectangle of pixels, `w` by `h` from its top left corner at `x`, `y`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClipRect {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+impl ClipRect {
+    pub const fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
+        Self { x, y, w, h }
     }
 
-    fn norm(self) -> Self {
-        let l = self.len();
-        if l <= 0.000_01 {
-            Self::new(0.0, 0.0, 0.0)
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.w && y < self.y + self.h
+    }
+
+    /// The part covered by both rectangles; empty rectangles come out with zero size.
+    pub fn intersect(&self, other: ClipRect) -> ClipRect {
+        let x0 = self.x.max(other.x);
+        let y0 = self.y.max(other.y);
+        let x1 = (self.x + self.w).min(other.x + other.w);
+        let y1 = (self.y + self.h).min(other.y + other.h);
+        ClipRect::new(x0, y0, (x1 - x0).max(0), (y1 - y0).max(0))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.w <= 0 || self.h <= 0
+    }
+
+    pub fn area(&self) -> usize {
+        if self.is_empty() {
+            0
         } else {
-            self.mul(1.0 / l)
+            self.w as usize * self.h as usize
+        }
+    }
+
+    /// Whether `other` lies entirely inside this rectangle.
+    pub fn contains_rect(&self, other: ClipRect) -> bool {
+        other.x >= self.x
+            && other.y >= self.y
+            && other.x + other.w <= self.x + self.w
+            && other.y + other.h <= self.y + self.h
+    }
+
+    /// The smallest rectangle covering both.
+    pub fn union(&self, other: ClipRect) -> ClipRect {
+        let x0 = self.x.min(other.x);
+        let y0 = self.y.min(other.y);
+        let x1 = (self.x + self.w).max(other.x + other.w);
+        let y1 = (self.y + self.h).max(other.y + other.h);
+        ClipRect::new(x0, y0, x1 - x0, y1 - y0)
+    }
+
+    /// Whether the rectangles overlap or share an edge.
+    fn touches(&self, other: ClipRect) -> bool {
+        self.x <= other.x + other.w
+            && other.x <= self.x + self.w
+            && self.y <= other.y + other.h
+            && other.y <= self.y + self.h
+    }
+}
+
+/// Most separate rectangles `DirtyRects` keeps before it starts growing existing ones.
+pub const MAX_DIRTY_RECTS: usize = 16;
+
+/// Parts of the screen drawn to since the last present, kept as a short list of rectangles.
+#[derive(Debug, Clone, Copy)]
+pub struct DirtyRects {
+    rects: [ClipRect; MAX_DIRTY_RECTS],
+    len: usize,
+    /// Everything is dirty, so the list is not kept up to date.
+    full: bool,
+}
+
+impl DirtyRects {
+    pub const fn new() -> Self {
+        Self {
+            rects: [ClipRect::new(0, 0, 0, 0); MAX_DIRTY_RECTS],
+            len: 0,
+            full: false,
+        }
+    }
+
+    pub fn rects(&self) -> &[ClipRect] {
+        &self.rects[..self.len]
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.full && self.len == 0
+    }
+
+    /// Total area of the rectangles; overlaps are counted twice.
+    pub fn area(&self) -> usize {
+        self.rects().iter().map(ClipRect::area).sum()
+    }
+
+    pub fn mark_all(&mut self) {
+        self.full = true;
+        self.len = 0;
+    }
+
+    pub fn clear(&mut self) {
+        self.full = false;
+        self.len = 0;
+    }
+
+    /// Adds `rect`, merging it with the rectangles it touches where their bounding box wastes
+    /// little area. When the list is full, the rectangle that grows least takes it in.
+    pub fn add(&mut self, rect: ClipRect) {
+        if self.full || rect.is_empty() {
+            return;
         }
+        // Newest first: runs of pixels mostly land in the rectangle the last one went to.
+        if self.rects().iter().rev().any(|r| r.contains_rect(rect)) {
+            return;
+        }
+
+        let mut rect = rect;
+        let mut i = 0;
+        while i < self.len {
+            let other = self.rects[i];
+            if rect.touches(other) && cheap_to_merge(rect, other) {
+                rect = rect.union(other);
+                self.remove(i);
+                i = 0;
+            } else {
+                i += 1;
+            }
+        }
+
+        if self.len == MAX_DIRTY_RECTS {
+            let growth = |r: &ClipRect| r.union(rect).area() - r.area();
+            let (i, _) = self
+                .rects()
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, r)| growth(r))
+                .unwrap_or((0, &rect));
+            self.rects[i] = self.rects[i].union(rect);
+            return;
+        }
+        self.rects[self.len] = rect;
+        self.len += 1;
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.len -= 1;
+        self.rects[index] = self.rects[self.len];
     }
 }
 
-struct Renderer<'a> {
+impl Default for DirtyRects {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Merging is worth it if the bounding box is at most a third bigger than the two rectangles.
+fn cheap_to_merge(a: ClipRect, b: ClipRect) -> bool {
+    a.union(b).area() * 3 <= (a.area() + b.area()) * 4
+}
+
+pub struct Renderer<'a> {
     buffer: &'a mut [u8],
     info: FrameBufferInfo,
+    /// Pixels outside this rectangle are left alone by everything but `fill` and scrolling.
+    clip: ClipRect,
+    /// What changed since the last `present`.
+    dirty: DirtyRects,
+    /// Bytes the last `present` copied.
+    bytes_presented: usize,
 }
 
 impl<'a> Renderer<'a> {
-    fn new(buffer: &'a mut [u8], info: FrameBufferInfo) -> Self {
-        Self { buffer, info }
+    /// A renderer drawing into `buffer`, laid out as `info` describes. Nothing counts as dirty
+    /// yet, so call `mark_all_dirty` or `present_all` if what is already in `buffer` has never
+    /// been shown.
+    pub fn new(buffer: &'a mut [u8], info: FrameBufferInfo) -> Self {
+        let clip = ClipRect::new(0, 0, info.width as i32, info.height as i32);
+        Self {
+            buffer,
+            info,
+            clip,
+            dirty: DirtyRects::new(),
+            bytes_presented: 0,
+        }
     }
 
-    fn width(&self) -> i32 {
+    pub fn width(&self) -> i32 {
         self.info.width as i32
     }
 
-    fn height(&self) -> i32 {
+    pub fn height(&self) -> i32 {
         self.info.height as i32
     }
 
-    fn set_pixel(&mut self, x: i32, y: i32, color: Color) {
-        if x < 0 || y < 0 || x >= self.width() || y >= self.height() {
-            return;
+    /// The screen rectangle drawing is currently limited to.
+    pub fn clip(&self) -> ClipRect {
+        self.clip
+    }
+
+    /// Limits drawing to `clip`, or to the part of it on screen.
+    pub fn set_clip(&mut self, clip: ClipRect) {
+        self.clip = clip.intersect(ClipRect::new(0, 0, self.width(), self.height()));
+    }
+
+    /// Lets drawing reach the whole screen again.
+    pub fn reset_clip(&mut self) {
+        self.clip = ClipRect::new(0, 0, self.width(), self.height());
+    }
+
+    /// Records `rect` as changed, for drawing done straight into the buffer.
+    pub fn mark_dirty(&mut self, rect: ClipRect) {
+        self.dirty
+            .add(rect.intersect(ClipRect::new(0, 0, self.width(), self.height())));
+    }
+
+    /// Makes the next `present` copy the whole buffer.
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty.mark_all();
+    }
+
+    pub fn dirty(&self) -> &DirtyRects {
+        &self.dirty
+    }
+
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if self.write_pixel(x, y, color) {
+            self.dirty.add(ClipRect::new(x, y, 1, 1));
         }
+    }
 
-        let x = x as usize;
-        let y = y as usize;
-        let pixel_index = y * self.info.stride + x;
+    /// Like `set_pixel`, but leaves marking the pixel dirty to the caller, so a run of pixels
+    /// can be marked as one rectangle. Returns whether the pixel was written.
+    pub(crate) fn write_pixel(&mut self, x: i32, y: i32, color: Color) -> bool {
+        if !self.clip.contains(x, y) {
+            return false;
+        }
+
+        let pixel_index = y as usize * self.info.stride + x as usize;
         let byte_index = pixel_index * self.info.bytes_per_pixel;
         if byte_index + self.info.bytes_per_pixel > self.buffer.len() {
-            return;
+            return false;
         }
 
         match self.info.pixel_format {
@@ -118,9 +380,110 @@ impl<'a> Renderer<'a> {
             }
             _ => {}
         }
+        true
     }
 
-    fn fill(&mut self, color: Color) {
+    /// The colour at `x`, `y`, black off screen.
+    pub fn get_pixel(&self, x: i32, y: i32) -> Color {
+        if x < 0 || y < 0 || x >= self.width() || y >= self.height() {
+            return Color::rgb(0, 0, 0);
+        }
+
+        let bpp = self.info.bytes_per_pixel;
+        let byte_index = (y as usize * self.info.stride + x as usize) * bpp;
+        let Some(px) = self.buffer.get(byte_index..byte_index + bpp) else {
+            return Color::rgb(0, 0, 0);
+        };
+        let channel = |i: usize| px.get(i).copied().unwrap_or(0);
+        match self.info.pixel_format {
+            PixelFormat::Rgb => Color::rgb(channel(0), channel(1), channel(2)),
+            PixelFormat::Bgr => Color::rgb(channel(2), channel(1), channel(0)),
+            PixelFormat::U8 => Color::rgb(px[0], px[0], px[0]),
+            _ => Color::rgb(channel(0), channel(1), channel(2)),
+        }
+    }
+
+    /// Composites `color` over the pixel at `x`, `y`.
+    pub fn blend_pixel(&mut self, x: i32, y: i32, color: Rgba) {
+        match color.a {
+            0 => {}
+            255 => self.set_pixel(x, y, color.rgb()),
+            _ => {
+                if self.clip.contains(x, y) {
+                    let dst = self.get_pixel(x, y);
+                    self.set_pixel(x, y, color.over(dst));
+                }
+            }
+        }
+    }
+
+    /// Composites `color` over the `w` by `h` rectangle with its top left corner at `x`, `y`.
+    pub fn blend_rect(&mut self, x: i32, y: i32, w: i32, h: i32, color: Rgba) {
+        let area = ClipRect::new(x, y, w, h).intersect(self.clip);
+        for row in area.y..area.y + area.h {
+            let pixels = (0..area.w).map(|_| color);
+            self.blend_row(area.x, row, pixels);
+        }
+    }
+
+    /// Composites a run of pixels onto row `y`, starting at `x` and going right. Pixels outside
+    /// the clip rectangle are skipped.
+    pub fn blend_row(&mut self, x: i32, y: i32, pixels: impl Iterator<Item = Rgba>) {
+        let clip = self.clip;
+        if y < clip.y || y >= clip.y + clip.h {
+            return;
+        }
+        let skip = (clip.x - x).max(0);
+        let start = x + skip;
+        let room = (clip.x + clip.w - start).max(0) as usize;
+        let pixels = pixels.skip(skip as usize).take(room);
+
+        // 32-bit RGB and BGR are by far the most common formats, so those get written straight
+        // into the buffer instead of going through `set_pixel` one channel at a time.
+        let Some((r, b)) = self.rgb_offsets() else {
+            for (i, color) in pixels.enumerate() {
+                self.blend_pixel(start + i as i32, y, color);
+            }
+            return;
+        };
+        let row_start = (y as usize * self.info.stride + start as usize) * 4;
+        let row_end = (row_start + room * 4).min(self.buffer.len());
+        let Some(row) = self.buffer.get_mut(row_start..row_end) else {
+            return;
+        };
+        self.dirty
+            .add(ClipRect::new(start, y, (row.len() / 4) as i32, 1));
+        for (px, color) in row.as_chunks_mut::<4>().0.iter_mut().zip(pixels) {
+            match color.a {
+                0 => {}
+                255 => {
+                    px[r] = color.r;
+                    px[1] = color.g;
+                    px[b] = color.b;
+                }
+                a => {
+                    px[r] = blend_channel(color.r, px[r], a);
+                    px[1] = blend_channel(color.g, px[1], a);
+                    px[b] = blend_channel(color.b, px[b], a);
+                }
+            }
+        }
+    }
+
+    /// Byte offsets of red and blue within a pixel, for 4-byte RGB and BGR framebuffers.
+    fn rgb_offsets(&self) -> Option<(usize, usize)> {
+        if self.info.bytes_per_pixel != 4 {
+            return None;
+        }
+        match self.info.pixel_format {
+            PixelFormat::Rgb => Some((0, 2)),
+            PixelFormat::Bgr => Some((2, 0)),
+            _ => None,
+        }
+    }
+
+    pub fn fill(&mut self, color: Color) {
+        self.dirty.mark_all();
         let bpp = self.info.bytes_per_pixel;
         match self.info.pixel_format {
             PixelFormat::Rgb => {
@@ -165,24 +528,86 @@ impl<'a> Renderer<'a> {
         }
     }
 
-    fn fill_block(&mut self, x0: i32, y0: i32, w: i32, h: i32, color: Color) {
+    pub fn fill_block(&mut self, x0: i32, y0: i32, w: i32, h: i32, color: Color) {
+        self.mark_dirty(ClipRect::new(x0, y0, w, h).intersect(self.clip));
         for y in y0..(y0 + h) {
             for x in x0..(x0 + w) {
-                self.set_pixel(x, y, color);
+                self.write_pixel(x, y, color);
             }
         }
     }
 
-    fn present(&self, target: &mut [u8]) {
-        target.copy_from_slice(self.buffer);
+    /// Moves pixel rows `y0..y1` up by `rows` and fills the rows exposed at the bottom of that band with `color`.
+    pub fn scroll_up(&mut self, y0: i32, y1: i32, rows: i32, color: Color) {
+        let (y0, y1, rows) = self.clamp_band(y0, y1, rows);
+        let row_bytes = self.info.stride * self.info.bytes_per_pixel;
+        let start = y0 as usize * row_bytes;
+        let end = y1 as usize * row_bytes;
+        let shift = rows as usize * row_bytes;
+        self.buffer.copy_within(start + shift..end, start);
+        self.mark_dirty(ClipRect::new(0, y0, self.width(), y1 - y0));
+        self.fill_block(0, y1 - rows, self.width(), rows, color);
     }
-}
 
-#[derive(Clone, Copy)]
-struct Sphere {
-    center: Vec3,
-    radius: f32,
-    albedo: Vec3,
+    /// Moves pixel rows `y0..y1` down by `rows` and fills the rows exposed at the top of that band with `color`.
+    pub fn scroll_down(&mut self, y0: i32, y1: i32, rows: i32, color: Color) {
+        let (y0, y1, rows) = self.clamp_band(y0, y1, rows);
+        let row_bytes = self.info.stride * self.info.bytes_per_pixel;
+        let start = y0 as usize * row_bytes;
+        let end = y1 as usize * row_bytes;
+        let shift = rows as usize * row_bytes;
+        self.buffer.copy_within(start..end - shift, start + shift);
+        self.mark_dirty(ClipRect::new(0, y0, self.width(), y1 - y0));
+        self.fill_block(0, y0, self.width(), rows, color);
+    }
+
+    fn clamp_band(&self, y0: i32, y1: i32, rows: i32) -> (i32, i32, i32) {
+        let max_y = (self.buffer.len() / (self.info.stride * self.info.bytes_per_pixel)) as i32;
+        let y0 = y0.clamp(0, self.height().min(max_y));
+        let y1 = y1.clamp(y0, self.height().min(max_y));
+        (y0, y1, rows.clamp(0, y1 - y0))
+    }
+
+    /// Copies what changed since the last present into `target`, a framebuffer with the same
+    /// layout. Falls back to copying everything once the changes cover half the screen, where
+    /// one big copy beats many small ones. Returns the number of bytes copied.
+    pub fn present(&mut self, target: &mut [u8]) -> usize {
+        let screen = self.info.width * self.info.height;
+        if self.dirty.is_full() || self.dirty.area() * 2 >= screen {
+            return self.present_all(target);
+        }
+
+        let bpp = self.info.bytes_per_pixel;
+        let len = self.buffer.len().min(target.len());
+        let mut copied = 0;
+        for rect in self.dirty.rects() {
+            for y in rect.y..rect.y + rect.h {
+                let start = (y as usize * self.info.stride + rect.x as usize) * bpp;
+                let end = (start + rect.w as usize * bpp).min(len);
+                if start < end {
+                    target[start..end].copy_from_slice(&self.buffer[start..end]);
+                    copied += end - start;
+                }
+            }
+        }
+        self.dirty.clear();
+        self.bytes_presented = copied;
+        copied
+    }
+
+    /// Copies the whole buffer into `target`, whatever changed. Returns the number of bytes copied.
+    pub fn present_all(&mut self, target: &mut [u8]) -> usize {
+        let len = self.buffer.len().min(target.len());
+        target[..len].copy_from_slice(&self.buffer[..len]);
+        self.dirty.clear();
+        self.bytes_presented = len;
+        len
+    }
+
+    /// Bytes copied by the last `present` or `present_all`.
+    pub fn bytes_presented(&self) -> usize {
+        self.bytes_presented
+    }
 }
 
 fn clamp01(v: f32) -> f32 {
@@ -197,147 +622,495 @@ fn to_color(v: Vec3) -> Color {
     )
 }
 
-fn scene(tick: u64) -> [Sphere; 3] {
+/// Height of the demo's floor.
+const FLOOR_Y: f32 = -1.0;
+
+/// The demo scene at `tick`: three balls bouncing on a checkered floor that melt together
+/// where they meet, a spinning box with a ball carved out of it, a tumbling mirror ring, and
+/// a warm and a cool light. `None` if that is more than a `Scene` has room for.
+fn scene(tick: u64) -> Option<Scene> {
     let t = tick as f32 * 0.045;
-    [
-        Sphere {
-            center: Vec3::new(
-                1.9 * sinf(t * 0.9 + 0.2),
-                1.1 * sinf(t * 1.3 + 1.2),
-                -3.4 + 1.0 * sinf(t * 0.7 + 2.1),
-            ),
-            radius: 0.52,
-            albedo: Vec3::new(1.0, 0.35, 0.35),
-        },
-        Sphere {
-            center: Vec3::new(
-                2.0 * cosf(t * 0.8 + 2.4),
-                1.0 * sinf(t * 1.1 + 0.5),
-                -3.2 + 1.15 * cosf(t * 0.6 + 1.1),
-            ),
-            radius: 0.48,
-            albedo: Vec3::new(0.35, 1.0, 0.55),
-        },
-        Sphere {
-            center: Vec3::new(
-                1.7 * sinf(t * 1.05 + 4.0),
-                1.2 * cosf(t * 0.95 + 0.9),
-                -3.6 + 0.9 * sinf(t * 0.8 + 4.2),
-            ),
-            radius: 0.44,
-            albedo: Vec3::new(0.4, 0.6, 1.0),
-        },
-    ]
-}
+    let mut scene = Scene::new();
 
-fn ray_sphere_intersection(ro: Vec3, rd: Vec3, s: Sphere) -> Option<f32> {
-    let oc = ro.sub(s.center);
-    let a = rd.dot(rd);
-    let b = 2.0 * oc.dot(rd);
-    let c = oc.dot(oc) - s.radius * s.radius;
-    let disc = b * b - 4.0 * a * c;
-    if disc < 0.0 {
-        return None;
+    let floor = scene.add_material(Material {
+        checker: Some(Vec3::new(0.25, 0.25, 0.3)),
+        reflectivity: 0.15,
+        ..Material::new(Vec3::new(0.8, 0.8, 0.8))
+    })?;
+    let red = scene.add_material(Material::new(Vec3::new(1.0, 0.35, 0.35)))?;
+    let green = scene.add_material(Material::new(Vec3::new(0.35, 1.0, 0.55)))?;
+    let blue = scene.add_material(Material::new(Vec3::new(0.4, 0.6, 1.0)))?;
+    let gold = scene.add_material(Material {
+        specular: 0.8,
+        shininess: 48.0,
+        reflectivity: 0.25,
+        ..Material::new(Vec3::new(0.9, 0.7, 0.3))
+    })?;
+    let mirror = scene.add_material(Material {
+        specular: 1.0,
+        shininess: 96.0,
+        reflectivity: 0.7,
+        ..Material::new(Vec3::new(0.9, 0.9, 0.95))
+    })?;
+
+    // Radius, material, position on the floor, and how fast each one bounces.
+    let balls = [
+        (
+            0.52,
+            red,
+            1.9 * sinf(t * 0.9 + 0.2),
+            -3.4 + sinf(t * 0.7 + 2.1),
+            1.3,
+        ),
+        (
+            0.48,
+            green,
+            2.0 * cosf(t * 0.8 + 2.4),
+            -3.2 + 1.15 * cosf(t * 0.6 + 1.1),
+            1.1,
+        ),
+        (
+            0.44,
+            blue,
+            1.7 * sinf(t * 1.05 + 4.0),
+            -3.6 + 0.9 * sinf(t * 0.8 + 4.2),
+            0.95,
+        ),
+    ];
+    let mut blob = None;
+    for (radius, material, x, z, speed) in balls {
+        let y = FLOOR_Y + radius + 1.4 * sinf(t * speed).abs();
+        let ball = scene.add(Object::new(
+            Shape::Sphere { radius },
+            Vec3::new(x, y, z),
+            material,
+        ))?;
+        blob = Some(match blob {
+            Some(blob) => scene.combine(Op::SmoothUnion(0.35), blob, ball)?,
+            None => ball,
+        });
     }
-    let sqrt_disc = sqrtf(disc);
-    let inv_2a = 1.0 / (2.0 * a);
-    let t0 = (-b - sqrt_disc) * inv_2a;
-    let t1 = (-b + sqrt_disc) * inv_2a;
 
-    if t0 > 0.0 {
-        Some(t0)
-    } else if t1 > 0.0 {
-        Some(t1)
-    } else {
-        None
+    let centre = Vec3::new(-2.2, FLOOR_Y + 0.5, -4.6);
+    let block = scene.add(
+        Object::new(
+            Shape::Box {
+                half: Vec3::splat(0.45),
+                radius: 0.06,
+            },
+            centre,
+            gold,
+        )
+        .rotated(Axis::Y, t * 0.6),
+    )?;
+    let hole = scene.add(Object::new(
+        Shape::Sphere { radius: 0.56 },
+        centre + Vec3::new(0.0, 0.2 * sinf(t), 0.0),
+        gold,
+    ))?;
+    let carved = scene.combine(Op::SmoothSubtract(0.05), block, hole)?;
+
+    let ring = scene.add(
+        Object::new(
+            Shape::Torus {
+                major: 0.45,
+                minor: 0.14,
+            },
+            Vec3::new(2.3, FLOOR_Y + 0.75, -4.8),
+            mirror,
+        )
+        .rotated(Axis::X, t * 0.7),
+    )?;
+
+    let ground = scene.add(Object::new(
+        Shape::Plane {
+            normal: Vec3::new(0.0, 1.0, 0.0),
+        },
+        Vec3::new(0.0, FLOOR_Y, 0.0),
+        floor,
+    ))?;
+
+    let mut root = scene.combine(Op::Union, carved, ring)?;
+    if let Some(blob) = blob {
+        root = scene.combine(Op::Union, root, blob)?;
     }
+    scene.combine(Op::Union, root, ground)?;
+
+    scene.add_light(Light {
+        position: Vec3::new(3.0, 4.0, 2.0),
+        color: Vec3::new(1.0, 0.92, 0.8),
+    })?;
+    scene.add_light(Light {
+        position: Vec3::new(-3.5, 2.5, 0.5),
+        color: Vec3::new(0.3, 0.4, 0.65),
+    })?;
+    Some(scene)
 }
 
-fn raycast_spheres(ro: Vec3, rd: Vec3, spheres: &[Sphere; 3]) -> Option<(f32, usize)> {
-    let mut best_t = f32::INFINITY;
-    let mut best_id = 0usize;
-    let mut hit = false;
-
-    for (i, s) in spheres.iter().enumerate() {
-        if let Some(t) = ray_sphere_intersection(ro, rd, *s) {
-            if t < best_t {
-                best_t = t;
-                best_id = i;
-                hit = true;
+/// Most tiles a frame is split into.
+const MAX_TILES: usize = 64;
+
+/// Everything a tile needs to render its part of a frame.
+struct FrameSetup {
+    info: FrameBufferInfo,
+    scene: Scene,
+    jitter: f32,
+    render_w: i32,
+    render_h: i32,
+    block_w: i32,
+    block_h: i32,
+    /// Rows of blocks per tile; the last tile also gets the pixel rows below the last block.
+    tile_rows: i32,
+    camera: Vec3,
+    /// The camera's right and up vectors, scaled to reach the edges of the view.
+    right: Vec3,
+    up: Vec3,
+    forward: Vec3,
+}
+
+impl FrameSetup {
+    /// Renders rows of blocks `rows` into `renderer`, whose top pixel row is `y0` on screen.
+    fn render_rows(&self, renderer: &mut Renderer, y0: i32, rows: core::ops::Range<i32>) {
+        for py in rows {
+            for px in 0..self.render_w {
+                let uvx = ((px as f32 + 0.5 + self.jitter) / self.render_w as f32) * 2.0 - 1.0;
+                let uvy = ((py as f32 + 0.5 - self.jitter) / self.render_h as f32) * 2.0 - 1.0;
+                let rd = (self.right * uvx - self.up * uvy + self.forward).norm();
+                let c = self.scene.trace(self.camera, rd);
+                renderer.fill_block(
+                    px * self.block_w,
+                    py * self.block_h - y0,
+                    self.block_w,
+                    self.block_h,
+                    to_color(c),
+                );
             }
         }
     }
 
-    if hit {
-        Some((best_t, best_id))
-    } else {
-        None
+    /// Takes tiles off `next` until there are none left and renders them.
+    fn render_tiles(&self, tiles: &[Mutex<&mut [u8]>], next: &AtomicUsize) {
+        let row_bytes = self.info.stride * self.info.bytes_per_pixel;
+        let tile_height = self.tile_rows * self.block_h;
+        loop {
+            let index = next.fetch_add(1, Ordering::Relaxed);
+            let Some(tile) = tiles.get(index) else {
+                return;
+            };
+            let mut band = tile.lock();
+            let mut info = self.info;
+            info.byte_len = band.len();
+            info.height = band.len() / row_bytes;
+            let mut renderer = Renderer::new(&mut band, info);
+            renderer.fill(Color::rgb(0, 0, 0));
+
+            let first = index as i32 * self.tile_rows;
+            let rows = first..(first + self.tile_rows).min(self.render_h);
+            self.render_rows(&mut renderer, index as i32 * tile_height, rows);
+        }
+    }
+}
+
+/// Time-stamp counter ticks per timer tick, measured over one tick.
+fn tsc_per_tick() -> u64 {
+    let start = interupts::timer_ticks();
+    while interupts::timer_ticks() == start {
+        core::hint::spin_loop();
     }
+    let tsc = unsafe { core::arch::x86_64::_rdtsc() };
+    while interupts::timer_ticks() <= start + 1 {
+        core::hint::spin_loop();
+    }
+    (unsafe { core::arch::x86_64::_rdtsc() } - tsc).max(1)
+}
+
+/// Average render time per frame for one way of rendering.
+#[derive(Default, Clone, Copy)]
+struct FrameTimes {
+    frames: u64,
+    cycles: u64,
+}
+
+impl FrameTimes {
+    fn add(&mut self, cycles: u64) {
+        self.frames += 1;
+        self.cycles += cycles;
+    }
+
+    fn micros(&self, tsc_per_tick: u64) -> u64 {
+        let per_second = tsc_per_tick * u64::from(interupts::TIMER_HZ);
+        (self.cycles / self.frames.max(1)) * 1_000_000 / per_second
+    }
+}
+
+/// Where the demo camera starts, and where Home puts it back.
+const CAMERA_START: Vec3 = Vec3::new(0.0, 0.0, 2.8);
+/// Distance flown per movement key press.
+const MOVE_STEP: f32 = 0.15;
+/// Radians turned per arrow key press.
+const TURN_STEP: f32 = 0.05;
+/// Radians turned per mouse count.
+const MOUSE_SENSITIVITY: f32 = 0.004;
+/// How far the camera can look up or down, just short of straight up.
+const MAX_PITCH: f32 = 1.45;
+/// Range of `Camera::fov`, and the factor each `+`/`-` press changes it by.
+const MIN_FOV: f32 = 0.2;
+const MAX_FOV: f32 = 3.0;
+const FOV_STEP: f32 = 1.1;
+/// Range of screen pixels per rendered pixel along each axis. Lower is sharper and slower.
+const MIN_BLOCK: i32 = 2;
+const MAX_BLOCK: i32 = 32;
+const DEFAULT_BLOCK: i32 = 16;
+
+/// Where the demo looks from.
+#[derive(Clone, Copy)]
+struct Camera {
+    position: Vec3,
+    /// Radians turned right from looking down -Z.
+    yaw: f32,
+    /// Radians tilted up.
+    pitch: f32,
+    /// Tangent of half the vertical field of view.
+    fov: f32,
 }
 
-fn shade_hit(ro: Vec3, rd: Vec3, t: f32, s: Sphere) -> Vec3 {
-    let p = ro.add(rd.mul(t));
-    let n = p.sub(s.center).norm();
-    let light_pos = Vec3::new(0.0, 0.0, 2.5);
-    let l = light_pos.sub(p).norm();
-    let v = ro.sub(p).norm();
-    let h = l.add(v).norm();
+impl Camera {
+    const fn new() -> Camera {
+        Camera {
+            position: CAMERA_START,
+            yaw: 0.0,
+            pitch: 0.0,
+            fov: 1.1,
+        }
+    }
 
-    let diff = clamp01(n.dot(l));
-    let spec = powf(clamp01(n.dot(h)), 28.0) * 0.4;
-    let amb = 0.08;
-    let shade = amb + diff * 0.95;
+    /// Unit vectors pointing right, up and forward from the camera.
+    fn basis(&self) -> (Vec3, Vec3, Vec3) {
+        let (sin_yaw, cos_yaw) = (sinf(self.yaw), cosf(self.yaw));
+        let (sin_pitch, cos_pitch) = (sinf(self.pitch), cosf(self.pitch));
+        let right = Vec3::new(cos_yaw, 0.0, sin_yaw);
+        let up = Vec3::new(-sin_yaw * sin_pitch, cos_pitch, cos_yaw * sin_pitch);
+        let forward = Vec3::new(sin_yaw * cos_pitch, sin_pitch, -cos_yaw * cos_pitch);
+        (right, up, forward)
+    }
 
-    s.albedo.mul(shade).add(Vec3::new(spec, spec, spec))
+    /// Back to the starting position and direction, keeping the field of view.
+    fn reset(&mut self) {
+        *self = Camera {
+            fov: self.fov,
+            ..Camera::new()
+        };
+    }
+
+    fn turn(&mut self, yaw: f32, pitch: f32) {
+        self.yaw += yaw;
+        self.pitch = (self.pitch + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    /// Moves along the camera's own axes, so forward is wherever it is looking.
+    fn fly(&mut self, right: f32, up: f32, forward: f32) {
+        let (r, u, f) = self.basis();
+        self.position += r * right + u * up + f * forward;
+    }
 }
 
-pub fn run_bouncy_circles(framebuffer: &mut FrameBuffer) -> ! {
+/// Runs the raymarched scene demo until Escape is pressed on the active console.
+///
+/// W/S and Up/Down fly forward and back, A/D strafe, Q/E sink and rise, Left/Right turn, and
+/// moving the mouse looks around. Home or the right mouse button puts the camera back where
+/// it started. `+`/`-` zoom by narrowing and widening the field of view, `]`/`[` raise and
+/// lower the render resolution, Space or P pauses the animation, F12 takes a screenshot, and
+/// M switches between rendering on every CPU and on this one only.
+///
+/// The frame is split into bands of block rows that the CPUs take in turn. Render times are
+/// logged every second, and the speedup over one CPU when the demo ends.
+pub fn run_bouncy_circles(framebuffer: &mut FrameBuffer) {
     let info = framebuffer.info();
     let framebuffer_bytes = framebuffer.buffer_mut();
-    let byte_len = info.byte_len;
-    if byte_len == 0 || byte_len > MAX_BACKBUFFER_BYTES {
-        loop {
-            core::hint::spin_loop();
-        }
+    if info.byte_len == 0 {
+        return;
     }
+    // The demo was handed the framebuffer, and the backbuffer with it.
+    let Some(backbuffer) = (unsafe { backbuffer(info.byte_len) }) else {
+        return;
+    };
 
-    let backbuffer_ptr = core::ptr::addr_of_mut!(BACKBUFFER) as *mut u8;
-    let backbuffer = unsafe { core::slice::from_raw_parts_mut(backbuffer_ptr, byte_len) };
-    let mut renderer = Renderer::new(backbuffer, info);
+    let (width, height) = (info.width as i32, info.height as i32);
+    let mut block = DEFAULT_BLOCK;
+    let mut camera = Camera::new();
+    let mut paused = false;
+    // Animation time, which stands still while paused.
+    let mut scene_tick = interupts::timer_ticks();
+    let mut last_tick = scene_tick;
+    // Movement from before the demo started is not meant for it.
+    mouse::take_motion();
 
-    let render_w = (renderer.width() / 10).max(80);
-    let render_h = (renderer.height() / 10).max(45);
-    let block_w = (renderer.width() / render_w).max(1);
-    let block_h = (renderer.height() / render_h).max(1);
-    let aspect = render_w as f32 / render_h as f32;
-    let camera = Vec3::new(0.0, 0.0, 2.8);
-    let fov = 1.1f32;
+    let tsc_per_tick = tsc_per_tick();
+    let cpus = parallel::online_cpus();
+    let mut parallel = cpus > 1;
+    let mut single = FrameTimes::default();
+    let mut multi = FrameTimes::default();
+    let mut report_tick = interupts::timer_ticks() + u64::from(interupts::TIMER_HZ);
+    let mut bytes_presented = 0u64;
 
     loop {
         let frame_tick = interupts::timer_ticks();
-        let spheres = scene(frame_tick);
-        renderer.fill(Color::rgb(0, 0, 0));
-        let jitter = if (frame_tick & 1) == 0 { 0.25 } else { -0.25 };
-
-        for py in 0..render_h {
-            for px in 0..render_w {
-                let uvx = ((px as f32 + 0.5 + jitter) / render_w as f32) * 2.0 - 1.0;
-                let uvy = ((py as f32 + 0.5 - jitter) / render_h as f32) * 2.0 - 1.0;
-                let rd = Vec3::new(uvx * aspect * fov, -uvy * fov, -1.0).norm();
-                let c = if let Some((t_hit, id)) = raycast_spheres(camera, rd, &spheres) {
-                    shade_hit(camera, rd, t_hit, spheres[id])
-                } else {
-                    let sky = 0.15 + 0.85 * clamp01(0.5 + 0.5 * rd.y);
-                    Vec3::new(0.02, 0.03, 0.08).mul(sky)
-                };
-                renderer.fill_block(px * block_w, py * block_h, block_w, block_h, to_color(c));
+        if !paused {
+            scene_tick += frame_tick - last_tick;
+        }
+        last_tick = frame_tick;
+
+        let render_w = (width / block).max(1);
+        let render_h = (height / block).max(1);
+        let block_h = (height / render_h).max(1);
+        // One tile is kept back for the rows below the last block.
+        let tile_rows = (render_h as usize).div_ceil(MAX_TILES - 1).max(1) as i32;
+        let tile_bytes = (tile_rows * block_h) as usize * info.stride * info.bytes_per_pixel;
+        let aspect = render_w as f32 / render_h as f32;
+        let (right, up, forward) = camera.basis();
+
+        let Some(scene) = scene(scene_tick) else {
+            log::warn!("demo: the scene does not fit in sdf::Scene");
+            return;
+        };
+        let setup = FrameSetup {
+            info,
+            scene,
+            jitter: if (frame_tick & 1) == 0 { 0.25 } else { -0.25 },
+            render_w,
+            render_h,
+            block_w: (width / render_w).max(1),
+            block_h,
+            tile_rows,
+            camera: camera.position,
+            right: right * (aspect * camera.fov),
+            up: up * camera.fov,
+            forward,
+        };
+
+        let start = unsafe { core::arch::x86_64::_rdtsc() };
+        {
+            let mut tiles: [Mutex<&mut [u8]>; MAX_TILES] =
+                [const { Mutex::new(&mut []) }; MAX_TILES];
+            let mut count = 0;
+            for (tile, band) in tiles.iter_mut().zip(backbuffer.chunks_mut(tile_bytes)) {
+                *tile.get_mut() = band;
+                count += 1;
+            }
+            let tiles = &tiles[..count];
+            let next = AtomicUsize::new(0);
+            if parallel {
+                parallel::run_on_all_cpus(&|_cpu| setup.render_tiles(tiles, &next));
+            } else {
+                setup.render_tiles(tiles, &next);
             }
         }
+        let cycles = unsafe { core::arch::x86_64::_rdtsc() } - start;
+        if parallel {
+            multi.add(cycles);
+        } else {
+            single.add(cycles);
+        }
+
+        // The whole frame is redrawn, so there is nothing to gain from tracking what changed.
+        let mut renderer = Renderer::new(backbuffer, info);
+        bytes_presented += renderer.present_all(framebuffer_bytes) as u64;
+
+        if frame_tick >= report_tick {
+            report_tick = frame_tick + u64::from(interupts::TIMER_HZ);
+            let (mode, times) = if parallel {
+                ("cpus", multi)
+            } else {
+                ("cpu", single)
+            };
+            log::debug!(
+                "demo: {} {}, {} us per frame",
+                if parallel { cpus } else { 1 },
+                mode,
+                times.micros(tsc_per_tick)
+            );
+        }
+
+        let motion = mouse::take_motion();
+        camera.turn(
+            motion.dx as f32 * MOUSE_SENSITIVITY,
+            motion.dy as f32 * MOUSE_SENSITIVITY,
+        );
+        if motion.right {
+            camera.reset();
+        }
 
-        renderer.present(framebuffer_bytes);
+        // Everything typed since the last frame is handled, so held keys keep up with their
+        // repeat rate however slow frames are.
+        while let Some(key) = console::read_key(console::active()) {
+            match key {
+                DecodedKey::Unicode('w' | 'W') | DecodedKey::RawKey(KeyCode::ArrowUp) => {
+                    camera.fly(0.0, 0.0, MOVE_STEP)
+                }
+                DecodedKey::Unicode('s' | 'S') | DecodedKey::RawKey(KeyCode::ArrowDown) => {
+                    camera.fly(0.0, 0.0, -MOVE_STEP)
+                }
+                DecodedKey::Unicode('a' | 'A') => camera.fly(-MOVE_STEP, 0.0, 0.0),
+                DecodedKey::Unicode('d' | 'D') => camera.fly(MOVE_STEP, 0.0, 0.0),
+                DecodedKey::Unicode('q' | 'Q') => camera.fly(0.0, -MOVE_STEP, 0.0),
+                DecodedKey::Unicode('e' | 'E') => camera.fly(0.0, MOVE_STEP, 0.0),
+                DecodedKey::RawKey(KeyCode::ArrowLeft) => camera.turn(-TURN_STEP, 0.0),
+                DecodedKey::RawKey(KeyCode::ArrowRight) => camera.turn(TURN_STEP, 0.0),
+                DecodedKey::RawKey(KeyCode::Home) => camera.reset(),
+                DecodedKey::Unicode('+' | '=') => {
+                    camera.fov = (camera.fov / FOV_STEP).max(MIN_FOV);
+                }
+                DecodedKey::Unicode('-' | '_') => {
+                    camera.fov = (camera.fov * FOV_STEP).min(MAX_FOV);
+                }
+                DecodedKey::Unicode(']') => {
+                    block = (block - 1).max(MIN_BLOCK);
+                    log::debug!("demo: rendering at {}x{}", width / block, height / block);
+                }
+                DecodedKey::Unicode('[') => {
+                    block = (block + 1).min(MAX_BLOCK);
+                    log::debug!("demo: rendering at {}x{}", width / block, height / block);
+                }
+                DecodedKey::Unicode(' ' | 'p' | 'P') => paused = !paused,
+                DecodedKey::Unicode('m' | 'M') => parallel = !parallel,
+                DecodedKey::RawKey(KeyCode::F12) => screenshot::send(&renderer),
+                DecodedKey::Unicode('\x1b') => {
+                    report_speedup(cpus, single, multi, tsc_per_tick);
+                    let frames = (single.frames + multi.frames).max(1);
+                    log::debug!(
+                        "demo: {} bytes presented per frame",
+                        bytes_presented / frames
+                    );
+                    return;
+                }
+                _ => {}
+            }
+        }
 
         while interupts::timer_ticks() == frame_tick {
             core::hint::spin_loop();
         }
     }
 }
+
+fn report_speedup(cpus: usize, single: FrameTimes, multi: FrameTimes, tsc_per_tick: u64) {
+    if single.frames == 0 || multi.frames == 0 {
+        let times = if multi.frames == 0 { single } else { multi };
+        log::info!(
+            "demo: {} us per frame on {} cpu(s)",
+            times.micros(tsc_per_tick),
+            if multi.frames == 0 { 1 } else { cpus }
+        );
+        return;
+    }
+    let single_us = single.micros(tsc_per_tick);
+    let multi_us = multi.micros(tsc_per_tick).max(1);
+    log::info!(
+        "demo: {} us per frame on 1 cpu, {} us on {} cpus, {}.{:02}x speedup",
+        single_us,
+        multi_us,
+        cpus,
+        single_us / multi_us,
+        single_us * 100 / multi_us % 100
+    );
+}
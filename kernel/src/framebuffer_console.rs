@@ -0,0 +1,600 @@
+use core::fmt::Write;
+
+use bootloader_api::info::{FrameBuffer, FrameBufferInfo};
+use spin::Mutex;
+
+use crate::ansi::{AnsiParser, AnsiScreen, TabStops, DEFAULT_TAB_WIDTH};
+use crate::console::{self, VIRTUAL_CONSOLES};
+use crate::font::Font;
+use crate::framebuffer::{backbuffer, clip_to_backbuffer, Color, Renderer};
+use crate::scrollback::Scrollback;
+use crate::tui::TextCanvas;
+use crate::vga_text_mode::{ColorData, VGAChar, VGAColorCode};
+
+/// Largest text grid the console keeps, enough for 1920x1440 with the 8x16 font.
+pub const MAX_COLS: usize = 240;
+pub const MAX_ROWS: usize = 90;
+
+/// Number of lines kept after they scroll off the top of the screen.
+pub const SCROLLBACK_LINES: usize = 200;
+
+/// One console per virtual console. Only the active one holds the framebuffer; the others keep
+/// recording text into their cells until they are switched to.
+pub static FRAMEBUFFER_CONSOLES: [Mutex<FramebufferConsole>; VIRTUAL_CONSOLES] =
+    [const { Mutex::new(FramebufferConsole::new()) }; VIRTUAL_CONSOLES];
+
+pub const DEFAULT_FOREGROUND: VGAColorCode = VGAColorCode::LightGray;
+pub const DEFAULT_BACKGROUND: VGAColorCode = VGAColorCode::Black;
+
+/// The standard 16-colour VGA palette, indexed by `VGAColorCode`.
+const PALETTE: [Color; 16] = [
+    Color::rgb(0x00, 0x00, 0x00),
+    Color::rgb(0x00, 0x00, 0xAA),
+    Color::rgb(0x00, 0xAA, 0x00),
+    Color::rgb(0x00, 0xAA, 0xAA),
+    Color::rgb(0xAA, 0x00, 0x00),
+    Color::rgb(0xAA, 0x00, 0xAA),
+    Color::rgb(0xAA, 0x55, 0x00),
+    Color::rgb(0xAA, 0xAA, 0xAA),
+    Color::rgb(0x55, 0x55, 0x55),
+    Color::rgb(0x55, 0x55, 0xFF),
+    Color::rgb(0x55, 0xFF, 0x55),
+    Color::rgb(0x55, 0xFF, 0xFF),
+    Color::rgb(0xFF, 0x55, 0x55),
+    Color::rgb(0xFF, 0x55, 0xFF),
+    Color::rgb(0xFF, 0xFF, 0x55),
+    Color::rgb(0xFF, 0xFF, 0xFF),
+];
+
+pub fn palette_color(color: VGAColorCode) -> Color {
+    PALETTE[color as usize]
+}
+
+/// Takes over the framebuffer for text output. From here on `print!`/`println!` render into it.
+/// The font is picked to suit the framebuffer resolution.
+pub fn init(framebuffer: &'static mut FrameBuffer) {
+    let info = framebuffer.info();
+    let font = Font::for_resolution(info.width, info.height);
+    for console in FRAMEBUFFER_CONSOLES.iter() {
+        let mut console = console.lock();
+        console.set_font(font);
+        console.set_geometry(info);
+    }
+    FRAMEBUFFER_CONSOLES[console::active()]
+        .lock()
+        .show(framebuffer);
+}
+
+/// Detaches the framebuffer from the active console so something else can draw into it.
+/// Text written while detached is still recorded and shows up once the framebuffer is restored.
+pub fn take_framebuffer() -> Option<&'static mut FrameBuffer> {
+    FRAMEBUFFER_CONSOLES[console::active()]
+        .lock()
+        .take_framebuffer()
+}
+
+/// Switches every console to `font`, keeping the text already on them.
+pub fn set_font(font: Font) {
+    for console in FRAMEBUFFER_CONSOLES.iter() {
+        console.lock().set_font(font);
+    }
+}
+
+/// Hands a framebuffer taken with `take_framebuffer` back to the active console and redraws the text on it.
+pub fn restore_framebuffer(framebuffer: &'static mut FrameBuffer) {
+    FRAMEBUFFER_CONSOLES[console::active()]
+        .lock()
+        .show(framebuffer);
+}
+
+/// Glyph 0 of code page 437 is blank, so this renders the same as a space.
+const EMPTY_CELL: VGAChar = VGAChar {
+    char: 0,
+    color_code: ColorData::new(VGAColorCode::Black, VGAColorCode::Black, false),
+};
+
+pub struct FramebufferConsole {
+    framebuffer: Option<&'static mut FrameBuffer>,
+    /// Draws into the backbuffer while the console holds the framebuffer. What changed is copied
+    /// to the screen at the end of each write.
+    renderer: Option<Renderer<'static>>,
+    info: Option<FrameBufferInfo>,
+    font: Font,
+    pub col: usize,
+    pub row: usize,
+    cols: usize,
+    rows: usize,
+    foreground: VGAColorCode,
+    background: VGAColorCode,
+    scroll_top: usize,
+    scroll_bottom: usize,
+    ansi: AnsiParser,
+    tabs: TabStops,
+    /// Whether a word running past the right edge moves to the next line as a whole.
+    word_wrap: bool,
+    cells: [[VGAChar; MAX_COLS]; MAX_ROWS],
+    scrollback: Scrollback<VGAChar, MAX_COLS, SCROLLBACK_LINES>,
+    /// How many lines the view is scrolled back into history; 0 shows the live screen.
+    view_offset: usize,
+    /// Cell currently drawn in inverted colours as the cursor, if any.
+    cursor_drawn: Option<(usize, usize)>,
+}
+
+impl FramebufferConsole {
+    pub const fn new() -> FramebufferConsole {
+        FramebufferConsole {
+            framebuffer: None,
+            renderer: None,
+            info: None,
+            font: Font::vga(),
+            col: 0,
+            row: 0,
+            cols: 1,
+            rows: 1,
+            foreground: DEFAULT_FOREGROUND,
+            background: DEFAULT_BACKGROUND,
+            scroll_top: 0,
+            scroll_bottom: 0,
+            ansi: AnsiParser::new(),
+            tabs: TabStops::new(DEFAULT_TAB_WIDTH),
+            word_wrap: false,
+            cells: [[EMPTY_CELL; MAX_COLS]; MAX_ROWS],
+            scrollback: Scrollback::new(EMPTY_CELL),
+            view_offset: 0,
+            cursor_drawn: None,
+        }
+    }
+
+    /// Sizes the text grid to a framebuffer described by `info` and clears it. Text is drawn on
+    /// the backbuffer, so rows of a framebuffer too big for it are left unused.
+    pub fn set_geometry(&mut self, info: FrameBufferInfo) {
+        self.info = Some(clip_to_backbuffer(info));
+        self.resize();
+        self.scrollback.clear();
+        self.clear_screen();
+    }
+
+    /// Renders with `font` from now on. The grid is resized to match; if it loses rows, lines
+    /// from the top move into the scrollback so the cursor stays on screen.
+    pub fn set_font(&mut self, font: Font) {
+        self.font = font;
+        if self.info.is_none() {
+            return;
+        }
+
+        self.resize();
+        let background = palette_color(DEFAULT_BACKGROUND);
+        if let Some(renderer) = self.renderer() {
+            renderer.fill(background);
+        }
+        self.redraw();
+        self.update_cursor();
+        self.present();
+    }
+
+    /// Recomputes the grid size from the framebuffer and font.
+    fn resize(&mut self) {
+        let Some(info) = self.info else {
+            return;
+        };
+        self.cols = (info.width / self.font.width()).clamp(1, MAX_COLS);
+        self.rows = (info.height / self.font.height()).clamp(1, MAX_ROWS);
+        self.scroll_top = 0;
+        self.scroll_bottom = self.rows - 1;
+
+        let excess = (self.row + 1).saturating_sub(self.rows);
+        for line in 0..excess {
+            self.scrollback.push(&self.cells[line]);
+        }
+        self.cells.copy_within(excess.., 0);
+        self.cells[MAX_ROWS - excess..].fill([EMPTY_CELL; MAX_COLS]);
+        self.row -= excess;
+        self.col = self.col.min(self.cols);
+    }
+
+    /// Puts the console on `framebuffer` and redraws it from the stored cells.
+    pub fn show(&mut self, framebuffer: &'static mut FrameBuffer) {
+        // The backbuffer comes with the framebuffer, which is ours until `take_framebuffer`.
+        self.renderer = self.info.and_then(|info| {
+            let buffer = unsafe { backbuffer(info.byte_len) }?;
+            Some(Renderer::new(buffer, info))
+        });
+        self.framebuffer = Some(framebuffer);
+
+        let background = palette_color(DEFAULT_BACKGROUND);
+        if let Some(renderer) = self.renderer() {
+            renderer.fill(background);
+        }
+        self.redraw();
+        self.update_cursor();
+        self.present();
+    }
+
+    /// Stops drawing and gives the framebuffer back, if this console has it.
+    pub fn take_framebuffer(&mut self) -> Option<&'static mut FrameBuffer> {
+        self.cursor_drawn = None;
+        self.renderer = None;
+        self.framebuffer.take()
+    }
+
+    /// Copies what was drawn since the last call from the backbuffer to the screen.
+    pub fn present(&mut self) {
+        if let (Some(renderer), Some(framebuffer)) =
+            (self.renderer.as_mut(), self.framebuffer.as_mut())
+        {
+            renderer.present(framebuffer.buffer_mut());
+        }
+    }
+
+    /// Whether the console has been sized to a framebuffer and should receive `print!` output.
+    pub fn is_enabled(&self) -> bool {
+        self.info.is_some()
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn set_color(&mut self, foreground: VGAColorCode, background: VGAColorCode) {
+        self.foreground = foreground;
+        self.background = background;
+    }
+
+    /// Puts a tab stop every `width` columns, replacing any set by escape sequences.
+    pub fn set_tab_width(&mut self, width: usize) {
+        self.tabs.set_width(width);
+    }
+
+    /// Enables or disables word-aware wrapping at the right edge.
+    pub fn set_word_wrap(&mut self, word_wrap: bool) {
+        self.word_wrap = word_wrap;
+    }
+
+    fn renderer(&mut self) -> Option<&mut Renderer<'static>> {
+        self.renderer.as_mut()
+    }
+
+    fn attribute(&self) -> ColorData {
+        ColorData::new(self.foreground, self.background, false)
+    }
+
+    fn blank_cell(&self) -> VGAChar {
+        VGAChar {
+            char: b' ',
+            color_code: self.attribute(),
+        }
+    }
+
+    /// Draws `cell`. There is no blinking on the framebuffer, so bit 7 of the attribute always
+    /// selects a bright background.
+    fn draw_cell(&mut self, cell: VGAChar, col: usize, row: usize) {
+        let foreground = palette_color(cell.color_code.foreground());
+        let background = palette_color(cell.color_code.background());
+        let glyph = self.font.glyph(cell.char);
+        let (width, height) = (self.font.width(), self.font.height());
+        if let Some(renderer) = self.renderer.as_mut() {
+            let (x, y) = ((col * width) as i32, (row * height) as i32);
+            renderer.draw_bitmap(x, y, width, glyph, foreground, background);
+        }
+    }
+
+    /// Puts the code page 437 glyph `byte` into the cell at `col`, `row` in the current colours.
+    pub fn draw_glyph(&mut self, byte: u8, col: usize, row: usize) {
+        let cell = VGAChar {
+            char: byte,
+            color_code: self.attribute(),
+        };
+        self.put_cell(cell, col, row);
+    }
+
+    /// Stores `cell` at `col`, `row`, drawing it if the live screen is showing.
+    fn put_cell(&mut self, cell: VGAChar, col: usize, row: usize) {
+        if col >= self.cols || row >= self.rows {
+            return;
+        }
+
+        self.cells[row][col] = cell;
+        if self.view_offset == 0 {
+            self.draw_cell(cell, col, row);
+        }
+    }
+
+    /// Repaints the whole screen from the stored cells and scrollback.
+    pub fn redraw(&mut self) {
+        self.cursor_drawn = None;
+        let history = self.scrollback.len();
+        for row in 0..self.rows {
+            // Index into the scrollback followed by the live screen.
+            let line = history + row - self.view_offset;
+            for col in 0..self.cols {
+                let cell = match self.scrollback.get(line) {
+                    Some(cells) => cells[col],
+                    None => self.cells[line - history][col],
+                };
+                self.draw_cell(cell, col, row);
+            }
+        }
+    }
+
+    /// Scrolls the view `lines` further back into the scrollback history.
+    pub fn scroll_view_up(&mut self, lines: usize) {
+        self.set_view_offset((self.view_offset + lines).min(self.scrollback.len()));
+    }
+
+    /// Scrolls the view `lines` back towards the live screen.
+    pub fn scroll_view_down(&mut self, lines: usize) {
+        self.set_view_offset(self.view_offset.saturating_sub(lines));
+    }
+
+    /// Returns the view to the live screen.
+    pub fn scroll_to_bottom(&mut self) {
+        self.set_view_offset(0);
+    }
+
+    fn set_view_offset(&mut self, offset: usize) {
+        if offset != self.view_offset {
+            self.view_offset = offset;
+            self.redraw();
+            self.update_cursor();
+            self.present();
+        }
+    }
+
+    /// Draws the cell under the cursor with its colours swapped, so line editing is visible.
+    /// Nothing is drawn while the view is scrolled back.
+    pub fn update_cursor(&mut self) {
+        self.hide_cursor();
+        if self.view_offset > 0 || self.framebuffer.is_none() {
+            return;
+        }
+
+        let (col, row) = AnsiScreen::cursor(self);
+        let cell = self.cells[row][col];
+        let inverted = VGAChar {
+            char: cell.char,
+            color_code: ColorData::new(
+                cell.color_code.background(),
+                cell.color_code.foreground(),
+                false,
+            ),
+        };
+        self.draw_cell(inverted, col, row);
+        self.cursor_drawn = Some((col, row));
+    }
+
+    /// Puts the cell under the drawn cursor back to its normal colours.
+    fn hide_cursor(&mut self) {
+        if let Some((col, row)) = self.cursor_drawn.take() {
+            if self.view_offset == 0 && col < self.cols && row < self.rows {
+                self.draw_cell(self.cells[row][col], col, row);
+            }
+        }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        if self.col >= self.cols {
+            self.wrap_line();
+        }
+
+        self.draw_glyph(byte, self.col, self.row);
+
+        self.col += 1;
+    }
+
+    /// Writes `s`, interpreting ANSI escape sequences along the way. Characters outside ASCII are
+    /// shown as their code page 437 glyph.
+    pub fn write_str(&mut self, s: &str) {
+        self.hide_cursor();
+        self.scroll_to_bottom();
+
+        let mut ansi = self.ansi;
+        for c in s.chars() {
+            ansi.feed_char(self, c);
+        }
+        self.ansi = ansi;
+        self.update_cursor();
+        self.present();
+    }
+
+    /// Continues on the next line once the cursor has run off the right edge. With word wrap on,
+    /// the partial word at the end of the line is carried over instead of being split.
+    fn wrap_line(&mut self) {
+        let mut word = [EMPTY_CELL; MAX_COLS];
+        let mut len = 0;
+        if self.word_wrap {
+            let line = &self.cells[self.row][..self.cols];
+            // A line without spaces is one long word, which has to be split anyway.
+            if let Some(space) = line.iter().rposition(|cell| cell.char == b' ') {
+                len = self.cols - space - 1;
+                word[..len].copy_from_slice(&line[space + 1..]);
+                AnsiScreen::erase(self, self.row, space + 1, self.cols);
+            }
+        }
+
+        self.new_line();
+        for (col, &cell) in word[..len].iter().enumerate() {
+            self.put_cell(cell, col, self.row);
+        }
+        self.col = len;
+    }
+
+    pub fn new_line(&mut self) {
+        self.col = 0;
+        if self.row == self.scroll_bottom {
+            if self.scroll_top == 0 {
+                self.scrollback.push(&self.cells[0]);
+            }
+            self.scroll_up(1);
+        } else if self.row < self.rows - 1 {
+            self.row += 1;
+        }
+    }
+
+    /// Pixel rows covered by the scroll region.
+    fn scroll_band(&self) -> (i32, i32) {
+        (
+            (self.scroll_top * self.font.height()) as i32,
+            ((self.scroll_bottom + 1) * self.font.height()) as i32,
+        )
+    }
+
+    pub fn clear_screen(&mut self) {
+        let blank = self.blank_cell();
+        for row in self.cells.iter_mut() {
+            row.fill(blank);
+        }
+        self.view_offset = 0;
+        self.redraw();
+
+        self.col = 0;
+        self.row = 0;
+        self.update_cursor();
+        self.present();
+    }
+}
+
+impl Default for FramebufferConsole {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnsiScreen for FramebufferConsole {
+    fn size(&self) -> (usize, usize) {
+        (self.cols, self.rows)
+    }
+
+    fn cursor(&self) -> (usize, usize) {
+        (self.col.min(self.cols - 1), self.row)
+    }
+
+    fn set_cursor(&mut self, col: usize, row: usize) {
+        self.col = col;
+        self.row = row;
+    }
+
+    fn default_colors(&self) -> (VGAColorCode, VGAColorCode) {
+        (DEFAULT_FOREGROUND, DEFAULT_BACKGROUND)
+    }
+
+    fn set_colors(&mut self, foreground: VGAColorCode, background: VGAColorCode) {
+        self.set_color(foreground, background);
+    }
+
+    fn set_blink(&mut self, _blink: bool) {}
+
+    fn erase(&mut self, row: usize, start_col: usize, end_col: usize) {
+        let blank = self.blank_cell();
+        let end_col = end_col.min(self.cols);
+        self.cells[row][start_col.min(end_col)..end_col].fill(blank);
+
+        let background = palette_color(self.background);
+        let (width, height) = (self.font.width(), self.font.height());
+        if let Some(renderer) = self.renderer() {
+            renderer.fill_block(
+                (start_col * width) as i32,
+                (row * height) as i32,
+                (end_col.saturating_sub(start_col) * width) as i32,
+                height as i32,
+                background,
+            );
+        }
+    }
+
+    fn set_scroll_region(&mut self, top: usize, bottom: usize) {
+        self.scroll_top = top;
+        self.scroll_bottom = bottom;
+    }
+
+    fn scroll_up(&mut self, lines: usize) {
+        let lines = lines.min(self.scroll_bottom + 1 - self.scroll_top);
+        let blank = self.blank_cell();
+        self.cells.copy_within(
+            self.scroll_top + lines..self.scroll_bottom + 1,
+            self.scroll_top,
+        );
+        for row in &mut self.cells[self.scroll_bottom + 1 - lines..=self.scroll_bottom] {
+            row.fill(blank);
+        }
+
+        let background = palette_color(self.background);
+        let (y0, y1) = self.scroll_band();
+        let height = self.font.height();
+        if let Some(renderer) = self.renderer() {
+            renderer.scroll_up(y0, y1, (lines * height) as i32, background);
+        }
+    }
+
+    fn scroll_down(&mut self, lines: usize) {
+        let lines = lines.min(self.scroll_bottom + 1 - self.scroll_top);
+        let blank = self.blank_cell();
+        self.cells.copy_within(
+            self.scroll_top..self.scroll_bottom + 1 - lines,
+            self.scroll_top + lines,
+        );
+        for row in &mut self.cells[self.scroll_top..self.scroll_top + lines] {
+            row.fill(blank);
+        }
+
+        let background = palette_color(self.background);
+        let (y0, y1) = self.scroll_band();
+        let height = self.font.height();
+        if let Some(renderer) = self.renderer() {
+            renderer.scroll_down(y0, y1, (lines * height) as i32, background);
+        }
+    }
+
+    fn tab_stops(&mut self) -> &mut TabStops {
+        &mut self.tabs
+    }
+
+    fn print(&mut self, byte: u8) {
+        self.write_byte(byte);
+    }
+
+    fn control(&mut self, byte: u8) {
+        match byte {
+            // ASCII newline
+            0x0a => {
+                self.new_line();
+            }
+            // carriage return
+            0x0d => {
+                self.col = 0;
+            }
+            // backspace, the same as on the VGA console: blank the cell, then step back
+            0x08 => {
+                self.draw_glyph(b' ', self.col, self.row);
+                if self.col > 0 {
+                    self.col -= 1;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl TextCanvas for FramebufferConsole {
+    fn size(&self) -> (usize, usize) {
+        (self.cols, self.rows)
+    }
+
+    fn put(&mut self, byte: u8, color: ColorData, col: usize, row: usize) {
+        let cell = VGAChar {
+            char: byte,
+            color_code: color,
+        };
+        self.put_cell(cell, col, row);
+    }
+}
+
+impl Write for FramebufferConsole {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_str(s);
+        Ok(())
+    }
+}
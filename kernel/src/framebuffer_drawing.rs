@@ -0,0 +1,291 @@
+use libm::{roundf, sqrtf};
+
+use crate::framebuffer::{ClipRect, Color, Renderer};
+
+/// Most corners `fill_polygon` looks at; any further points are ignored.
+pub const MAX_POLYGON_POINTS: usize = 64;
+
+/// Drawing primitives. Everything is clipped to the renderer's clip rectangle.
+impl Renderer<'_> {
+    /// Pixels `x0..=x1` of row `y`.
+    pub fn draw_hline(&mut self, x0: i32, x1: i32, y: i32, color: Color) {
+        let clip = self.clip();
+        if y < clip.y || y >= clip.y + clip.h {
+            return;
+        }
+        let (x0, x1) = if x0 <= x1 { (x0, x1) } else { (x1, x0) };
+        let (x0, x1) = (x0.max(clip.x), x1.min(clip.x + clip.w - 1));
+        self.mark_dirty(ClipRect::new(x0, y, x1 - x0 + 1, 1));
+        for x in x0..=x1 {
+            self.write_pixel(x, y, color);
+        }
+    }
+
+    /// Pixels `y0..=y1` of column `x`.
+    pub fn draw_vline(&mut self, x: i32, y0: i32, y1: i32, color: Color) {
+        let clip = self.clip();
+        if x < clip.x || x >= clip.x + clip.w {
+            return;
+        }
+        let (y0, y1) = if y0 <= y1 { (y0, y1) } else { (y1, y0) };
+        let (y0, y1) = (y0.max(clip.y), y1.min(clip.y + clip.h - 1));
+        self.mark_dirty(ClipRect::new(x, y0, 1, y1 - y0 + 1));
+        for y in y0..=y1 {
+            self.write_pixel(x, y, color);
+        }
+    }
+
+    /// A bitmap of one bit per pixel, `width` pixels wide, with its top left corner at `x`, `y`.
+    /// Rows are padded to whole bytes and the most significant bit is leftmost. Set bits are
+    /// drawn in `foreground` and clear ones in `background`.
+    pub fn draw_bitmap(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: usize,
+        bits: &[u8],
+        foreground: Color,
+        background: Color,
+    ) {
+        let bytes_per_row = width.div_ceil(8);
+        if bytes_per_row == 0 {
+            return;
+        }
+        let height = bits.len() / bytes_per_row;
+        let area = ClipRect::new(x, y, width as i32, height as i32).intersect(self.clip());
+        self.mark_dirty(area);
+        for (dy, row) in bits.chunks_exact(bytes_per_row).enumerate() {
+            for dx in 0..width {
+                let color = if row[dx / 8] & (0x80 >> (dx % 8)) != 0 {
+                    foreground
+                } else {
+                    background
+                };
+                self.write_pixel(x + dx as i32, y + dy as i32, color);
+            }
+        }
+    }
+
+    /// Bresenham line from `x0`, `y0` to `x1`, `y1`, both ends included.
+    pub fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: Color) {
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let sx = if x0 < x1 { 1 } else { -1 };
+        let sy = if y0 < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
+        let (mut x, mut y) = (x0, y0);
+
+        loop {
+            self.set_pixel(x, y, color);
+            if x == x1 && y == y1 {
+                return;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    /// A line `width` pixels wide with round ends, so joined segments leave no gaps.
+    pub fn draw_thick_line(
+        &mut self,
+        x0: i32,
+        y0: i32,
+        x1: i32,
+        y1: i32,
+        width: i32,
+        color: Color,
+    ) {
+        if width <= 1 {
+            self.draw_line(x0, y0, x1, y1, color);
+            return;
+        }
+
+        let radius = width / 2;
+        self.fill_circle(x0, y0, radius, color);
+        self.fill_circle(x1, y1, radius, color);
+
+        let dx = (x1 - x0) as f32;
+        let dy = (y1 - y0) as f32;
+        let len = sqrtf(dx * dx + dy * dy);
+        if len == 0.0 {
+            return;
+        }
+        // Half the width along the line's normal.
+        let nx = roundf(-dy / len * width as f32 / 2.0) as i32;
+        let ny = roundf(dx / len * width as f32 / 2.0) as i32;
+        self.fill_polygon(
+            &[
+                (x0 + nx, y0 + ny),
+                (x1 + nx, y1 + ny),
+                (x1 - nx, y1 - ny),
+                (x0 - nx, y0 - ny),
+            ],
+            color,
+        );
+    }
+
+    /// Outline of the `w` by `h` rectangle with its top left corner at `x`, `y`.
+    pub fn draw_rect(&mut self, x: i32, y: i32, w: i32, h: i32, color: Color) {
+        if w <= 0 || h <= 0 {
+            return;
+        }
+        let (x1, y1) = (x + w - 1, y + h - 1);
+        self.draw_hline(x, x1, y, color);
+        self.draw_hline(x, x1, y1, color);
+        if h > 2 {
+            self.draw_vline(x, y + 1, y1 - 1, color);
+            self.draw_vline(x1, y + 1, y1 - 1, color);
+        }
+    }
+
+    /// The `w` by `h` rectangle with its top left corner at `x`, `y`.
+    pub fn fill_rect(&mut self, x: i32, y: i32, w: i32, h: i32, color: Color) {
+        let area = ClipRect::new(x, y, w, h).intersect(self.clip());
+        if area.is_empty() {
+            return;
+        }
+        for row in area.y..area.y + area.h {
+            self.draw_hline(area.x, area.x + area.w - 1, row, color);
+        }
+    }
+
+    pub fn draw_circle(&mut self, cx: i32, cy: i32, r: i32, color: Color) {
+        self.draw_ellipse(cx, cy, r, r, color);
+    }
+
+    pub fn fill_circle(&mut self, cx: i32, cy: i32, r: i32, color: Color) {
+        self.fill_ellipse(cx, cy, r, r, color);
+    }
+
+    /// Midpoint ellipse outline with radii `rx` and `ry` around `cx`, `cy`.
+    pub fn draw_ellipse(&mut self, cx: i32, cy: i32, rx: i32, ry: i32, color: Color) {
+        if rx < 0 || ry < 0 {
+            return;
+        }
+        if rx == 0 || ry == 0 {
+            self.draw_line(cx - rx, cy - ry, cx + rx, cy + ry, color);
+            return;
+        }
+
+        let plot = |renderer: &mut Self, x: i64, y: i64| {
+            let (x, y) = (x as i32, y as i32);
+            renderer.set_pixel(cx + x, cy + y, color);
+            renderer.set_pixel(cx - x, cy + y, color);
+            renderer.set_pixel(cx + x, cy - y, color);
+            renderer.set_pixel(cx - x, cy - y, color);
+        };
+
+        let rx2 = i64::from(rx) * i64::from(rx);
+        let ry2 = i64::from(ry) * i64::from(ry);
+        let mut x = 0i64;
+        let mut y = i64::from(ry);
+        let mut px = 0;
+        let mut py = 2 * rx2 * y;
+
+        // Upper region, where the slope is shallower than -1 and x steps every pixel.
+        let mut p = ry2 - rx2 * y + rx2 / 4;
+        while px < py {
+            plot(self, x, y);
+            x += 1;
+            px += 2 * ry2;
+            if p < 0 {
+                p += ry2 + px;
+            } else {
+                y -= 1;
+                py -= 2 * rx2;
+                p += ry2 + px - py;
+            }
+        }
+
+        // Lower region, where y steps every pixel.
+        p = (ry2 * (2 * x + 1) * (2 * x + 1)) / 4 + rx2 * (y - 1) * (y - 1) - rx2 * ry2;
+        while y >= 0 {
+            plot(self, x, y);
+            y -= 1;
+            py -= 2 * rx2;
+            if p > 0 {
+                p += rx2 - py;
+            } else {
+                x += 1;
+                px += 2 * ry2;
+                p += rx2 - py + px;
+            }
+        }
+    }
+
+    /// Filled ellipse with radii `rx` and `ry` around `cx`, `cy`, one span per row.
+    pub fn fill_ellipse(&mut self, cx: i32, cy: i32, rx: i32, ry: i32, color: Color) {
+        if rx < 0 || ry < 0 {
+            return;
+        }
+        if ry == 0 {
+            self.draw_hline(cx - rx, cx + rx, cy, color);
+            return;
+        }
+
+        for dy in -ry..=ry {
+            let t = dy as f32 / ry as f32;
+            let half = (rx as f32 * sqrtf(1.0 - t * t) + 0.5) as i32;
+            self.draw_hline(cx - half, cx + half, cy + dy, color);
+        }
+    }
+
+    /// Outline through `points`, closed back to the first one.
+    pub fn draw_polygon(&mut self, points: &[(i32, i32)], color: Color) {
+        for (i, &(x0, y0)) in points.iter().enumerate() {
+            let (x1, y1) = points[(i + 1) % points.len()];
+            self.draw_line(x0, y0, x1, y1, color);
+        }
+    }
+
+    /// Scanline fill of the polygon through `points` with the even-odd rule, so it works for
+    /// concave and self-intersecting shapes too. Each row is sampled through its centre.
+    pub fn fill_polygon(&mut self, points: &[(i32, i32)], color: Color) {
+        let points = &points[..points.len().min(MAX_POLYGON_POINTS)];
+        if points.len() < 3 {
+            return;
+        }
+
+        let clip = self.clip();
+        let top = points.iter().map(|&(_, y)| y).min().unwrap_or(0);
+        let bottom = points.iter().map(|&(_, y)| y).max().unwrap_or(0);
+        let mut crossings = [0i32; MAX_POLYGON_POINTS];
+
+        for y in top.max(clip.y)..bottom.min(clip.y + clip.h) {
+            let mut count = 0;
+            for (i, &(xa, ya)) in points.iter().enumerate() {
+                let (xb, yb) = points[(i + 1) % points.len()];
+                let ((xa, ya), (xb, yb)) = if ya <= yb {
+                    ((xa, ya), (xb, yb))
+                } else {
+                    ((xb, yb), (xa, ya))
+                };
+                // Half-open in y, so a vertex shared by two edges is only counted once.
+                if y < ya || y >= yb {
+                    continue;
+                }
+                // Where the edge crosses the middle of the row, y + 0.5.
+                let (xa, xb) = (i64::from(xa), i64::from(xb));
+                let num = (xb - xa) * (2 * i64::from(y - ya) + 1);
+                let den = 2 * i64::from(yb - ya);
+                crossings[count] = (xa + num.div_euclid(den)) as i32;
+                count += 1;
+            }
+
+            let crossings = &mut crossings[..count];
+            crossings.sort_unstable();
+            for &[start, end] in crossings.as_chunks::<2>().0 {
+                if end > start {
+                    self.draw_hline(start, end - 1, y, color);
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use lazy_static::lazy_static;
+use spin::Once;
 use x86_64::instructions::tables::load_tss;
 use x86_64::registers::segmentation::{CS, DS, ES, SS, Segment};
 use x86_64::structures::gdt::SegmentSelector;
@@ -6,38 +7,51 @@ use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable};
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
 
+use crate::smp::MAX_CPUS;
+
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
+const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
+
 lazy_static! {
     static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-            let stack_end = stack_start + STACK_SIZE as u64;
-            stack_end
-        };
-        tss
+        static mut STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
+        new_tss(VirtAddr::from_ptr(&raw const STACK))
     };
 }
 
 lazy_static! {
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.append(Descriptor::kernel_code_segment());
-        let data_selector = gdt.append(Descriptor::kernel_data_segment());
-        let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
-        (
-            gdt,
-            Selectors {
-                code_selector,
-                data_selector,
-                tss_selector,
-            },
-        )
-    };
+    static ref GDT: (GlobalDescriptorTable, Selectors) = new_gdt(&TSS);
+}
+
+/// The application processors' TSSs and GDTs, indexed by CPU number minus one. Loading a TSS
+/// marks it busy, so every CPU needs one of its own, and with it its own double fault stack.
+static AP_TSS: [Once<TaskStateSegment>; MAX_CPUS - 1] = [const { Once::new() }; MAX_CPUS - 1];
+static AP_GDT: [Once<(GlobalDescriptorTable, Selectors)>; MAX_CPUS - 1] =
+    [const { Once::new() }; MAX_CPUS - 1];
+static mut AP_DOUBLE_FAULT_STACKS: [[u8; DOUBLE_FAULT_STACK_SIZE]; MAX_CPUS - 1] =
+    [[0; DOUBLE_FAULT_STACK_SIZE]; MAX_CPUS - 1];
+
+fn new_tss(double_fault_stack: VirtAddr) -> TaskStateSegment {
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+        double_fault_stack + DOUBLE_FAULT_STACK_SIZE as u64;
+    tss
+}
+
+fn new_gdt(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+    let mut gdt = GlobalDescriptorTable::new();
+    let code_selector = gdt.append(Descriptor::kernel_code_segment());
+    let data_selector = gdt.append(Descriptor::kernel_data_segment());
+    let tss_selector = gdt.append(Descriptor::tss_segment(tss));
+    (
+        gdt,
+        Selectors {
+            code_selector,
+            data_selector,
+            tss_selector,
+        },
+    )
 }
 
 struct Selectors {
@@ -47,12 +61,27 @@ struct Selectors {
 }
 
 pub fn init() {
-    GDT.0.load();
+    load(&GDT);
+}
+
+/// Loads a GDT and TSS of its own on application processor `cpu`, counting from 1.
+pub fn init_ap(cpu: usize) {
+    let index = cpu - 1;
+    let tss = AP_TSS[index].call_once(|| {
+        new_tss(VirtAddr::from_ptr(unsafe {
+            &raw const AP_DOUBLE_FAULT_STACKS[index]
+        }))
+    });
+    load(AP_GDT[index].call_once(|| new_gdt(tss)));
+}
+
+fn load((gdt, selectors): &'static (GlobalDescriptorTable, Selectors)) {
+    gdt.load();
     unsafe {
-        CS::set_reg(GDT.1.code_selector);
-        DS::set_reg(GDT.1.data_selector);
-        ES::set_reg(GDT.1.data_selector);
-        SS::set_reg(GDT.1.data_selector);
-        load_tss(GDT.1.tss_selector);
+        CS::set_reg(selectors.code_selector);
+        DS::set_reg(selectors.data_selector);
+        ES::set_reg(selectors.data_selector);
+        SS::set_reg(selectors.data_selector);
+        load_tss(selectors.tss_selector);
     }
 }
@@ -0,0 +1,814 @@
+use crate::framebuffer::Rgba;
+use crate::sprite::Image;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageError {
+    /// Not one of the formats `decode` knows.
+    UnknownFormat,
+    /// The data ends before the header or pixels it promises.
+    Truncated,
+    /// A header field that makes no sense, such as a zero width.
+    BadHeader,
+    /// A valid file using a feature the decoders leave out, e.g. a palette or 16-bit pixels.
+    Unsupported,
+    /// The output buffer holds fewer pixels than the image has.
+    BufferTooSmall,
+}
+
+/// Largest width or height accepted, to keep `width * height` sane.
+pub const MAX_DIMENSION: usize = 16384;
+
+const QOI_MAGIC: &[u8; 4] = b"qoif";
+const QOI_HEADER_SIZE: usize = 14;
+const QOI_OP_INDEX: u8 = 0x00;
+const QOI_OP_DIFF: u8 = 0x40;
+const QOI_OP_LUMA: u8 = 0x80;
+const QOI_OP_RGB: u8 = 0xFE;
+const QOI_OP_RGBA: u8 = 0xFF;
+const QOI_MASK_2: u8 = 0xC0;
+
+const BMP_MAGIC: &[u8; 2] = b"BM";
+const BMP_INFO_HEADER: usize = 14;
+const BMP_RGB: u32 = 0;
+const BMP_BITFIELDS: u32 = 3;
+const BMP_ALPHABITFIELDS: u32 = 6;
+/// Size of the BITMAPV4HEADER, the first info header with an alpha mask.
+const BMP_V4_HEADER_SIZE: usize = 108;
+
+const TGA_HEADER_SIZE: usize = 18;
+const TGA_TRUECOLOR: u8 = 2;
+const TGA_GRAYSCALE: u8 = 3;
+const TGA_RLE_TRUECOLOR: u8 = 10;
+const TGA_RLE_GRAYSCALE: u8 = 11;
+const TGA_RIGHT_TO_LEFT: u8 = 0x10;
+const TGA_TOP_TO_BOTTOM: u8 = 0x20;
+
+/// Width and height of an image in any supported format, to size the buffer for `decode`.
+pub fn dimensions(data: &[u8]) -> Result<(usize, usize), ImageError> {
+    match Format::detect(data)? {
+        Format::Bmp => bmp_header(data).map(|header| (header.width, header.height)),
+        Format::Tga => tga_header(data).map(|header| (header.width, header.height)),
+        Format::Ppm => ppm_header(data).map(|header| (header.width, header.height)),
+        Format::Qoi => qoi_header(data),
+    }
+}
+
+/// Decodes a BMP, TGA, binary PPM or QOI file into `out`, telling the format from its contents.
+pub fn decode<'a>(data: &[u8], out: &'a mut [Rgba]) -> Result<Image<'a>, ImageError> {
+    match Format::detect(data)? {
+        Format::Bmp => decode_bmp(data, out),
+        Format::Tga => decode_tga(data, out),
+        Format::Ppm => decode_ppm(data, out),
+        Format::Qoi => decode_qoi(data, out),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Bmp,
+    Tga,
+    Ppm,
+    Qoi,
+}
+
+impl Format {
+    fn detect(data: &[u8]) -> Result<Format, ImageError> {
+        if data.starts_with(QOI_MAGIC) {
+            Ok(Format::Qoi)
+        } else if data.starts_with(BMP_MAGIC) {
+            Ok(Format::Bmp)
+        } else if data.starts_with(b"P6") {
+            Ok(Format::Ppm)
+        } else if matches!(tga_header(data), Ok(_) | Err(ImageError::Unsupported)) {
+            // TGA has no magic number, so it is whatever has a plausible TGA header.
+            Ok(Format::Tga)
+        } else {
+            Err(ImageError::UnknownFormat)
+        }
+    }
+}
+
+fn bytes<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], ImageError> {
+    data.get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(ImageError::Truncated)
+}
+
+fn u16_le(data: &[u8], offset: usize) -> Result<u16, ImageError> {
+    bytes(data, offset).map(u16::from_le_bytes)
+}
+
+fn u32_le(data: &[u8], offset: usize) -> Result<u32, ImageError> {
+    bytes(data, offset).map(u32::from_le_bytes)
+}
+
+fn u32_be(data: &[u8], offset: usize) -> Result<u32, ImageError> {
+    bytes(data, offset).map(u32::from_be_bytes)
+}
+
+/// The first `width * height` pixels of `out`, after checking the size is sensible.
+fn output(out: &mut [Rgba], width: usize, height: usize) -> Result<&mut [Rgba], ImageError> {
+    if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(ImageError::BadHeader);
+    }
+    out.get_mut(..width * height)
+        .ok_or(ImageError::BufferTooSmall)
+}
+
+fn finish(out: &[Rgba], width: usize, height: usize) -> Result<Image<'_>, ImageError> {
+    Image::new(width, height, out).ok_or(ImageError::BufferTooSmall)
+}
+
+struct BmpHeader {
+    width: usize,
+    height: usize,
+    /// Rows are stored bottom to top unless the height in the file is negative.
+    bottom_up: bool,
+    bits_per_pixel: usize,
+    pixels_offset: usize,
+    /// Red, green, blue and alpha masks; an alpha mask of 0 means the image is opaque.
+    masks: [u32; 4],
+}
+
+fn bmp_header(data: &[u8]) -> Result<BmpHeader, ImageError> {
+    if !data.starts_with(BMP_MAGIC) {
+        return Err(ImageError::UnknownFormat);
+    }
+    let pixels_offset = u32_le(data, 10)? as usize;
+    let header_size = u32_le(data, BMP_INFO_HEADER)? as usize;
+    if header_size < 40 {
+        // The old OS/2 core header.
+        return Err(ImageError::Unsupported);
+    }
+    let width = i32::from_le_bytes(bytes(data, 18)?);
+    let height = i32::from_le_bytes(bytes(data, 22)?);
+    let bits_per_pixel = u16_le(data, 28)? as usize;
+    let compression = u32_le(data, 30)?;
+    if width <= 0 || height == 0 {
+        return Err(ImageError::BadHeader);
+    }
+
+    let masks = match (bits_per_pixel, compression) {
+        (24, BMP_RGB) => [0xFF_0000, 0x00_FF00, 0x00_00FF, 0],
+        // Plain 32-bit BMPs officially have no alpha, but plenty of writers put it in the top byte.
+        (32, BMP_RGB) => [0xFF_0000, 0x00_FF00, 0x00_00FF, 0xFF00_0000],
+        (32, BMP_BITFIELDS | BMP_ALPHABITFIELDS) => {
+            // The masks follow a 40-byte header and are part of the larger ones.
+            let mask = |i: usize| u32_le(data, BMP_INFO_HEADER + 40 + 4 * i);
+            let has_alpha = compression == BMP_ALPHABITFIELDS || header_size >= BMP_V4_HEADER_SIZE;
+            [
+                mask(0)?,
+                mask(1)?,
+                mask(2)?,
+                if has_alpha { mask(3)? } else { 0 },
+            ]
+        }
+        _ => return Err(ImageError::Unsupported),
+    };
+
+    Ok(BmpHeader {
+        width: width as usize,
+        height: height.unsigned_abs() as usize,
+        bottom_up: height > 0,
+        bits_per_pixel,
+        pixels_offset,
+        masks,
+    })
+}
+
+/// The bits of `value` selected by `mask`, scaled to 0..=255.
+fn bmp_channel(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let max = mask >> mask.trailing_zeros();
+    let channel = (value & mask) >> mask.trailing_zeros();
+    ((u64::from(channel) * 255 + u64::from(max) / 2) / u64::from(max)) as u8
+}
+
+/// Decodes an uncompressed 24-bit, or uncompressed or bitfield 32-bit, BMP.
+pub fn decode_bmp<'a>(data: &[u8], out: &'a mut [Rgba]) -> Result<Image<'a>, ImageError> {
+    let header = bmp_header(data)?;
+    let (width, height) = (header.width, header.height);
+    let pixels = output(out, width, height)?;
+    let bytes_per_pixel = header.bits_per_pixel / 8;
+    // Rows are padded to a multiple of four bytes.
+    let stride = (width * bytes_per_pixel).next_multiple_of(4);
+    let [r, g, b, a] = header.masks;
+
+    let mut any_alpha = false;
+    for y in 0..height {
+        let file_row = if header.bottom_up { height - 1 - y } else { y };
+        let start = header.pixels_offset + file_row * stride;
+        let row = data
+            .get(start..start + width * bytes_per_pixel)
+            .ok_or(ImageError::Truncated)?;
+        for (x, px) in row.chunks_exact(bytes_per_pixel).enumerate() {
+            let mut value = [0; 4];
+            value[..bytes_per_pixel].copy_from_slice(px);
+            let value = u32::from_le_bytes(value);
+            let alpha = if a == 0 { 255 } else { bmp_channel(value, a) };
+            any_alpha |= alpha != 0;
+            pixels[y * width + x] = Rgba::new(
+                bmp_channel(value, r),
+                bmp_channel(value, g),
+                bmp_channel(value, b),
+                alpha,
+            );
+        }
+    }
+
+    // An alpha channel that is zero throughout is really an unused byte.
+    if !any_alpha {
+        for pixel in pixels.iter_mut() {
+            pixel.a = 255;
+        }
+    }
+    finish(out, width, height)
+}
+
+struct TgaHeader {
+    width: usize,
+    height: usize,
+    image_type: u8,
+    bytes_per_pixel: usize,
+    descriptor: u8,
+    pixels_offset: usize,
+}
+
+fn tga_header(data: &[u8]) -> Result<TgaHeader, ImageError> {
+    let header: [u8; TGA_HEADER_SIZE] = bytes(data, 0)?;
+    let [id_length, color_map_type, image_type, ..] = header;
+    let width = u16::from_le_bytes([header[12], header[13]]) as usize;
+    let height = u16::from_le_bytes([header[14], header[15]]) as usize;
+    let bits_per_pixel = header[16];
+    let descriptor = header[17];
+
+    if color_map_type > 1 || width == 0 || height == 0 {
+        return Err(ImageError::UnknownFormat);
+    }
+    let valid_depth = match image_type {
+        TGA_TRUECOLOR | TGA_RLE_TRUECOLOR => matches!(bits_per_pixel, 24 | 32),
+        TGA_GRAYSCALE | TGA_RLE_GRAYSCALE => bits_per_pixel == 8,
+        // Colour-mapped images are valid TGA but not supported.
+        1 | 9 => return Err(ImageError::Unsupported),
+        _ => return Err(ImageError::UnknownFormat),
+    };
+    if !valid_depth {
+        return Err(ImageError::Unsupported);
+    }
+    if color_map_type == 1 {
+        return Err(ImageError::Unsupported);
+    }
+
+    Ok(TgaHeader {
+        width,
+        height,
+        image_type,
+        bytes_per_pixel: bits_per_pixel as usize / 8,
+        descriptor,
+        pixels_offset: TGA_HEADER_SIZE + id_length as usize,
+    })
+}
+
+/// Decodes an uncompressed or run-length encoded true-colour (24/32-bit) or greyscale TGA.
+pub fn decode_tga<'a>(data: &[u8], out: &'a mut [Rgba]) -> Result<Image<'a>, ImageError> {
+    let header = tga_header(data)?;
+    let (width, height) = (header.width, header.height);
+    let pixels = output(out, width, height)?;
+    let bpp = header.bytes_per_pixel;
+    let rle = matches!(header.image_type, TGA_RLE_TRUECOLOR | TGA_RLE_GRAYSCALE);
+
+    let to_rgba = |px: &[u8]| match px {
+        [b, g, r, a] => Rgba::new(*r, *g, *b, *a),
+        [b, g, r] => Rgba::new(*r, *g, *b, 255),
+        [gray] => Rgba::new(*gray, *gray, *gray, 255),
+        _ => Rgba::TRANSPARENT,
+    };
+
+    // Pixels are stored in file order first and put the right way round afterwards.
+    let mut offset = header.pixels_offset;
+    let mut index = 0;
+    while index < width * height {
+        let (count, repeat) = if rle {
+            let packet = *data.get(offset).ok_or(ImageError::Truncated)?;
+            offset += 1;
+            (usize::from(packet & 0x7F) + 1, packet & 0x80 != 0)
+        } else {
+            (width * height, false)
+        };
+        let count = count.min(width * height - index);
+
+        if repeat {
+            let px = data
+                .get(offset..offset + bpp)
+                .ok_or(ImageError::Truncated)?;
+            offset += bpp;
+            pixels[index..index + count].fill(to_rgba(px));
+        } else {
+            let run = data
+                .get(offset..offset + count * bpp)
+                .ok_or(ImageError::Truncated)?;
+            offset += count * bpp;
+            for (pixel, px) in pixels[index..index + count]
+                .iter_mut()
+                .zip(run.chunks_exact(bpp))
+            {
+                *pixel = to_rgba(px);
+            }
+        }
+        index += count;
+    }
+
+    if header.descriptor & TGA_TOP_TO_BOTTOM == 0 {
+        for y in 0..height / 2 {
+            let (top, bottom) = pixels.split_at_mut((height - 1 - y) * width);
+            top[y * width..(y + 1) * width].swap_with_slice(&mut bottom[..width]);
+        }
+    }
+    if header.descriptor & TGA_RIGHT_TO_LEFT != 0 {
+        for row in pixels.chunks_exact_mut(width) {
+            row.reverse();
+        }
+    }
+    finish(out, width, height)
+}
+
+struct PpmHeader {
+    width: usize,
+    height: usize,
+    max_value: usize,
+    pixels_offset: usize,
+}
+
+fn ppm_header(data: &[u8]) -> Result<PpmHeader, ImageError> {
+    if !data.starts_with(b"P6") {
+        return Err(ImageError::UnknownFormat);
+    }
+
+    let mut offset = 2;
+    let mut fields = [0usize; 3];
+    for field in fields.iter_mut() {
+        // Whitespace and comments, which run from `#` to the end of the line.
+        loop {
+            match data.get(offset) {
+                Some(b' ' | b'\t' | b'\n' | b'\r' | 0x0B | 0x0C) => offset += 1,
+                Some(b'#') => {
+                    while !matches!(data.get(offset), Some(b'\n' | b'\r') | None) {
+                        offset += 1;
+                    }
+                }
+                Some(b'0'..=b'9') => break,
+                Some(_) => return Err(ImageError::BadHeader),
+                None => return Err(ImageError::Truncated),
+            }
+        }
+        while let Some(&digit @ b'0'..=b'9') = data.get(offset) {
+            *field = (*field)
+                .checked_mul(10)
+                .and_then(|value| value.checked_add(usize::from(digit - b'0')))
+                .ok_or(ImageError::BadHeader)?;
+            offset += 1;
+        }
+    }
+    // Exactly one whitespace byte separates the header from the pixels.
+    if !matches!(data.get(offset), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+        return Err(ImageError::Truncated);
+    }
+
+    let [width, height, max_value] = fields;
+    if max_value == 0 || max_value > 65535 {
+        return Err(ImageError::BadHeader);
+    }
+    Ok(PpmHeader {
+        width,
+        height,
+        max_value,
+        pixels_offset: offset + 1,
+    })
+}
+
+/// Decodes a binary (`P6`) PPM. Samples above 8 bits are scaled down.
+pub fn decode_ppm<'a>(data: &[u8], out: &'a mut [Rgba]) -> Result<Image<'a>, ImageError> {
+    let header = ppm_header(data)?;
+    let (width, height) = (header.width, header.height);
+    let pixels = output(out, width, height)?;
+    let sample_size = if header.max_value < 256 { 1 } else { 2 };
+    let samples = data
+        .get(header.pixels_offset..header.pixels_offset + width * height * 3 * sample_size)
+        .ok_or(ImageError::Truncated)?;
+
+    let scale = |sample: &[u8]| -> u8 {
+        let value = match sample {
+            [high, low] => usize::from(u16::from_be_bytes([*high, *low])),
+            [value] => usize::from(*value),
+            _ => 0,
+        };
+        ((value.min(header.max_value) * 255 + header.max_value / 2) / header.max_value) as u8
+    };
+    for (pixel, px) in pixels.iter_mut().zip(samples.chunks_exact(3 * sample_size)) {
+        let (r, rest) = px.split_at(sample_size);
+        let (g, b) = rest.split_at(sample_size);
+        *pixel = Rgba::new(scale(r), scale(g), scale(b), 255);
+    }
+    finish(out, width, height)
+}
+
+fn qoi_header(data: &[u8]) -> Result<(usize, usize), ImageError> {
+    if !data.starts_with(QOI_MAGIC) {
+        return Err(ImageError::UnknownFormat);
+    }
+    let width = u32_be(data, 4)? as usize;
+    let height = u32_be(data, 8)? as usize;
+    let channels = *data.get(12).ok_or(ImageError::Truncated)?;
+    if !matches!(channels, 3 | 4) {
+        return Err(ImageError::BadHeader);
+    }
+    Ok((width, height))
+}
+
+/// Decodes a QOI ("Quite OK Image") file.
+pub fn decode_qoi<'a>(data: &[u8], out: &'a mut [Rgba]) -> Result<Image<'a>, ImageError> {
+    let (width, height) = qoi_header(data)?;
+    let pixels = output(out, width, height)?;
+
+    let mut seen = [Rgba::TRANSPARENT; 64];
+    let mut px = Rgba::new(0, 0, 0, 255);
+    let mut offset = QOI_HEADER_SIZE;
+    let mut next = || -> Result<u8, ImageError> {
+        let byte = *data.get(offset).ok_or(ImageError::Truncated)?;
+        offset += 1;
+        Ok(byte)
+    };
+
+    let mut index = 0;
+    while index < pixels.len() {
+        let op = next()?;
+        let mut run = 1;
+        match op {
+            QOI_OP_RGB => {
+                px.r = next()?;
+                px.g = next()?;
+                px.b = next()?;
+            }
+            QOI_OP_RGBA => {
+                px.r = next()?;
+                px.g = next()?;
+                px.b = next()?;
+                px.a = next()?;
+            }
+            _ => match op & QOI_MASK_2 {
+                QOI_OP_INDEX => px = seen[usize::from(op)],
+                QOI_OP_DIFF => {
+                    px.r = px.r.wrapping_add((op >> 4) & 0x03).wrapping_sub(2);
+                    px.g = px.g.wrapping_add((op >> 2) & 0x03).wrapping_sub(2);
+                    px.b = px.b.wrapping_add(op & 0x03).wrapping_sub(2);
+                }
+                QOI_OP_LUMA => {
+                    let second = next()?;
+                    let dg = (op & 0x3F).wrapping_sub(32);
+                    px.r =
+                        px.r.wrapping_add(dg)
+                            .wrapping_add(second >> 4)
+                            .wrapping_sub(8);
+                    px.g = px.g.wrapping_add(dg);
+                    px.b =
+                        px.b.wrapping_add(dg)
+                            .wrapping_add(second & 0x0F)
+                            .wrapping_sub(8);
+                }
+                // QOI_OP_RUN, the only tag left.
+                _ => run = usize::from(op & 0x3F) + 1,
+            },
+        }
+
+        let hash = px.r as usize * 3 + px.g as usize * 5 + px.b as usize * 7 + px.a as usize * 11;
+        seen[hash % 64] = px;
+        let run = run.min(pixels.len() - index);
+        pixels[index..index + run].fill(px);
+        index += run;
+    }
+    finish(out, width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::Color;
+
+    /// A small picture in each supported format, the same in all of them apart from alpha, which
+    /// the 24-bit BMP and the PPM do not have.
+    const SAMPLES: [(&str, &[u8]); 5] = [
+        ("bmp", include_bytes!("../images/sample.bmp")),
+        (
+            "bitfields bmp",
+            include_bytes!("../images/sample-bitfields.bmp"),
+        ),
+        ("rle tga", include_bytes!("../images/sample.tga")),
+        ("ppm", include_bytes!("../images/sample.ppm")),
+        ("qoi", include_bytes!("../images/sample.qoi")),
+    ];
+
+    /// Width and height of each of the `SAMPLES`.
+    const SAMPLE_SIZE: (usize, usize) = (23, 16);
+
+    const BMP: &[u8] = SAMPLES[0].1;
+    const BITFIELDS_BMP: &[u8] = SAMPLES[1].1;
+    const TGA: &[u8] = SAMPLES[2].1;
+    const PPM: &[u8] = SAMPLES[3].1;
+    const QOI: &[u8] = SAMPLES[4].1;
+
+    const PIXELS: usize = SAMPLE_SIZE.0 * SAMPLE_SIZE.1;
+
+    /// What the samples show: a gradient with a white square on it, a row of alternating red
+    /// and blue, a row of small steps, and a half transparent top right corner.
+    fn sample_pixel(x: usize, y: usize, alpha: bool) -> Rgba {
+        let (r, g, b) = if (4..10).contains(&x) && (4..10).contains(&y) {
+            (255, 255, 255)
+        } else if y == 13 {
+            if x % 2 == 0 {
+                (200, 30, 30)
+            } else {
+                (30, 30, 200)
+            }
+        } else if y >= 14 {
+            (100 + x as u8, 100, 100)
+        } else {
+            (x as u8 * 4, y as u8 * 8, 128)
+        };
+        let a = if alpha && x >= 19 && y < 4 { 128 } else { 255 };
+        Rgba::new(r, g, b, a)
+    }
+
+    fn check_sample(data: &[u8], alpha: bool) {
+        assert_eq!(dimensions(data), Ok(SAMPLE_SIZE));
+        let mut out = [Rgba::TRANSPARENT; PIXELS];
+        let image = decode(data, &mut out).unwrap();
+        assert_eq!((image.width(), image.height()), SAMPLE_SIZE);
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                assert_eq!(
+                    image.pixel(x, y),
+                    sample_pixel(x, y, alpha),
+                    "pixel {x}, {y}"
+                );
+            }
+        }
+    }
+
+    fn decode_error(data: &[u8]) -> Option<ImageError> {
+        let mut out = [Rgba::TRANSPARENT; PIXELS];
+        decode(data, &mut out).err()
+    }
+
+    /// `data` with `bytes` written over it at `offset`.
+    fn patched(data: &[u8], offset: usize, bytes: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        data
+    }
+
+    #[test]
+    fn decodes_known_pixels() {
+        for (name, data) in SAMPLES {
+            let mut out = [Rgba::TRANSPARENT; PIXELS];
+            let image = decode(data, &mut out).unwrap();
+            assert_eq!(image.pixel(0, 0).rgb(), Color::rgb(0, 0, 128), "{name}");
+            assert_eq!(image.pixel(5, 5).rgb(), Color::rgb(255, 255, 255), "{name}");
+            assert_eq!(image.pixel(1, 13).rgb(), Color::rgb(30, 30, 200), "{name}");
+            assert_eq!(
+                image.pixel(22, 15).rgb(),
+                Color::rgb(122, 100, 100),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_bmp() {
+        check_sample(BMP, false);
+        check_sample(BITFIELDS_BMP, true);
+    }
+
+    #[test]
+    fn decodes_tga() {
+        check_sample(TGA, true);
+    }
+
+    #[test]
+    fn decodes_ppm() {
+        check_sample(PPM, false);
+    }
+
+    #[test]
+    fn decodes_qoi() {
+        check_sample(QOI, true);
+    }
+
+    #[test]
+    fn rejects_unknown_data() {
+        assert_eq!(decode_error(b""), Some(ImageError::UnknownFormat));
+        assert_eq!(
+            decode_error(b"hello, world"),
+            Some(ImageError::UnknownFormat)
+        );
+    }
+
+    #[test]
+    fn rejects_small_buffers() {
+        for (_, data) in SAMPLES {
+            let mut out = [Rgba::TRANSPARENT; PIXELS - 1];
+            assert_eq!(
+                decode(data, &mut out).err(),
+                Some(ImageError::BufferTooSmall)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_bmp() {
+        // Cut off in the middle of the bitfield masks.
+        let masks = BMP_INFO_HEADER + 40;
+        for len in [10, masks, masks + 6, masks + 14] {
+            assert_eq!(
+                decode_error(&BITFIELDS_BMP[..len]),
+                Some(ImageError::Truncated)
+            );
+        }
+        // The padding at the end of the last row may go missing, but not its pixels.
+        let mut out = [Rgba::TRANSPARENT; PIXELS];
+        assert!(decode(&BMP[..BMP.len() - 3], &mut out).is_ok());
+        assert_eq!(
+            decode_error(&BMP[..BMP.len() - 4]),
+            Some(ImageError::Truncated)
+        );
+        assert_eq!(
+            decode_error(&BITFIELDS_BMP[..BITFIELDS_BMP.len() - 1]),
+            Some(ImageError::Truncated)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_bmp() {
+        // Zero width.
+        let data = patched(BMP, 18, &0i32.to_le_bytes());
+        assert_eq!(decode_error(&data), Some(ImageError::BadHeader));
+        // The OS/2 core header.
+        let data = patched(BMP, BMP_INFO_HEADER, &12u32.to_le_bytes());
+        assert_eq!(decode_error(&data), Some(ImageError::Unsupported));
+        // 16-bit and 24-bit bitfields.
+        let data = patched(BITFIELDS_BMP, 28, &16u16.to_le_bytes());
+        assert_eq!(decode_error(&data), Some(ImageError::Unsupported));
+        let data = patched(BITFIELDS_BMP, 28, &24u16.to_le_bytes());
+        assert_eq!(decode_error(&data), Some(ImageError::Unsupported));
+        // Pixels said to start past the end of the file.
+        let data = patched(BMP, 10, &u32::MAX.to_le_bytes());
+        assert_eq!(decode_error(&data), Some(ImageError::Truncated));
+    }
+
+    #[test]
+    fn bmp_bitfields_scale_narrow_masks() {
+        assert_eq!(bmp_channel(0x1F, 0x1F), 255);
+        assert_eq!(bmp_channel(0x10, 0x1F), 132);
+        assert_eq!(bmp_channel(0x7C00, 0x7C00), 255);
+        assert_eq!(bmp_channel(0xFFFF_FFFF, 0), 0);
+    }
+
+    #[test]
+    fn rejects_truncated_tga() {
+        // Every cut inside the run-length packets, including right after a packet header.
+        for len in TGA_HEADER_SIZE + 6..TGA.len() {
+            assert_eq!(
+                decode_error(&TGA[..len]),
+                Some(ImageError::Truncated),
+                "{len} bytes"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_tga() {
+        // Colour-mapped.
+        let data = patched(TGA, 2, &[1]);
+        assert_eq!(decode_error(&data), Some(ImageError::Unsupported));
+        // 16 bits per pixel.
+        let data = patched(TGA, 16, &[16]);
+        assert_eq!(decode_error(&data), Some(ImageError::Unsupported));
+    }
+
+    #[test]
+    fn tga_runs_stop_at_the_last_pixel() {
+        // A 2x1 RLE greyscale image whose one packet repeats its pixel six times.
+        let mut data = [0; TGA_HEADER_SIZE + 2];
+        data[2] = TGA_RLE_GRAYSCALE;
+        data[12] = 2;
+        data[14] = 1;
+        data[16] = 8;
+        data[17] = TGA_TOP_TO_BOTTOM;
+        data[TGA_HEADER_SIZE..].copy_from_slice(&[0x85, 77]);
+        let mut out = [Rgba::TRANSPARENT; 2];
+        let image = decode_tga(&data, &mut out).unwrap();
+        assert_eq!(image.pixels(), [Rgba::new(77, 77, 77, 255); 2]);
+    }
+
+    #[test]
+    fn rejects_malformed_ppm() {
+        assert_eq!(
+            decode_error(&PPM[..PPM.len() - 1]),
+            Some(ImageError::Truncated)
+        );
+        assert_eq!(decode_error(b"P6 2 2"), Some(ImageError::Truncated));
+        assert_eq!(decode_error(b"P6 2 x 255\n"), Some(ImageError::BadHeader));
+        assert_eq!(
+            decode_error(b"P6 1 1 0\n\0\0\0"),
+            Some(ImageError::BadHeader)
+        );
+        assert_eq!(decode_error(b"P6 0 1 255\n"), Some(ImageError::BadHeader));
+    }
+
+    #[test]
+    fn scales_16_bit_ppm() {
+        let mut out = [Rgba::TRANSPARENT; 1];
+        let image = decode(b"P6 1 1 65535\n\xFF\xFF\x80\x00\x00\x00", &mut out).unwrap();
+        assert_eq!(image.pixel(0, 0), Rgba::new(255, 128, 0, 255));
+    }
+
+    /// A QOI header for a `width` by `height` RGBA image followed by `ops`.
+    fn qoi(width: u32, height: u32, ops: &[u8]) -> Vec<u8> {
+        let mut data = QOI_MAGIC.to_vec();
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[4, 0]);
+        data.extend_from_slice(ops);
+        data
+    }
+
+    #[test]
+    fn rejects_truncated_qoi() {
+        assert_eq!(
+            decode_error(&QOI[..QOI_HEADER_SIZE - 2]),
+            Some(ImageError::Truncated)
+        );
+        // The 8-byte end marker is never read, so only cuts before it are noticed.
+        for len in QOI_HEADER_SIZE..QOI.len() - 8 {
+            assert_eq!(
+                decode_error(&QOI[..len]),
+                Some(ImageError::Truncated),
+                "{len} bytes"
+            );
+        }
+        // An RGB, RGBA and luma op missing their last byte.
+        for ops in [
+            &[QOI_OP_RGB, 1, 2][..],
+            &[QOI_OP_RGBA, 1, 2, 3],
+            &[QOI_OP_LUMA],
+        ] {
+            assert_eq!(decode_error(&qoi(1, 1, ops)), Some(ImageError::Truncated));
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_qoi() {
+        let data = patched(QOI, 12, &[5]);
+        assert_eq!(decode_error(&data), Some(ImageError::BadHeader));
+        assert_eq!(decode_error(&qoi(0, 1, &[])), Some(ImageError::BadHeader));
+        let too_wide = MAX_DIMENSION as u32 + 1;
+        assert_eq!(
+            decode_error(&qoi(too_wide, 1, &[])),
+            Some(ImageError::BadHeader)
+        );
+    }
+
+    #[test]
+    fn decodes_qoi_ops() {
+        let decode_pixels = |width: u32, ops: &[u8]| {
+            let data = qoi(width, 1, ops);
+            let mut out = [Rgba::TRANSPARENT; 4];
+            let image = decode_qoi(&data, &mut out).unwrap();
+            image.pixels().to_vec()
+        };
+        let black = Rgba::new(0, 0, 0, 255);
+
+        // Nothing has been seen yet, so the index is all transparent black.
+        assert_eq!(decode_pixels(1, &[QOI_OP_INDEX | 5]), [Rgba::TRANSPARENT]);
+        // A run longer than the image stops at its last pixel.
+        assert_eq!(decode_pixels(2, &[0xC0 | 61]), [black; 2]);
+        // Differences wrap around.
+        assert_eq!(
+            decode_pixels(1, &[QOI_OP_DIFF]),
+            [Rgba::new(254, 254, 254, 255)]
+        );
+        // Green moves by 31; red and blue by 7 more than that.
+        assert_eq!(
+            decode_pixels(1, &[QOI_OP_LUMA | 0x3F, 0xFF]),
+            [Rgba::new(38, 31, 38, 255)]
+        );
+        // An RGB pixel, another one, and the first one again from the index.
+        let red = Rgba::new(200, 0, 0, 255);
+        let ops = [QOI_OP_RGB, 200, 0, 0, QOI_OP_RGB, 0, 0, 9];
+        let hash = (200 * 3 + 255 * 11) % 64;
+        let mut ops = ops.to_vec();
+        ops.push(QOI_OP_INDEX | hash as u8);
+        assert_eq!(decode_pixels(3, &ops), [red, Rgba::new(0, 0, 9, 255), red]);
+    }
+}
@@ -2,7 +2,7 @@ use core::sync::atomic::{AtomicU64, Ordering};
 
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
 
-use crate::{gdt, vga_text_mode_terminal::CURSOR_TOGGLE_FLAG};
+use crate::{console, fpu, gdt, mouse, serial};
 use lazy_static::lazy_static;
 
 use pic8259::ChainedPics;
@@ -10,6 +10,15 @@ use spin;
 
 pub static TIMER_TICKS: AtomicU64 = AtomicU64::new(0);
 
+/// How many times each of the 16 PIC lines has fired.
+static IRQ_COUNTS: [AtomicU64; 16] = [const { AtomicU64::new(0) }; 16];
+
+/// Rate the PIT is programmed to in `init`, i.e. timer ticks per second.
+pub const TIMER_HZ: u32 = 60;
+
+/// Vector the local APIC raises for spurious interrupts once `smp` has enabled it.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
 ////////////////    IDT    ////////////////
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
@@ -21,6 +30,9 @@ lazy_static! {
         }
         idt[InterruptIndex::Timer.as_u8()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_u8()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Com1.as_u8()].set_handler_fn(serial_interrupt_handler);
+        idt[InterruptIndex::Mouse.as_u8()].set_handler_fn(mouse_interrupt_handler);
+        idt[SPURIOUS_VECTOR].set_handler_fn(spurious_interrupt_handler);
         idt
     };
 }
@@ -61,24 +73,38 @@ extern "x86-interrupt" fn double_fault_handler(
     );
 }
 
+/// Spurious interrupts are not acknowledged.
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    TIMER_TICKS.fetch_add(1, Ordering::Relaxed);
-    let current_state = CURSOR_TOGGLE_FLAG.load(Ordering::SeqCst);
-    CURSOR_TOGGLE_FLAG.store(!current_state, Ordering::SeqCst);
+    fpu::with_saved_state(|| {
+        TIMER_TICKS.fetch_add(1, Ordering::Relaxed);
+        count_irq(InterruptIndex::Timer);
+        serial::handle_tick();
 
-    // Acknowledge the interrupt
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-    }
+        // Acknowledge the interrupt
+        unsafe {
+            PICS.lock()
+                .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+        }
+    });
 }
 
 pub fn timer_ticks() -> u64 {
     TIMER_TICKS.load(Ordering::Relaxed)
 }
 
+fn count_irq(irq: InterruptIndex) {
+    IRQ_COUNTS[irq.irq_line() as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of interrupts received on `irq` since boot.
+pub fn irq_count(irq: InterruptIndex) -> u64 {
+    IRQ_COUNTS[irq.irq_line() as usize].load(Ordering::Relaxed)
+}
+
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    use pc_keyboard::{layouts, HandleControl, Keyboard, ScancodeSet1};
+    use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, Keyboard, ScancodeSet1};
     use spin::Mutex;
     use x86_64::instructions::port::Port;
 
@@ -88,18 +114,60 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
         );
     }
 
-    let mut keyboard = KEYBOARD.lock();
-    let mut port = Port::new(0x60);
+    fpu::with_saved_state(|| {
+        count_irq(InterruptIndex::Keyboard);
+        let mut keyboard = KEYBOARD.lock();
+        let mut port = Port::new(0x60);
+
+        let scancode: u8 = unsafe { port.read() };
+        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
+            let shifted = keyboard.get_modifiers().is_shifted();
+            let alt = keyboard.get_modifiers().is_alt();
+            match keyboard.process_keyevent(key_event) {
+                Some(DecodedKey::RawKey(KeyCode::PageUp)) if shifted => console::scroll_view_up(),
+                Some(DecodedKey::RawKey(KeyCode::PageDown)) if shifted => {
+                    console::scroll_view_down()
+                }
+                Some(DecodedKey::RawKey(KeyCode::F1)) if alt => console::switch_to(0),
+                Some(DecodedKey::RawKey(KeyCode::F2)) if alt => console::switch_to(1),
+                Some(DecodedKey::RawKey(KeyCode::F3)) if alt => console::switch_to(2),
+                Some(DecodedKey::RawKey(KeyCode::F4)) if alt => console::switch_to(3),
+                Some(DecodedKey::RawKey(KeyCode::F5)) if alt => console::switch_to(4),
+                Some(DecodedKey::RawKey(KeyCode::F6)) if alt => console::switch_to(5),
+                Some(key) => console::push_key(key),
+                None => {}
+            }
+        }
 
-    let scancode: u8 = unsafe { port.read() };
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        let _ = keyboard.process_keyevent(key_event);
-    }
+        unsafe {
+            PICS.lock()
+                .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+        }
+    });
+}
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
-    }
+extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    fpu::with_saved_state(|| {
+        count_irq(InterruptIndex::Com1);
+        serial::handle_interrupt();
+
+        unsafe {
+            PICS.lock()
+                .notify_end_of_interrupt(InterruptIndex::Com1.as_u8());
+        }
+    });
+}
+
+extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    fpu::with_saved_state(|| {
+        count_irq(InterruptIndex::Mouse);
+        mouse::handle_interrupt();
+
+        unsafe {
+            PICS.lock()
+                .notify_end_of_interrupt(InterruptIndex::Mouse.as_u8());
+        }
+    });
 }
 
 ////////////////    PIC    /////////////////////
@@ -114,13 +182,44 @@ pub static PICS: spin::Mutex<ChainedPics> =
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Com1 = PIC_1_OFFSET + 4,
+    Mouse = PIC_1_OFFSET + 12,
 }
 
 impl InterruptIndex {
+    pub const ALL: [InterruptIndex; 4] = [
+        InterruptIndex::Timer,
+        InterruptIndex::Keyboard,
+        InterruptIndex::Com1,
+        InterruptIndex::Mouse,
+    ];
+
     fn as_u8(self) -> u8 {
         self as u8
     }
+
+    /// The PIC input line, 0-15.
+    pub fn irq_line(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
+    }
+}
+/// Lets `irq` through the PIC. Lines the firmware left masked stay masked after `PICS.initialize()`.
+pub fn unmask_irq(irq: InterruptIndex) {
+    let line = irq.irq_line();
+    let mut pics = PICS.lock();
+    unsafe {
+        let [mut mask1, mut mask2] = pics.read_masks();
+        if line < 8 {
+            mask1 &= !(1 << line);
+        } else {
+            // Secondary PIC lines also need the cascade line on the primary.
+            mask1 &= !(1 << 2);
+            mask2 &= !(1 << (line - 8));
+        }
+        pics.write_masks(mask1, mask2);
+    }
 }
+
 //////////////// TRIGGER FAULTS ////////////////
 pub fn trigger_page_fault() {
     unsafe {
@@ -0,0 +1,240 @@
+use core::fmt::{self, Write};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::console::{self, KERNEL_CONSOLE};
+use crate::interupts::{timer_ticks, TIMER_HZ};
+use crate::scrollback::Scrollback;
+use crate::serial;
+
+/// Longest log line; anything past it is cut off.
+pub const LINE_LEN: usize = 160;
+
+/// Number of lines the in-memory log keeps for `dmesg`.
+pub const RING_LINES: usize = 256;
+
+const MAX_MODULE_FILTERS: usize = 16;
+const MAX_SINKS: usize = 8;
+
+static LOGGER: KernelLogger = KernelLogger;
+
+static FILTERS: Mutex<Filters> = Mutex::new(Filters::new());
+
+/// A sink and the most verbose level it receives.
+type SinkEntry = (&'static dyn LogSink, LevelFilter);
+
+static SINKS: Mutex<[Option<SinkEntry>; MAX_SINKS]> = Mutex::new([None; MAX_SINKS]);
+
+static RING: Mutex<Scrollback<u8, LINE_LEN, RING_LINES>> = Mutex::new(Scrollback::new(0));
+
+/// Somewhere finished log lines go. `line` carries the timestamp, level and module but no newline.
+pub trait LogSink: Sync {
+    fn write(&self, level: Level, line: &str);
+}
+
+/// Writes log lines to COM1.
+pub struct SerialSink;
+
+/// Writes log lines to the kernel console, coloured by level. Only add it once the console has
+/// somewhere to draw.
+pub struct ConsoleSink;
+
+/// Keeps the last `RING_LINES` log lines in memory, to be read back with `dmesg`.
+pub struct RingSink;
+
+pub static SERIAL_SINK: SerialSink = SerialSink;
+pub static CONSOLE_SINK: ConsoleSink = ConsoleSink;
+pub static RING_SINK: RingSink = RingSink;
+
+impl LogSink for SerialSink {
+    fn write(&self, _level: Level, line: &str) {
+        serial::_print(format_args!("{}\n", line));
+    }
+}
+
+impl LogSink for ConsoleSink {
+    fn write(&self, level: Level, line: &str) {
+        let color = match level {
+            Level::Error => "\x1b[91m",
+            Level::Warn => "\x1b[93m",
+            Level::Info => "",
+            Level::Debug | Level::Trace => "\x1b[90m",
+        };
+        // `print_to` rather than `print!`, so mirroring to serial does not log the line twice.
+        console::print_to(KERNEL_CONSOLE, format_args!("{}{}\x1b[0m\n", color, line));
+    }
+}
+
+impl LogSink for RingSink {
+    fn write(&self, _level: Level, line: &str) {
+        let mut padded = [0; LINE_LEN];
+        padded[..line.len()].copy_from_slice(line.as_bytes());
+        RING.lock().push(&padded);
+    }
+}
+
+/// Installs the kernel logger with the serial and in-memory sinks, logging `Info` and above.
+pub fn init() {
+    add_sink(&SERIAL_SINK, LevelFilter::Trace);
+    add_sink(&RING_SINK, LevelFilter::Trace);
+    if log::set_logger(&LOGGER).is_ok() {
+        // Filtering happens per module in `KernelLogger::enabled`.
+        log::set_max_level(LevelFilter::Trace);
+    }
+}
+
+/// Sends every line at or above `level` to `sink` as well.
+pub fn add_sink(sink: &'static dyn LogSink, level: LevelFilter) {
+    without_interrupts(|| {
+        let mut sinks = SINKS.lock();
+        if let Some(slot) = sinks.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some((sink, level));
+        }
+    });
+}
+
+/// Level for modules without a filter of their own.
+pub fn set_level(level: LevelFilter) {
+    without_interrupts(|| FILTERS.lock().default = level);
+}
+
+/// Level for `module` and everything below it, e.g. `"kernel::interupts"`. The most specific
+/// filter wins.
+pub fn set_module_level(module: &'static str, level: LevelFilter) {
+    without_interrupts(|| FILTERS.lock().set(module, level));
+}
+
+/// Calls `f` with each line in the in-memory log, oldest first.
+pub fn dmesg(mut f: impl FnMut(&str)) {
+    let mut line = [0; LINE_LEN];
+    let mut index = 0;
+    loop {
+        // Copy the line out so `f` can log or print without holding the lock.
+        let found = without_interrupts(|| match RING.lock().get(index) {
+            Some(stored) => {
+                line = *stored;
+                true
+            }
+            None => false,
+        });
+        if !found {
+            return;
+        }
+
+        let len = line.iter().position(|&byte| byte == 0).unwrap_or(LINE_LEN);
+        f(core::str::from_utf8(&line[..len]).unwrap_or("?"));
+        index += 1;
+    }
+}
+
+struct Filters {
+    default: LevelFilter,
+    modules: [Option<(&'static str, LevelFilter)>; MAX_MODULE_FILTERS],
+}
+
+impl Filters {
+    const fn new() -> Filters {
+        Filters {
+            default: LevelFilter::Info,
+            modules: [None; MAX_MODULE_FILTERS],
+        }
+    }
+
+    fn set(&mut self, module: &'static str, level: LevelFilter) {
+        let slot = self
+            .modules
+            .iter()
+            .position(|filter| matches!(filter, Some((name, _)) if *name == module))
+            .or_else(|| self.modules.iter().position(Option::is_none));
+        if let Some(slot) = slot {
+            self.modules[slot] = Some((module, level));
+        }
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .flatten()
+            .filter(|(module, _)| is_within(target, module))
+            .max_by_key(|(module, _)| module.len())
+            .map_or(self.default, |&(_, level)| level)
+    }
+}
+
+/// Whether `target` is `module` or one of its submodules.
+fn is_within(target: &str, module: &str) -> bool {
+    match target.strip_prefix(module) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+/// Fixed-size line that silently drops whatever does not fit.
+struct LineBuffer {
+    bytes: [u8; LINE_LEN],
+    len: usize,
+}
+
+impl LineBuffer {
+    fn as_str(&self) -> &str {
+        // Truncation can split a character; keep only the valid prefix.
+        match core::str::from_utf8(&self.bytes[..self.len]) {
+            Ok(line) => line,
+            Err(error) => {
+                core::str::from_utf8(&self.bytes[..error.valid_up_to()]).unwrap_or_default()
+            }
+        }
+    }
+}
+
+impl Write for LineBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let count = s.len().min(LINE_LEN - self.len);
+        self.bytes[self.len..self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        Ok(())
+    }
+}
+
+struct KernelLogger;
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= without_interrupts(|| FILTERS.lock().level_for(metadata.target()))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let ticks = timer_ticks();
+        let hz = u64::from(TIMER_HZ);
+        let mut line = LineBuffer {
+            bytes: [0; LINE_LEN],
+            len: 0,
+        };
+        let _ = write!(
+            line,
+            "[{:>5}.{:03}] {:<5} {}: {}",
+            ticks / hz,
+            ticks % hz * 1000 / hz,
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        without_interrupts(|| {
+            let sinks = *SINKS.lock();
+            for (sink, level) in sinks.iter().flatten() {
+                if record.level() <= *level {
+                    sink.write(record.level(), line.as_str());
+                }
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
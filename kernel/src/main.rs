@@ -1,55 +1,204 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![feature(abi_x86_interrupt)]
+// The unit tests run on the host, where nothing calls into the kernel from `kernel_main`.
+#![cfg_attr(test, allow(dead_code, unused_imports))]
 
-use core::fmt::Write;
-
+use bootloader_api::config::{BootloaderConfig, Mapping};
 use bootloader_api::{entry_point, BootInfo};
 
+use console::KERNEL_CONSOLE;
+use font::Font;
 use framebuffer::run_bouncy_circles;
 use interupts::PICS;
-use serial::SerialWriter;
+use log::{info, warn, LevelFilter};
+use shell::Shell;
+use tui::{ProgressBar, Rect, Window, DEFAULT_STYLE};
 use util::halt_loop;
 
+pub mod ansi;
+pub mod console;
+pub mod cp437;
+pub mod font;
+pub mod fpu;
 pub mod framebuffer;
+pub mod framebuffer_console;
+pub mod framebuffer_drawing;
 pub mod gdt;
+pub mod image;
 pub mod interupts;
+pub mod logger;
+pub mod math;
+pub mod mouse;
+pub mod parallel;
 pub mod random;
+pub mod screenshot;
+pub mod scrollback;
+pub mod sdf;
 pub mod serial;
+pub mod shell;
+pub mod smp;
+pub mod snake;
+pub mod sprite;
+pub mod tetris;
+pub mod tui;
 pub mod util;
 pub mod vga_text_mode;
 pub mod vga_text_mode_drawing;
 pub mod vga_text_mode_terminal;
 
-entry_point!(kernel_main);
+/// Physical memory is mapped so that `smp` can reach the local APIC and low memory.
+const BOOTLOADER_CONFIG: BootloaderConfig = {
+    let mut config = BootloaderConfig::new_default();
+    config.mappings.physical_memory = Some(Mapping::Dynamic);
+    config
+};
+
+#[cfg(not(test))]
+entry_point!(kernel_main, config = &BOOTLOADER_CONFIG);
+
+/// How long the boot log stays on screen before the demo menu covers it.
+const BOOT_PAUSE_TICKS: u64 = 2 * interupts::TIMER_HZ as u64;
 
 pub fn init() {
     gdt::init();
     interupts::init();
     unsafe { PICS.lock().initialize() };
-    interupts::init_pit(60);
+    serial::init_input();
+    mouse::init();
+    interupts::init_pit(interupts::TIMER_HZ);
     x86_64::instructions::interrupts::enable();
 }
 
 fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
-    let mut serial = SerialWriter::new();
-    let _ = writeln!(&mut serial, "kernel: start");
+    // Compiled code uses SSE anywhere, so it has to be on before anything else runs.
+    let fpu = fpu::init();
+    logger::init();
+    info!("start");
+    info!(
+        "fpu: sse={} sse2={} xsave={} avx={}",
+        fpu.sse, fpu.sse2, fpu.xsave, fpu.avx
+    );
 
     init();
-    let _ = writeln!(&mut serial, "kernel: init done");
+    info!("init done");
+
+    if let Some(offset) = boot_info.physical_memory_offset.into_option() {
+        let cpus = smp::start_aps(&boot_info.memory_regions, offset);
+        info!("{} cpu(s) online", cpus);
+    }
+
+    let ramdisk = boot_info.ramdisk_addr.into_option().map(|addr| {
+        // The bootloader maps the ramdisk for us and leaves it alone afterwards.
+        unsafe { core::slice::from_raw_parts(addr as *const u8, boot_info.ramdisk_len as usize) }
+    });
 
     if let Some(framebuffer) = boot_info.framebuffer.as_mut() {
         let info = framebuffer.info();
-        let _ = writeln!(
-            &mut serial,
-            "kernel: framebuffer {}x{} {:?}",
+        info!(
+            "framebuffer {}x{} {:?}",
             info.width,
             info.height,
             info.pixel_format
         );
-        run_bouncy_circles(framebuffer);
+        framebuffer_console::init(framebuffer);
+        logger::add_sink(&logger::CONSOLE_SINK, LevelFilter::Info);
+        // A PSF font passed as the ramdisk replaces the built-in ones.
+        if let Some(ramdisk) = ramdisk {
+            match Font::parse(ramdisk) {
+                Ok(font) => framebuffer_console::set_font(font),
+                Err(error) => warn!("ramdisk is not a font: {:?}", error),
+            }
+        }
+        // Copy console output to serial so headless runs and test logs see it too.
+        console::set_mirror_to_serial(true);
+        println!("phils-rust-os");
+        println!(
+            "framebuffer {}x{} {:?}, {} bytes per pixel",
+            info.width,
+            info.height,
+            info.pixel_format,
+            info.bytes_per_pixel
+        );
+        for n in 1..console::VIRTUAL_CONSOLES {
+            console::print_to(n, format_args!("phils-rust-os console {}\n", n + 1));
+        }
+
+        boot_pause();
+        demo_menu();
+
+        Shell::new(&boot_info.memory_regions).run();
     }
 
-    let _ = writeln!(&mut serial, "kernel: no framebuffer");
+    warn!("no framebuffer");
     halt_loop();
 }
+
+/// Leaves the boot log on screen for a couple of seconds, with a progress bar along the bottom.
+fn boot_pause() {
+    let start = interupts::timer_ticks();
+    let mut progress = ProgressBar::new(BOOT_PAUSE_TICKS as usize);
+    loop {
+        let elapsed = interupts::timer_ticks() - start;
+        progress.set(elapsed as usize);
+        console::with_canvas(KERNEL_CONSOLE, |canvas| {
+            let (cols, rows) = canvas.size();
+            let panel = Window::panel(
+                Rect::new(0, rows.saturating_sub(3), cols, 3),
+                Some("Starting"),
+            );
+            panel.draw(canvas, &DEFAULT_STYLE);
+            let inner = panel.inner();
+            progress.draw(
+                canvas,
+                &DEFAULT_STYLE,
+                inner.col + 1,
+                inner.row,
+                inner.width.saturating_sub(2),
+            );
+        });
+        if elapsed >= BOOT_PAUSE_TICKS {
+            return;
+        }
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Lets the user run demos from a menu until they pick the shell or press Escape.
+fn demo_menu() {
+    const ITEMS: &[&str] = &["Bouncing spheres", "Snake", "Tetris", "About", "Shell"];
+
+    loop {
+        match tui::run_menu(KERNEL_CONSOLE, "Demos", ITEMS, &DEFAULT_STYLE) {
+            Some(0) => {
+                if let Some(framebuffer) = framebuffer_console::take_framebuffer() {
+                    run_bouncy_circles(framebuffer);
+                    framebuffer_console::restore_framebuffer(framebuffer);
+                }
+            }
+            Some(1) => {
+                snake::run(KERNEL_CONSOLE);
+                print!("\x1b[2J\x1b[H");
+            }
+            Some(2) => {
+                tetris::run(KERNEL_CONSOLE);
+                print!("\x1b[2J\x1b[H");
+            }
+            Some(3) => {
+                tui::message_box(
+                    KERNEL_CONSOLE,
+                    "About",
+                    "phils-rust-os\n\n\
+                     Escape leaves a running demo.\n\
+                     Alt+F1..F6 switch consoles.\n\
+                     Shift+PgUp/PgDn scroll back.",
+                    &DEFAULT_STYLE,
+                );
+                // The menu is smaller than the message box; don't leave its edges behind.
+                print!("\x1b[2J\x1b[H");
+            }
+            _ => break,
+        }
+    }
+    print!("\x1b[2J\x1b[H");
+}
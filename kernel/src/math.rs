@@ -0,0 +1,730 @@
+//! Vectors, matrices and quaternions for the renderers. Matrices are column-major and act on
+//! column vectors, so `a * b * v` applies `b` first. Coordinates are right-handed with -Z
+//! forward, as in OpenGL.
+
+use core::ops::{Add, AddAssign, Div, DivAssign, Index, Mul, MulAssign, Neg, Sub, SubAssign};
+
+use libm::{acosf, cosf, sinf, sqrtf, tanf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Vec2 {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Vec3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Vec4 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+/// What all three vector types share: component-wise arithmetic, scaling by a scalar, and the
+/// dot product with everything built on it.
+macro_rules! impl_vector {
+    ($vec:ident { $($field:ident),+ }) => {
+        impl $vec {
+            pub const ZERO: $vec = $vec::splat(0.0);
+            pub const ONE: $vec = $vec::splat(1.0);
+
+            pub const fn new($($field: f32),+) -> $vec {
+                $vec { $($field),+ }
+            }
+
+            pub const fn splat(v: f32) -> $vec {
+                $vec { $($field: v),+ }
+            }
+
+            pub fn dot(self, rhs: $vec) -> f32 {
+                0.0 $(+ self.$field * rhs.$field)+
+            }
+
+            pub fn len_squared(self) -> f32 {
+                self.dot(self)
+            }
+
+            pub fn len(self) -> f32 {
+                sqrtf(self.len_squared())
+            }
+
+            /// The unit vector in the same direction, or zero for a (nearly) zero vector.
+            pub fn norm(self) -> $vec {
+                let len = self.len();
+                if len <= 0.000_01 {
+                    $vec::ZERO
+                } else {
+                    self / len
+                }
+            }
+
+            pub fn distance(self, other: $vec) -> f32 {
+                (other - self).len()
+            }
+
+            pub fn abs(self) -> $vec {
+                $vec { $($field: self.$field.abs()),+ }
+            }
+
+            /// Component-wise minimum.
+            pub fn min(self, rhs: $vec) -> $vec {
+                $vec { $($field: self.$field.min(rhs.$field)),+ }
+            }
+
+            /// Component-wise maximum.
+            pub fn max(self, rhs: $vec) -> $vec {
+                $vec { $($field: self.$field.max(rhs.$field)),+ }
+            }
+
+            pub fn clamp(self, min: $vec, max: $vec) -> $vec {
+                self.max(min).min(max)
+            }
+
+            pub fn min_element(self) -> f32 {
+                f32::INFINITY $(.min(self.$field))+
+            }
+
+            pub fn max_element(self) -> f32 {
+                f32::NEG_INFINITY $(.max(self.$field))+
+            }
+
+            /// `self` at `t` = 0, `other` at `t` = 1.
+            pub fn lerp(self, other: $vec, t: f32) -> $vec {
+                self + (other - self) * t
+            }
+        }
+
+        impl Add for $vec {
+            type Output = $vec;
+            fn add(self, rhs: $vec) -> $vec {
+                $vec { $($field: self.$field + rhs.$field),+ }
+            }
+        }
+
+        impl Sub for $vec {
+            type Output = $vec;
+            fn sub(self, rhs: $vec) -> $vec {
+                $vec { $($field: self.$field - rhs.$field),+ }
+            }
+        }
+
+        /// Component-wise product, e.g. for tinting a colour by a light.
+        impl Mul for $vec {
+            type Output = $vec;
+            fn mul(self, rhs: $vec) -> $vec {
+                $vec { $($field: self.$field * rhs.$field),+ }
+            }
+        }
+
+        impl Mul<f32> for $vec {
+            type Output = $vec;
+            fn mul(self, rhs: f32) -> $vec {
+                $vec { $($field: self.$field * rhs),+ }
+            }
+        }
+
+        impl Mul<$vec> for f32 {
+            type Output = $vec;
+            fn mul(self, rhs: $vec) -> $vec {
+                rhs * self
+            }
+        }
+
+        impl Div<f32> for $vec {
+            type Output = $vec;
+            fn div(self, rhs: f32) -> $vec {
+                self * (1.0 / rhs)
+            }
+        }
+
+        impl Neg for $vec {
+            type Output = $vec;
+            fn neg(self) -> $vec {
+                $vec { $($field: -self.$field),+ }
+            }
+        }
+
+        impl AddAssign for $vec {
+            fn add_assign(&mut self, rhs: $vec) {
+                *self = *self + rhs;
+            }
+        }
+
+        impl SubAssign for $vec {
+            fn sub_assign(&mut self, rhs: $vec) {
+                *self = *self - rhs;
+            }
+        }
+
+        impl MulAssign<f32> for $vec {
+            fn mul_assign(&mut self, rhs: f32) {
+                *self = *self * rhs;
+            }
+        }
+
+        impl DivAssign<f32> for $vec {
+            fn div_assign(&mut self, rhs: f32) {
+                *self = *self / rhs;
+            }
+        }
+    };
+}
+
+impl_vector!(Vec2 { x, y });
+impl_vector!(Vec3 { x, y, z });
+impl_vector!(Vec4 { x, y, z, w });
+
+impl Vec2 {
+    /// `self` turned a quarter turn anticlockwise.
+    pub fn perp(self) -> Vec2 {
+        Vec2::new(-self.y, self.x)
+    }
+
+    /// Z component of the 3D cross product; positive if `rhs` is anticlockwise from `self`.
+    pub fn perp_dot(self, rhs: Vec2) -> f32 {
+        self.x * rhs.y - self.y * rhs.x
+    }
+}
+
+impl Vec3 {
+    pub const X: Vec3 = Vec3::new(1.0, 0.0, 0.0);
+    pub const Y: Vec3 = Vec3::new(0.0, 1.0, 0.0);
+    pub const Z: Vec3 = Vec3::new(0.0, 0.0, 1.0);
+
+    pub fn cross(self, rhs: Vec3) -> Vec3 {
+        Vec3::new(
+            self.y * rhs.z - self.z * rhs.y,
+            self.z * rhs.x - self.x * rhs.z,
+            self.x * rhs.y - self.y * rhs.x,
+        )
+    }
+
+    /// Mirrors the direction `self` about the unit surface normal `n`.
+    pub fn reflect(self, n: Vec3) -> Vec3 {
+        self - n * (2.0 * self.dot(n))
+    }
+
+    /// Bends the unit direction `self` through a surface with unit normal `n`, where `eta` is
+    /// the ratio of refractive indices (outside over inside). `None` means total internal
+    /// reflection.
+    pub fn refract(self, n: Vec3, eta: f32) -> Option<Vec3> {
+        let cos_i = -self.dot(n);
+        let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+        if k < 0.0 {
+            return None;
+        }
+        Some(self * eta + n * (eta * cos_i - sqrtf(k)))
+    }
+
+    pub const fn extend(self, w: f32) -> Vec4 {
+        Vec4::new(self.x, self.y, self.z, w)
+    }
+}
+
+impl Vec4 {
+    /// The first three components.
+    pub const fn truncate(self) -> Vec3 {
+        Vec3::new(self.x, self.y, self.z)
+    }
+}
+
+/// A 3x3 matrix, for rotations and scaling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat3 {
+    pub cols: [Vec3; 3],
+}
+
+impl Mat3 {
+    pub const IDENTITY: Mat3 = Mat3::from_cols(Vec3::X, Vec3::Y, Vec3::Z);
+
+    pub const fn from_cols(x: Vec3, y: Vec3, z: Vec3) -> Mat3 {
+        Mat3 { cols: [x, y, z] }
+    }
+
+    pub const fn from_scale(scale: Vec3) -> Mat3 {
+        Mat3::from_cols(
+            Vec3::new(scale.x, 0.0, 0.0),
+            Vec3::new(0.0, scale.y, 0.0),
+            Vec3::new(0.0, 0.0, scale.z),
+        )
+    }
+
+    /// Rotation by `angle` radians about the X axis, anticlockwise looking down the axis.
+    pub fn rotation_x(angle: f32) -> Mat3 {
+        let (s, c) = (sinf(angle), cosf(angle));
+        Mat3::from_cols(Vec3::X, Vec3::new(0.0, c, s), Vec3::new(0.0, -s, c))
+    }
+
+    pub fn rotation_y(angle: f32) -> Mat3 {
+        let (s, c) = (sinf(angle), cosf(angle));
+        Mat3::from_cols(Vec3::new(c, 0.0, -s), Vec3::Y, Vec3::new(s, 0.0, c))
+    }
+
+    pub fn rotation_z(angle: f32) -> Mat3 {
+        let (s, c) = (sinf(angle), cosf(angle));
+        Mat3::from_cols(Vec3::new(c, s, 0.0), Vec3::new(-s, c, 0.0), Vec3::Z)
+    }
+
+    /// Rotation by `angle` radians about the unit vector `axis`.
+    pub fn from_axis_angle(axis: Vec3, angle: f32) -> Mat3 {
+        Quat::from_axis_angle(axis, angle).to_mat3()
+    }
+
+    pub fn row(&self, i: usize) -> Vec3 {
+        let [x, y, z] = self.cols;
+        match i {
+            0 => Vec3::new(x.x, y.x, z.x),
+            1 => Vec3::new(x.y, y.y, z.y),
+            _ => Vec3::new(x.z, y.z, z.z),
+        }
+    }
+
+    pub fn transpose(&self) -> Mat3 {
+        Mat3::from_cols(self.row(0), self.row(1), self.row(2))
+    }
+
+    pub fn determinant(&self) -> f32 {
+        let [x, y, z] = self.cols;
+        x.dot(y.cross(z))
+    }
+
+    /// `None` if the matrix is singular.
+    pub fn inverse(&self) -> Option<Mat3> {
+        let [x, y, z] = self.cols;
+        let det = self.determinant();
+        if det.abs() <= f32::EPSILON {
+            return None;
+        }
+        // The rows of the inverse are the cross products of pairs of columns.
+        Some(Mat3::from_cols(y.cross(z), z.cross(x), x.cross(y)).transpose() * (1.0 / det))
+    }
+}
+
+impl Default for Mat3 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Index<usize> for Mat3 {
+    type Output = Vec3;
+
+    /// Column `i`.
+    fn index(&self, i: usize) -> &Vec3 {
+        &self.cols[i]
+    }
+}
+
+impl Mul<Vec3> for Mat3 {
+    type Output = Vec3;
+    fn mul(self, v: Vec3) -> Vec3 {
+        let [x, y, z] = self.cols;
+        x * v.x + y * v.y + z * v.z
+    }
+}
+
+impl Mul for Mat3 {
+    type Output = Mat3;
+    fn mul(self, rhs: Mat3) -> Mat3 {
+        let [x, y, z] = rhs.cols;
+        Mat3::from_cols(self * x, self * y, self * z)
+    }
+}
+
+impl Mul<f32> for Mat3 {
+    type Output = Mat3;
+    fn mul(self, rhs: f32) -> Mat3 {
+        let [x, y, z] = self.cols;
+        Mat3::from_cols(x * rhs, y * rhs, z * rhs)
+    }
+}
+
+/// A 4x4 matrix, for transforms that include translation and projection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4 {
+    pub cols: [Vec4; 4],
+}
+
+impl Mat4 {
+    pub const IDENTITY: Mat4 = Mat4::from_cols(
+        Vec4::new(1.0, 0.0, 0.0, 0.0),
+        Vec4::new(0.0, 1.0, 0.0, 0.0),
+        Vec4::new(0.0, 0.0, 1.0, 0.0),
+        Vec4::new(0.0, 0.0, 0.0, 1.0),
+    );
+
+    pub const fn from_cols(x: Vec4, y: Vec4, z: Vec4, w: Vec4) -> Mat4 {
+        Mat4 { cols: [x, y, z, w] }
+    }
+
+    /// `linear` followed by moving by `translation`.
+    pub const fn from_mat3_translation(linear: Mat3, translation: Vec3) -> Mat4 {
+        let [x, y, z] = linear.cols;
+        Mat4::from_cols(
+            x.extend(0.0),
+            y.extend(0.0),
+            z.extend(0.0),
+            translation.extend(1.0),
+        )
+    }
+
+    pub const fn from_translation(translation: Vec3) -> Mat4 {
+        Mat4::from_mat3_translation(Mat3::IDENTITY, translation)
+    }
+
+    pub const fn from_scale(scale: Vec3) -> Mat4 {
+        Mat4::from_mat3_translation(Mat3::from_scale(scale), Vec3::ZERO)
+    }
+
+    pub fn from_quat(rotation: Quat) -> Mat4 {
+        Mat4::from_mat3_translation(rotation.to_mat3(), Vec3::ZERO)
+    }
+
+    /// View matrix for a camera at `eye` looking at `target`, with `up` roughly upwards. It
+    /// takes world coordinates to ones where the camera is at the origin looking down -Z.
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Mat4 {
+        let forward = (target - eye).norm();
+        let right = forward.cross(up).norm();
+        let up = right.cross(forward);
+        let rotation = Mat3::from_cols(right, up, -forward).transpose();
+        Mat4::from_mat3_translation(rotation, -(rotation * eye))
+    }
+
+    /// Perspective projection with a vertical field of view of `fov_y` radians and width over
+    /// height `aspect`. Points between `near` and `far` in front of the camera end up with Z
+    /// from -1 to 1 after dividing by W.
+    pub fn perspective(fov_y: f32, aspect: f32, near: f32, far: f32) -> Mat4 {
+        let f = 1.0 / tanf(fov_y * 0.5);
+        let depth = 1.0 / (near - far);
+        Mat4::from_cols(
+            Vec4::new(f / aspect, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, f, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, (far + near) * depth, -1.0),
+            Vec4::new(0.0, 0.0, 2.0 * far * near * depth, 0.0),
+        )
+    }
+
+    pub fn row(&self, i: usize) -> Vec4 {
+        let [x, y, z, w] = self.cols;
+        let pick = |v: Vec4| match i {
+            0 => v.x,
+            1 => v.y,
+            2 => v.z,
+            _ => v.w,
+        };
+        Vec4::new(pick(x), pick(y), pick(z), pick(w))
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        Mat4::from_cols(self.row(0), self.row(1), self.row(2), self.row(3))
+    }
+
+    /// Transforms a point, including translation and the divide by W.
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let v = *self * p.extend(1.0);
+        if v.w == 0.0 || v.w == 1.0 {
+            v.truncate()
+        } else {
+            v.truncate() / v.w
+        }
+    }
+
+    /// Transforms a direction, which translation does not affect.
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        (*self * v.extend(0.0)).truncate()
+    }
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Index<usize> for Mat4 {
+    type Output = Vec4;
+
+    /// Column `i`.
+    fn index(&self, i: usize) -> &Vec4 {
+        &self.cols[i]
+    }
+}
+
+impl Mul<Vec4> for Mat4 {
+    type Output = Vec4;
+    fn mul(self, v: Vec4) -> Vec4 {
+        let [x, y, z, w] = self.cols;
+        x * v.x + y * v.y + z * v.z + w * v.w
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let [x, y, z, w] = rhs.cols;
+        Mat4::from_cols(self * x, self * y, self * z, self * w)
+    }
+}
+
+/// A rotation as a unit quaternion `w + xi + yj + zk`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quat {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+impl Quat {
+    pub const IDENTITY: Quat = Quat::new(0.0, 0.0, 0.0, 1.0);
+
+    pub const fn new(x: f32, y: f32, z: f32, w: f32) -> Quat {
+        Quat { x, y, z, w }
+    }
+
+    /// Rotation by `angle` radians about the unit vector `axis`.
+    pub fn from_axis_angle(axis: Vec3, angle: f32) -> Quat {
+        let (s, c) = (sinf(angle * 0.5), cosf(angle * 0.5));
+        Quat::new(axis.x * s, axis.y * s, axis.z * s, c)
+    }
+
+    /// Turning by `yaw` about Y, then `pitch` about X, then `roll` about Z, each measured in
+    /// the already turned frame, which is how a camera or a plane is usually steered.
+    pub fn from_yaw_pitch_roll(yaw: f32, pitch: f32, roll: f32) -> Quat {
+        Quat::from_axis_angle(Vec3::Y, yaw)
+            * Quat::from_axis_angle(Vec3::X, pitch)
+            * Quat::from_axis_angle(Vec3::Z, roll)
+    }
+
+    fn as_vec4(self) -> Vec4 {
+        Vec4::new(self.x, self.y, self.z, self.w)
+    }
+
+    fn from_vec4(v: Vec4) -> Quat {
+        Quat::new(v.x, v.y, v.z, v.w)
+    }
+
+    pub fn dot(self, rhs: Quat) -> f32 {
+        self.as_vec4().dot(rhs.as_vec4())
+    }
+
+    /// Scaled back to unit length, undoing drift from repeated multiplication.
+    pub fn norm(self) -> Quat {
+        let v = self.as_vec4().norm();
+        if v == Vec4::ZERO {
+            Quat::IDENTITY
+        } else {
+            Quat::from_vec4(v)
+        }
+    }
+
+    /// The opposite rotation, for unit quaternions.
+    pub fn conjugate(self) -> Quat {
+        Quat::new(-self.x, -self.y, -self.z, self.w)
+    }
+
+    /// Turns `v`.
+    pub fn rotate(self, v: Vec3) -> Vec3 {
+        let q = Vec3::new(self.x, self.y, self.z);
+        let t = q.cross(v) * 2.0;
+        v + t * self.w + q.cross(t)
+    }
+
+    /// Spherical interpolation: `self` at `t` = 0, `other` at `t` = 1, turning at a steady
+    /// rate the short way round.
+    pub fn slerp(self, other: Quat, t: f32) -> Quat {
+        let (a, mut b) = (self.as_vec4(), other.as_vec4());
+        let mut cos = a.dot(b);
+        if cos < 0.0 {
+            b = -b;
+            cos = -cos;
+        }
+        // Nearly the same rotation: the sine below would be close to zero.
+        if cos > 0.9995 {
+            return Quat::from_vec4(a.lerp(b, t)).norm();
+        }
+        let angle = acosf(cos);
+        let sin = sinf(angle);
+        let wa = sinf((1.0 - t) * angle) / sin;
+        let wb = sinf(t * angle) / sin;
+        Quat::from_vec4(a * wa + b * wb)
+    }
+
+    pub fn to_mat3(self) -> Mat3 {
+        Mat3::from_cols(
+            self.rotate(Vec3::X),
+            self.rotate(Vec3::Y),
+            self.rotate(Vec3::Z),
+        )
+    }
+}
+
+impl Default for Quat {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// The rotation `rhs` followed by `self`.
+impl Mul for Quat {
+    type Output = Quat;
+    fn mul(self, rhs: Quat) -> Quat {
+        let (a, b) = (self, rhs);
+        Quat::new(
+            a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+            a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+            a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+            a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+        )
+    }
+}
+
+impl Mul<Vec3> for Quat {
+    type Output = Vec3;
+    fn mul(self, v: Vec3) -> Vec3 {
+        self.rotate(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f32::consts::FRAC_PI_2;
+
+    const EPSILON: f32 = 1e-5;
+
+    fn assert_vec3_near(actual: Vec3, expected: Vec3) {
+        assert!(
+            actual.distance(expected) < EPSILON,
+            "{actual:?} is not {expected:?}"
+        );
+    }
+
+    fn assert_mat3_near(actual: Mat3, expected: Mat3) {
+        for i in 0..3 {
+            assert_vec3_near(actual[i], expected[i]);
+        }
+    }
+
+    /// Whether `a` and `b` are the same rotation; `q` and `-q` both are.
+    fn assert_quat_near(a: Quat, b: Quat) {
+        assert!(1.0 - a.dot(b).abs() < EPSILON, "{a:?} is not {b:?}");
+    }
+
+    #[test]
+    fn mat3_inverse() {
+        let m = Mat3::from_cols(
+            Vec3::new(2.0, 0.5, -1.0),
+            Vec3::new(0.0, 3.0, 1.0),
+            Vec3::new(1.0, -2.0, 4.0),
+        );
+        let inverse = m.inverse().unwrap();
+        assert_mat3_near(inverse * m, Mat3::IDENTITY);
+        assert_mat3_near(m * inverse, Mat3::IDENTITY);
+
+        let rotation = Mat3::rotation_y(0.7) * Mat3::rotation_x(-1.2);
+        assert_mat3_near(rotation.inverse().unwrap(), rotation.transpose());
+
+        let singular = Mat3::from_cols(Vec3::X, Vec3::Y, Vec3::X + Vec3::Y);
+        assert_eq!(singular.inverse(), None);
+    }
+
+    #[test]
+    fn quat_rotation_matches_mat3() {
+        let v = Vec3::new(0.3, -1.2, 2.5);
+        for angle in [0.0, 0.4, FRAC_PI_2, 2.0, -2.9] {
+            let pairs = [
+                (Vec3::X, Mat3::rotation_x(angle)),
+                (Vec3::Y, Mat3::rotation_y(angle)),
+                (Vec3::Z, Mat3::rotation_z(angle)),
+            ];
+            for (axis, matrix) in pairs {
+                let quat = Quat::from_axis_angle(axis, angle);
+                assert_vec3_near(quat.rotate(v), matrix * v);
+                assert_mat3_near(quat.to_mat3(), matrix);
+            }
+        }
+
+        // Anticlockwise looking down the axis.
+        assert_vec3_near(Quat::from_axis_angle(Vec3::Z, FRAC_PI_2) * Vec3::X, Vec3::Y);
+
+        let a = Quat::from_axis_angle(Vec3::X, 0.6);
+        let b = Quat::from_axis_angle(Vec3::Y, -1.1);
+        assert_vec3_near((a * b) * v, a * (b * v));
+        assert_mat3_near(
+            (a * b).to_mat3(),
+            Mat3::rotation_x(0.6) * Mat3::rotation_y(-1.1),
+        );
+        assert_vec3_near(a.conjugate() * (a * v), v);
+    }
+
+    #[test]
+    fn slerp_endpoints() {
+        let a = Quat::from_axis_angle(Vec3::X, 0.3);
+        let b = Quat::from_axis_angle(Vec3::new(0.0, 0.6, 0.8), 2.2);
+        assert_quat_near(a.slerp(b, 0.0), a);
+        assert_quat_near(a.slerp(b, 1.0), b);
+        // Nearly equal rotations take the linear path.
+        let c = Quat::from_axis_angle(Vec3::X, 0.301);
+        assert_quat_near(a.slerp(c, 0.0), a);
+        assert_quat_near(a.slerp(c, 1.0), c);
+
+        let half = Quat::IDENTITY.slerp(Quat::from_axis_angle(Vec3::Z, 2.0), 0.5);
+        assert_quat_near(half, Quat::from_axis_angle(Vec3::Z, 1.0));
+    }
+
+    #[test]
+    fn refract() {
+        let n = Vec3::Y;
+        let down = -Vec3::Y;
+        // Straight on, the ray goes through unbent.
+        assert_vec3_near(down.refract(n, 1.0 / 1.5).unwrap(), down);
+
+        // At 45 degrees into glass, the sine of the angle shrinks by the ratio.
+        let d = Vec3::new(1.0, -1.0, 0.0).norm();
+        let eta = 1.0 / 1.5;
+        let out = d.refract(n, eta).unwrap();
+        assert!((out.len() - 1.0).abs() < EPSILON);
+        assert!((out.x - d.x * eta).abs() < EPSILON);
+
+        // Leaving glass at 45 degrees is past the critical angle of about 42.
+        assert_eq!(d.refract(n, 1.5), None);
+        let steep = Vec3::new(0.5, -1.0, 0.0).norm();
+        assert!(steep.refract(n, 1.5).is_some());
+    }
+
+    #[test]
+    fn look_at() {
+        let eye = Vec3::new(1.0, 2.0, 3.0);
+        let target = Vec3::new(4.0, 2.0, -1.0);
+        let view = Mat4::look_at(eye, target, Vec3::Y);
+        assert_vec3_near(view.transform_point(eye), Vec3::ZERO);
+        // The target is straight ahead, down -Z.
+        assert_vec3_near(view.transform_point(target), Vec3::new(0.0, 0.0, -5.0));
+        assert_vec3_near(view.transform_vector(Vec3::Y), Vec3::Y);
+    }
+
+    #[test]
+    fn perspective() {
+        let (near, far) = (0.5, 20.0);
+        let projection = Mat4::perspective(FRAC_PI_2, 2.0, near, far);
+        assert!((projection.transform_point(Vec3::new(0.0, 0.0, -near)).z + 1.0).abs() < EPSILON);
+        assert!((projection.transform_point(Vec3::new(0.0, 0.0, -far)).z - 1.0).abs() < EPSILON);
+        // With a 90 degree field of view the top edge is as high as it is far away, and the
+        // right edge twice that.
+        let corner = projection.transform_point(Vec3::new(6.0, 3.0, -3.0));
+        assert!((corner.x - 1.0).abs() < EPSILON && (corner.y - 1.0).abs() < EPSILON);
+
+        let view = Mat4::look_at(Vec3::new(0.0, 0.0, 5.0), Vec3::ZERO, Vec3::Y);
+        let centre = (projection * view).transform_point(Vec3::ZERO);
+        assert!(centre.x.abs() < EPSILON && centre.y.abs() < EPSILON);
+        assert!(centre.z > -1.0 && centre.z < 1.0);
+    }
+}
@@ -0,0 +1,186 @@
+use log::info;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+use crate::interupts::{self, InterruptIndex};
+
+/// PS/2 controller data port, shared by the keyboard and the mouse.
+const DATA_PORT: u16 = 0x60;
+/// PS/2 controller status register when read, command register when written.
+const COMMAND_PORT: u16 = 0x64;
+
+/// Status bit: a byte is waiting in the data port.
+const OUTPUT_FULL: u8 = 1 << 0;
+/// Status bit: the controller has not taken the last byte written yet.
+const INPUT_FULL: u8 = 1 << 1;
+
+/// Controller configuration bit: raise IRQ 12 for mouse bytes.
+const CONFIG_MOUSE_IRQ: u8 = 1 << 1;
+/// Controller configuration bit: the mouse clock is off.
+const CONFIG_MOUSE_CLOCK_OFF: u8 = 1 << 5;
+
+/// Status polls before giving up on the controller, so a machine without one does not hang.
+const POLL_LIMIT: u32 = 100_000;
+
+/// Byte the mouse answers accepted commands with.
+const ACK: u8 = 0xFA;
+
+static MOUSE: Mutex<Mouse> = Mutex::new(Mouse::new());
+
+/// Mouse movement since it was last taken, and the buttons held now.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Motion {
+    /// Counts to the right.
+    pub dx: i32,
+    /// Counts upwards, the PS/2 convention.
+    pub dy: i32,
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// Packet assembly and the motion collected from finished packets.
+struct Mouse {
+    packet: [u8; 3],
+    len: usize,
+    motion: Motion,
+}
+
+impl Mouse {
+    const fn new() -> Mouse {
+        Mouse {
+            packet: [0; 3],
+            len: 0,
+            motion: Motion {
+                dx: 0,
+                dy: 0,
+                left: false,
+                right: false,
+                middle: false,
+            },
+        }
+    }
+
+    fn add_byte(&mut self, byte: u8) {
+        // Bit 3 is always set in the first byte of a packet. Anything else there means a byte
+        // was lost, and skipping until it lines up again resynchronises.
+        if self.len == 0 && byte & 0x08 == 0 {
+            return;
+        }
+        self.packet[self.len] = byte;
+        self.len += 1;
+        if self.len < self.packet.len() {
+            return;
+        }
+        self.len = 0;
+
+        let [flags, x, y] = self.packet;
+        self.motion.left = flags & 0x01 != 0;
+        self.motion.right = flags & 0x02 != 0;
+        self.motion.middle = flags & 0x04 != 0;
+        // The overflow bits leave the movement meaningless, so it is dropped.
+        if flags & 0xC0 != 0 {
+            return;
+        }
+        // Movement is 9-bit two's complement, with the sign bits in the first byte.
+        let dx = i32::from(x) - if flags & 0x10 != 0 { 256 } else { 0 };
+        let dy = i32::from(y) - if flags & 0x20 != 0 { 256 } else { 0 };
+        self.motion.dx = self.motion.dx.saturating_add(dx);
+        self.motion.dy = self.motion.dy.saturating_add(dy);
+    }
+}
+
+/// Turns on the PS/2 mouse port and has the mouse start sending movement. Does nothing more
+/// than log if there is no mouse. Must run with interrupts off.
+pub fn init() {
+    if !enable() {
+        info!("mouse: no PS/2 mouse");
+        return;
+    }
+    interupts::unmask_irq(InterruptIndex::Mouse);
+    info!("mouse: PS/2 mouse enabled");
+}
+
+fn enable() -> bool {
+    // Drop stale bytes, so the replies read below are the ones asked for.
+    let mut status: Port<u8> = Port::new(COMMAND_PORT);
+    for _ in 0..16 {
+        if unsafe { status.read() } & OUTPUT_FULL == 0 {
+            break;
+        }
+        let _: u8 = unsafe { Port::new(DATA_PORT).read() };
+    }
+
+    // Enable the second PS/2 port.
+    if !write_command(0xA8) {
+        return false;
+    }
+
+    // Turn on its interrupt and its clock.
+    if !write_command(0x20) {
+        return false;
+    }
+    let Some(config) = read_data() else {
+        return false;
+    };
+    let config = (config | CONFIG_MOUSE_IRQ) & !CONFIG_MOUSE_CLOCK_OFF;
+    if !write_command(0x60) || !write_data(config) {
+        return false;
+    }
+
+    // Default settings, then start streaming movement packets.
+    send_to_mouse(0xF6) && send_to_mouse(0xF4)
+}
+
+/// Sends `byte` to the mouse rather than the keyboard and waits for it to be acknowledged.
+fn send_to_mouse(byte: u8) -> bool {
+    write_command(0xD4) && write_data(byte) && read_data() == Some(ACK)
+}
+
+fn write_command(command: u8) -> bool {
+    wait_status(INPUT_FULL, false) && {
+        unsafe { Port::new(COMMAND_PORT).write(command) };
+        true
+    }
+}
+
+fn write_data(byte: u8) -> bool {
+    wait_status(INPUT_FULL, false) && {
+        unsafe { Port::new(DATA_PORT).write(byte) };
+        true
+    }
+}
+
+fn read_data() -> Option<u8> {
+    wait_status(OUTPUT_FULL, true).then(|| unsafe { Port::new(DATA_PORT).read() })
+}
+
+/// Polls the status register until `bit` is `set`, or gives up after `POLL_LIMIT` tries.
+fn wait_status(bit: u8, set: bool) -> bool {
+    let mut status: Port<u8> = Port::new(COMMAND_PORT);
+    (0..POLL_LIMIT).any(|_| {
+        let ready = (unsafe { status.read() } & bit != 0) == set;
+        if !ready {
+            core::hint::spin_loop();
+        }
+        ready
+    })
+}
+
+/// Reads the byte the mouse sent. Called from the mouse interrupt.
+pub fn handle_interrupt() {
+    let byte: u8 = unsafe { Port::new(DATA_PORT).read() };
+    MOUSE.lock().add_byte(byte);
+}
+
+/// Movement since the last call, with the current buttons.
+pub fn take_motion() -> Motion {
+    without_interrupts(|| {
+        let mut mouse = MOUSE.lock();
+        let motion = mouse.motion;
+        mouse.motion.dx = 0;
+        mouse.motion.dy = 0;
+        motion
+    })
+}
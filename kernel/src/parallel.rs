@@ -0,0 +1,106 @@
+//! Spreading work over CPUs. `smp` starts the application processors (APs), which then wait in
+//! `ap_main` for work from `run_on_all_cpus`.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::Mutex;
+
+/// CPUs taking part in `run_on_all_cpus`, counting the boot CPU.
+static ONLINE_CPUS: AtomicUsize = AtomicUsize::new(1);
+
+/// The work being handed out and how many CPUs take part, or `None` between runs.
+static JOB: Mutex<Option<Job>> = Mutex::new(None);
+
+/// Bumped for every new job, which is what parked APs watch for.
+static JOB_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Held for a whole run, so only one is in flight.
+static DISPATCH: Mutex<()> = Mutex::new(());
+
+/// Static rather than on the caller's stack: CPUs can still be spinning on it after the last
+/// one has arrived and the caller has returned.
+static BARRIER: Barrier = Barrier::new();
+
+#[derive(Clone, Copy)]
+struct Job {
+    /// Only borrowed for the duration of `run_on_all_cpus`, which waits for every CPU to be
+    /// done with it before returning.
+    work: &'static (dyn Fn(usize) + Sync),
+    cpus: usize,
+}
+
+/// Number of CPUs work is spread over.
+pub fn online_cpus() -> usize {
+    ONLINE_CPUS.load(Ordering::Acquire)
+}
+
+/// Runs `work` on every online CPU at once, passing each its CPU number (0 is the boot CPU),
+/// and returns once all of them have finished.
+pub fn run_on_all_cpus(work: &(dyn Fn(usize) + Sync)) {
+    let _dispatch = DISPATCH.lock();
+    let cpus = online_cpus();
+    if cpus > 1 {
+        // SAFETY: the barrier below keeps `work` alive until every CPU has returned from it.
+        let work: &'static (dyn Fn(usize) + Sync) = unsafe { core::mem::transmute(work) };
+        *JOB.lock() = Some(Job { work, cpus });
+        JOB_GENERATION.fetch_add(1, Ordering::Release);
+    }
+
+    work(0);
+
+    if cpus > 1 {
+        BARRIER.wait(cpus);
+        *JOB.lock() = None;
+    }
+}
+
+/// Where an application processor goes once it is running 64-bit kernel code with its own
+/// stack, GDT and IDT. It registers itself and then waits for work from `run_on_all_cpus`.
+pub fn ap_main() -> ! {
+    // Read before registering, so a job that counts this CPU always looks new to it.
+    let mut seen = JOB_GENERATION.load(Ordering::Acquire);
+    let cpu = ONLINE_CPUS.fetch_add(1, Ordering::AcqRel);
+    loop {
+        let generation = JOB_GENERATION.load(Ordering::Acquire);
+        if generation == seen {
+            core::hint::spin_loop();
+            continue;
+        }
+        seen = generation;
+
+        let job = *JOB.lock();
+        // A CPU that came up after the job was handed out is not counted by the barrier.
+        if let Some(job) = job.filter(|job| cpu < job.cpus) {
+            (job.work)(cpu);
+            BARRIER.wait(job.cpus);
+        }
+    }
+}
+
+/// Spin barrier that can be reused straight away: the last CPU to arrive starts a new
+/// generation, which is what the others wait for.
+struct Barrier {
+    arrived: AtomicUsize,
+    generation: AtomicUsize,
+}
+
+impl Barrier {
+    const fn new() -> Barrier {
+        Barrier {
+            arrived: AtomicUsize::new(0),
+            generation: AtomicUsize::new(0),
+        }
+    }
+
+    fn wait(&self, parties: usize) {
+        let generation = self.generation.load(Ordering::Acquire);
+        if self.arrived.fetch_add(1, Ordering::AcqRel) + 1 == parties {
+            self.arrived.store(0, Ordering::Relaxed);
+            self.generation.fetch_add(1, Ordering::Release);
+        } else {
+            while self.generation.load(Ordering::Acquire) == generation {
+                core::hint::spin_loop();
+            }
+        }
+    }
+}
@@ -7,6 +7,13 @@ pub fn pseudo_rand() -> u64 {
     }
 }
 
+/// Restarts the sequence from `seed`, e.g. the timer tick count when a game starts.
+pub fn seed(seed: u64) {
+    unsafe {
+        RNG_STATE = seed;
+    }
+}
+
 use core::ops::Range;
 
 pub fn pseudo_rand_in_range_i32(range: Range<i32>) -> i32 {
@@ -0,0 +1,259 @@
+use core::fmt::{self, Write};
+
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::framebuffer::Renderer;
+use crate::serial::SERIAL1;
+
+/// Starts a frame: `@@SCREENSHOT BEGIN <width> <height> <bytes>`, where `bytes` is the size of
+/// the decoded PPM. The host tool in the launcher looks for these markers.
+pub const BEGIN_MARKER: &str = "@@SCREENSHOT BEGIN";
+
+/// Ends a frame: `@@SCREENSHOT END <crc32>`, the CRC-32 of the decoded PPM in hex.
+pub const END_MARKER: &str = "@@SCREENSHOT END";
+
+/// Base64 characters per line between the markers.
+pub const LINE_LEN: usize = 76;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Sends what `renderer` holds over COM1 as a binary PPM, base64 encoded between a begin and
+/// an end line so it survives a terminal and can be picked out of the rest of the serial output.
+pub fn send(renderer: &Renderer) {
+    let width = renderer.width().max(0) as usize;
+    let height = renderer.height().max(0) as usize;
+    log::info!("screenshot: sending {}x{}", width, height);
+    write_frame(
+        width,
+        height,
+        |x, y| {
+            let color = renderer.get_pixel(x as i32, y as i32);
+            [color.r, color.g, color.b]
+        },
+        write_line,
+    );
+}
+
+/// Encodes a `width` by `height` frame with RGB pixels from `pixel`, handing `line` each line
+/// of the output without its newline.
+fn write_frame(
+    width: usize,
+    height: usize,
+    pixel: impl Fn(usize, usize) -> [u8; 3],
+    mut line: impl FnMut(fmt::Arguments),
+) {
+    let mut header = HeaderBuffer::new();
+    let _ = write!(header, "P6\n{} {}\n255\n", width, height);
+    let size = header.len + width * height * 3;
+
+    line(format_args!(
+        "\n{} {} {} {}",
+        BEGIN_MARKER, width, height, size
+    ));
+    let mut encoder = Encoder::new(&mut line);
+    encoder.push_all(header.as_bytes());
+    for y in 0..height {
+        for x in 0..width {
+            encoder.push_all(&pixel(x, y));
+        }
+    }
+    let crc = encoder.finish();
+    line(format_args!("{} {:08x}", END_MARKER, crc));
+}
+
+/// One line straight to the UART, bypassing the logger. Interrupts stay off only for the line,
+/// so the timer keeps running during a long transfer.
+fn write_line(args: fmt::Arguments) {
+    without_interrupts(|| {
+        let mut port = SERIAL1.lock();
+        let _ = port.write_fmt(args);
+        let _ = port.write_str("\n");
+    });
+}
+
+/// Room for `P6\n<width> <height>\n255\n`.
+struct HeaderBuffer {
+    bytes: [u8; 32],
+    len: usize,
+}
+
+impl HeaderBuffer {
+    const fn new() -> HeaderBuffer {
+        HeaderBuffer {
+            bytes: [0; 32],
+            len: 0,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl Write for HeaderBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.bytes
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Base64 encodes bytes as they come, handing `out` a line at a time, and keeps a CRC-32 of
+/// them.
+struct Encoder<F: FnMut(fmt::Arguments)> {
+    /// Up to two bytes waiting for a third.
+    pending: [u8; 3],
+    pending_len: usize,
+    line: [u8; LINE_LEN],
+    line_len: usize,
+    crc: u32,
+    out: F,
+}
+
+impl<F: FnMut(fmt::Arguments)> Encoder<F> {
+    fn new(out: F) -> Self {
+        Encoder {
+            pending: [0; 3],
+            pending_len: 0,
+            line: [0; LINE_LEN],
+            line_len: 0,
+            crc: 0xFFFF_FFFF,
+            out,
+        }
+    }
+
+    fn push_all(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push(byte);
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.crc = crc32_update(self.crc, byte);
+        self.pending[self.pending_len] = byte;
+        self.pending_len += 1;
+        if self.pending_len == 3 {
+            self.encode_pending();
+        }
+    }
+
+    /// Encodes the pending bytes, padding with `=` if there are fewer than three.
+    fn encode_pending(&mut self) {
+        let [a, b, c] = self.pending;
+        let group = (u32::from(a) << 16) | (u32::from(b) << 8) | u32::from(c);
+        for i in 0..4 {
+            let char = if i <= self.pending_len {
+                BASE64[(group >> (18 - 6 * i)) as usize & 0x3F]
+            } else {
+                b'='
+            };
+            self.emit(char);
+        }
+        self.pending = [0; 3];
+        self.pending_len = 0;
+    }
+
+    fn emit(&mut self, char: u8) {
+        self.line[self.line_len] = char;
+        self.line_len += 1;
+        if self.line_len == LINE_LEN {
+            self.flush_line();
+        }
+    }
+
+    fn flush_line(&mut self) {
+        if self.line_len == 0 {
+            return;
+        }
+        // Only base64 characters go into `line`.
+        let text = core::str::from_utf8(&self.line[..self.line_len]).unwrap_or_default();
+        (self.out)(format_args!("{}", text));
+        self.line_len = 0;
+    }
+
+    /// Sends whatever is left and returns the CRC-32 of everything pushed.
+    fn finish(mut self) -> u32 {
+        if self.pending_len > 0 {
+            self.encode_pending();
+        }
+        self.flush_line();
+        !self.crc
+    }
+}
+
+/// One byte of the reflected CRC-32 used by zlib and PNG.
+fn crc32_update(crc: u32, byte: u8) -> u32 {
+    let mut crc = crc ^ u32::from(byte);
+    for _ in 0..8 {
+        let mask = (crc & 1).wrapping_neg();
+        crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `send` writes to the serial line for an 8x4 frame of `sample_pixel`. The launcher's
+    /// tests extract the same file.
+    const SAMPLE_STREAM: &str = include_str!("../images/screenshot-sample.txt");
+
+    fn sample_pixel(x: usize, y: usize) -> [u8; 3] {
+        [x as u8 * 32, y as u8 * 64, 0x80]
+    }
+
+    /// The lines `bytes` encode to and their CRC-32.
+    fn encode(bytes: &[u8]) -> (Vec<String>, u32) {
+        let mut lines = Vec::new();
+        let mut encoder = Encoder::new(|args: fmt::Arguments| lines.push(args.to_string()));
+        encoder.push_all(bytes);
+        let crc = encoder.finish();
+        (lines, crc)
+    }
+
+    fn frame(width: usize, height: usize, pixel: impl Fn(usize, usize) -> [u8; 3]) -> String {
+        let mut stream = String::new();
+        write_frame(width, height, pixel, |args| {
+            stream.push_str(&args.to_string());
+            stream.push('\n');
+        });
+        stream
+    }
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(encode(b"").0, [""; 0]);
+        assert_eq!(encode(b"Man").0, ["TWFu"]);
+        assert_eq!(encode(b"Ma").0, ["TWE="]);
+        assert_eq!(encode(b"M").0, ["TQ=="]);
+        assert_eq!(encode(&[0xfb, 0xff, 0xbf]).0, ["+/+/"]);
+    }
+
+    #[test]
+    fn splits_base64_into_lines() {
+        let (lines, _) = encode(&[0; 57]);
+        assert_eq!(lines, ["A".repeat(LINE_LEN)]);
+        let (lines, _) = encode(&[0; 58]);
+        assert_eq!(lines, ["A".repeat(LINE_LEN), "AA==".to_string()]);
+    }
+
+    #[test]
+    fn crc32_matches_zlib() {
+        assert_eq!(encode(b"").1, 0);
+        assert_eq!(encode(b"123456789").1, 0xCBF4_3926);
+    }
+
+    #[test]
+    fn frames_a_ppm() {
+        assert_eq!(
+            frame(1, 1, |_, _| [1, 2, 3]),
+            "\n@@SCREENSHOT BEGIN 1 1 14\nUDYKMSAxCjI1NQoBAgM=\n@@SCREENSHOT END 72e29b64\n"
+        );
+        assert_eq!(frame(8, 4, sample_pixel), SAMPLE_STREAM);
+    }
+}
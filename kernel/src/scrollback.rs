@@ -0,0 +1,52 @@
+/// Ring buffer holding the last `LINES` lines that scrolled off the top of a console.
+/// Once full, pushing a line drops the oldest one.
+pub struct Scrollback<T: Copy, const WIDTH: usize, const LINES: usize> {
+    lines: [[T; WIDTH]; LINES],
+    oldest: usize,
+    len: usize,
+}
+
+impl<T: Copy, const WIDTH: usize, const LINES: usize> Scrollback<T, WIDTH, LINES> {
+    pub const fn new(blank: T) -> Self {
+        Self {
+            lines: [[blank; WIDTH]; LINES],
+            oldest: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, line: &[T; WIDTH]) {
+        if LINES == 0 {
+            return;
+        }
+
+        let slot = (self.oldest + self.len) % LINES;
+        self.lines[slot] = *line;
+        if self.len < LINES {
+            self.len += 1;
+        } else {
+            self.oldest = (self.oldest + 1) % LINES;
+        }
+    }
+
+    /// Line `index` counted from the oldest one still held.
+    pub fn get(&self, index: usize) -> Option<&[T; WIDTH]> {
+        if index >= self.len {
+            return None;
+        }
+        Some(&self.lines[(self.oldest + index) % LINES])
+    }
+
+    pub fn clear(&mut self) {
+        self.oldest = 0;
+        self.len = 0;
+    }
+}
@@ -0,0 +1,635 @@
+use libm::{expf, floorf, powf, sqrtf};
+
+use crate::math::{Mat3, Vec3};
+
+/// Most nodes, objects and combinations together, a `Scene` holds.
+pub const MAX_NODES: usize = 32;
+pub const MAX_MATERIALS: usize = 16;
+pub const MAX_LIGHTS: usize = 4;
+
+/// March steps for camera and reflection rays.
+const MAX_STEPS: usize = 80;
+/// March steps for shadow rays, which only need to find out whether something is in the way.
+const SHADOW_STEPS: usize = 24;
+/// Points sampled along the normal for ambient occlusion.
+const AO_SAMPLES: usize = 4;
+/// Reflection rays followed from a reflective surface, and from what they hit.
+const MAX_BOUNCES: usize = 1;
+
+/// Rays give up past this distance and see the sky.
+const MAX_DISTANCE: f32 = 30.0;
+/// Closer than this to a surface counts as a hit.
+const HIT_EPSILON: f32 = 0.002;
+/// Softness of shadow edges; larger is harder.
+const SHADOW_HARDNESS: f32 = 12.0;
+/// Fog thickness. Distant surfaces fade into the sky.
+const FOG_DENSITY: f32 = 0.0025;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    Sphere {
+        radius: f32,
+    },
+    /// A box with half-extents `half`, its edges rounded off by `radius`.
+    Box {
+        half: Vec3,
+        radius: f32,
+    },
+    /// A ring lying in the XZ plane: `major` is the ring's radius, `minor` the tube's.
+    Torus {
+        major: f32,
+        minor: f32,
+    },
+    /// The half-space below the plane through the object's position with normal `normal`.
+    Plane {
+        normal: Vec3,
+    },
+}
+
+impl Shape {
+    /// Signed distance from `p`, relative to the shape's centre, to the surface.
+    fn distance(&self, p: Vec3) -> f32 {
+        match *self {
+            Shape::Sphere { radius } => p.len() - radius,
+            Shape::Box { half, radius } => {
+                let q = p.abs() - half + Vec3::splat(radius);
+                q.max(Vec3::ZERO).len() + q.max_element().min(0.0) - radius
+            }
+            Shape::Torus { major, minor } => {
+                let ring = sqrtf(p.x * p.x + p.z * p.z) - major;
+                sqrtf(ring * ring + p.y * p.y) - minor
+            }
+            Shape::Plane { normal } => p.dot(normal),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+/// Index of a material added with `Scene::add_material`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaterialId(u8);
+
+/// Index of a node added with `Scene::add` or `Scene::combine`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeId(u8);
+
+/// A shape placed in the scene.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Object {
+    pub shape: Shape,
+    pub position: Vec3,
+    pub material: MaterialId,
+    /// Takes points from world space into the object's own, undoing its rotation.
+    to_local: Option<Mat3>,
+}
+
+impl Object {
+    pub const fn new(shape: Shape, position: Vec3, material: MaterialId) -> Object {
+        Object {
+            shape,
+            position,
+            material,
+            to_local: None,
+        }
+    }
+
+    /// The object turned by `angle` radians about `axis` through its position.
+    pub fn rotated(self, axis: Axis, angle: f32) -> Object {
+        let rotation = match axis {
+            Axis::X => Mat3::rotation_x(angle),
+            Axis::Y => Mat3::rotation_y(angle),
+            Axis::Z => Mat3::rotation_z(angle),
+        };
+        self.rotated_by(rotation)
+    }
+
+    /// The object turned by the rotation matrix `rotation` about its position.
+    pub fn rotated_by(mut self, rotation: Mat3) -> Object {
+        // A rotation's inverse is its transpose.
+        self.to_local = Some(rotation.transpose());
+        self
+    }
+
+    fn distance(&self, p: Vec3) -> f32 {
+        let local = p - self.position;
+        let local = match self.to_local {
+            Some(to_local) => to_local * local,
+            None => local,
+        };
+        self.shape.distance(local)
+    }
+}
+
+/// How `Scene::combine` joins two nodes. The smooth variants blend the surfaces over a
+/// distance of about `k` instead of leaving a sharp crease.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Union,
+    Intersect,
+    /// The first node with the second cut out of it.
+    Subtract,
+    SmoothUnion(f32),
+    SmoothSubtract(f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Node {
+    Object(Object),
+    Combine { op: Op, a: NodeId, b: NodeId },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Material {
+    pub albedo: Vec3,
+    /// Second colour of a 1-unit checkerboard in the XZ plane, for floors.
+    pub checker: Option<Vec3>,
+    /// Strength of the specular highlight.
+    pub specular: f32,
+    pub shininess: f32,
+    /// How much of the reflected scene shows, from 0 to 1.
+    pub reflectivity: f32,
+}
+
+impl Material {
+    /// A matte surface of colour `albedo` with a faint highlight.
+    pub const fn new(albedo: Vec3) -> Material {
+        Material {
+            albedo,
+            checker: None,
+            specular: 0.4,
+            shininess: 28.0,
+            reflectivity: 0.0,
+        }
+    }
+
+    fn albedo_at(&self, p: Vec3) -> Vec3 {
+        match self.checker {
+            Some(other) if (floorf(p.x) + floorf(p.z)) as i32 & 1 != 0 => other,
+            _ => self.albedo,
+        }
+    }
+}
+
+/// A point light.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+    pub position: Vec3,
+    pub color: Vec3,
+}
+
+/// Where a ray met the scene.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub t: f32,
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub material: MaterialId,
+}
+
+/// A signed distance field built from objects joined by `Op`s, with the materials and lights
+/// to shade it. Everything lives in fixed-size arrays, so building a scene every frame is cheap.
+///
+/// Objects and combinations share `MAX_NODES` slots, and there is room for `MAX_MATERIALS`
+/// materials and `MAX_LIGHTS` lights. Adding past those limits fails rather than grows.
+#[derive(Debug, Clone, Copy)]
+pub struct Scene {
+    nodes: [Node; MAX_NODES],
+    node_count: usize,
+    root: Option<NodeId>,
+    materials: [Material; MAX_MATERIALS],
+    material_count: usize,
+    lights: [Light; MAX_LIGHTS],
+    light_count: usize,
+    /// Light reaching surfaces from everywhere, before ambient occlusion.
+    pub ambient: Vec3,
+    /// Sky colour straight up; it darkens towards the ground.
+    pub sky: Vec3,
+}
+
+impl Scene {
+    pub const fn new() -> Scene {
+        const EMPTY: Node = Node::Object(Object::new(
+            Shape::Sphere { radius: 0.0 },
+            Vec3::ZERO,
+            MaterialId(0),
+        ));
+        Scene {
+            nodes: [EMPTY; MAX_NODES],
+            node_count: 0,
+            root: None,
+            materials: [Material::new(Vec3::ZERO); MAX_MATERIALS],
+            material_count: 0,
+            lights: [Light {
+                position: Vec3::ZERO,
+                color: Vec3::ZERO,
+            }; MAX_LIGHTS],
+            light_count: 0,
+            ambient: Vec3::splat(0.08),
+            sky: Vec3::new(0.02, 0.03, 0.08),
+        }
+    }
+
+    /// `None` if `MAX_MATERIALS` are already in use.
+    pub fn add_material(&mut self, material: Material) -> Option<MaterialId> {
+        if self.material_count == MAX_MATERIALS {
+            return None;
+        }
+        self.materials[self.material_count] = material;
+        self.material_count += 1;
+        Some(MaterialId(self.material_count as u8 - 1))
+    }
+
+    /// `None` if `MAX_LIGHTS` are already in use.
+    pub fn add_light(&mut self, light: Light) -> Option<()> {
+        if self.light_count == MAX_LIGHTS {
+            return None;
+        }
+        self.lights[self.light_count] = light;
+        self.light_count += 1;
+        Some(())
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights[..self.light_count]
+    }
+
+    /// Adds an object. It becomes the root if it is the first node. `None` if the scene is full.
+    pub fn add(&mut self, object: Object) -> Option<NodeId> {
+        self.push(Node::Object(object))
+    }
+
+    /// Joins two nodes and makes the result the root. `None` if the scene is full.
+    pub fn combine(&mut self, op: Op, a: NodeId, b: NodeId) -> Option<NodeId> {
+        let id = self.push(Node::Combine { op, a, b })?;
+        self.root = Some(id);
+        Some(id)
+    }
+
+    /// Makes `node` the one the scene is drawn from.
+    pub fn set_root(&mut self, node: NodeId) {
+        self.root = Some(node);
+    }
+
+    fn push(&mut self, node: Node) -> Option<NodeId> {
+        if self.node_count == MAX_NODES {
+            return None;
+        }
+        self.nodes[self.node_count] = node;
+        self.node_count += 1;
+        let id = NodeId(self.node_count as u8 - 1);
+        self.root.get_or_insert(id);
+        Some(id)
+    }
+
+    /// Distance from `p` to the nearest surface; infinite for an empty scene.
+    pub fn distance(&self, p: Vec3) -> f32 {
+        self.sample(p).0
+    }
+
+    fn sample(&self, p: Vec3) -> (f32, MaterialId) {
+        match self.root {
+            Some(root) => self.sample_node(root, p),
+            None => (f32::INFINITY, MaterialId(0)),
+        }
+    }
+
+    fn sample_node(&self, node: NodeId, p: Vec3) -> (f32, MaterialId) {
+        match self.nodes[node.0 as usize] {
+            Node::Object(object) => (object.distance(p), object.material),
+            Node::Combine { op, a, b } => {
+                let (da, ma) = self.sample_node(a, p);
+                let (db, mb) = self.sample_node(b, p);
+                let nearer = if da <= db { ma } else { mb };
+                match op {
+                    Op::Union => (da.min(db), nearer),
+                    Op::Intersect => (da.max(db), if da >= db { ma } else { mb }),
+                    Op::Subtract => (da.max(-db), ma),
+                    Op::SmoothUnion(k) => {
+                        let h = (0.5 + 0.5 * (db - da) / k).clamp(0.0, 1.0);
+                        (db + (da - db) * h - k * h * (1.0 - h), nearer)
+                    }
+                    Op::SmoothSubtract(k) => {
+                        let h = (0.5 - 0.5 * (da + db) / k).clamp(0.0, 1.0);
+                        (da + (-db - da) * h + k * h * (1.0 - h), ma)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sphere traces from `ro` along the unit direction `rd`.
+    pub fn march(&self, ro: Vec3, rd: Vec3) -> Option<Hit> {
+        let mut t = 0.0;
+        for _ in 0..MAX_STEPS {
+            let position = ro + rd * t;
+            let (d, material) = self.sample(position);
+            // The hit threshold grows with distance, as a pixel covers more of the scene there.
+            if d < HIT_EPSILON * (1.0 + t) {
+                return Some(Hit {
+                    t,
+                    position,
+                    normal: self.normal(position),
+                    material,
+                });
+            }
+            t += d;
+            if t > MAX_DISTANCE {
+                break;
+            }
+        }
+        None
+    }
+
+    /// Surface normal at `p` from the field's gradient, using four samples on a tetrahedron.
+    fn normal(&self, p: Vec3) -> Vec3 {
+        const E: f32 = 0.001;
+        let k = [
+            Vec3::new(1.0, -1.0, -1.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        ];
+        k.iter()
+            .fold(Vec3::ZERO, |n, &k| n + k * self.distance(p + k * E))
+            .norm()
+    }
+
+    /// How much light gets from `p` to something `max_t` away along `dir`, from 0 in full
+    /// shadow to 1, with a penumbra where the ray only just misses something.
+    pub fn soft_shadow(&self, p: Vec3, dir: Vec3, max_t: f32) -> f32 {
+        let mut light = 1.0f32;
+        let mut t = 0.02;
+        for _ in 0..SHADOW_STEPS {
+            if t >= max_t {
+                break;
+            }
+            let d = self.distance(p + dir * t);
+            if d < HIT_EPSILON {
+                return 0.0;
+            }
+            light = light.min(SHADOW_HARDNESS * d / t);
+            t += d.clamp(0.02, 0.5);
+        }
+        light.clamp(0.0, 1.0)
+    }
+
+    /// 1 for an open surface, less in creases and corners where nearby geometry blocks the
+    /// ambient light.
+    pub fn ambient_occlusion(&self, p: Vec3, n: Vec3) -> f32 {
+        let mut occlusion = 0.0;
+        let mut weight = 1.0;
+        for i in 1..=AO_SAMPLES {
+            let h = 0.04 + 0.12 * i as f32;
+            occlusion += (h - self.distance(p + n * h)) * weight;
+            weight *= 0.6;
+        }
+        (1.0 - 2.5 * occlusion).clamp(0.0, 1.0)
+    }
+
+    /// Sky colour seen in direction `rd`.
+    pub fn sky(&self, rd: Vec3) -> Vec3 {
+        let up = (0.5 + 0.5 * rd.y).clamp(0.0, 1.0);
+        self.sky * (0.15 + 0.85 * up)
+    }
+
+    /// Colour seen from `ro` looking along the unit direction `rd`.
+    pub fn trace(&self, ro: Vec3, rd: Vec3) -> Vec3 {
+        self.trace_bounce(ro, rd, 0)
+    }
+
+    fn trace_bounce(&self, ro: Vec3, rd: Vec3, bounce: usize) -> Vec3 {
+        let Some(hit) = self.march(ro, rd) else {
+            return self.sky(rd);
+        };
+        let mut color = shade_hit(self, rd, &hit);
+
+        let reflectivity = self.materials[hit.material.0 as usize].reflectivity;
+        if reflectivity > 0.0 && bounce < MAX_BOUNCES {
+            let dir = rd.reflect(hit.normal);
+            let start = hit.position + hit.normal * (HIT_EPSILON * 4.0);
+            let reflected = self.trace_bounce(start, dir, bounce + 1);
+            color = color.lerp(reflected, reflectivity);
+        }
+
+        let fog = 1.0 - expf(-FOG_DENSITY * hit.t * hit.t);
+        color.lerp(self.sky(rd), fog)
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Blinn-Phong shading of `hit`, seen along `rd`: ambient light dimmed by occlusion, plus
+/// diffuse and specular light from each light that is not in shadow.
+pub fn shade_hit(scene: &Scene, rd: Vec3, hit: &Hit) -> Vec3 {
+    let material = &scene.materials[hit.material.0 as usize];
+    let (p, n) = (hit.position, hit.normal);
+    let albedo = material.albedo_at(p);
+    let v = -rd;
+    // Lift shadow and occlusion rays off the surface so they do not hit it straight away.
+    let surface = p + n * (HIT_EPSILON * 4.0);
+
+    let ao = scene.ambient_occlusion(p, n);
+    let mut color = albedo * scene.ambient * ao;
+    for light in scene.lights() {
+        let to_light = light.position - p;
+        let distance = to_light.len();
+        let l = to_light / distance;
+        let diff = n.dot(l).clamp(0.0, 1.0);
+        if diff <= 0.0 {
+            continue;
+        }
+        let shadow = scene.soft_shadow(surface, l, distance);
+        if shadow <= 0.0 {
+            continue;
+        }
+        let h = (l + v).norm();
+        let spec = powf(n.dot(h).clamp(0.0, 1.0), material.shininess) * material.specular;
+        let lit = albedo * (diff * 0.95) + Vec3::splat(spec);
+        color += lit * light.color * shadow;
+    }
+    color
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "{actual} is not {expected}"
+        );
+    }
+
+    /// A scene of just `object`, with one material.
+    fn single(shape: Shape, position: Vec3) -> Scene {
+        let mut scene = Scene::new();
+        let material = scene.add_material(Material::new(Vec3::ONE)).unwrap();
+        scene.add(Object::new(shape, position, material)).unwrap();
+        scene
+    }
+
+    /// Unit spheres at the origin and at x = 1.5, joined by `op`.
+    fn two_spheres(op: Op) -> Scene {
+        let mut scene = Scene::new();
+        let material = scene.add_material(Material::new(Vec3::ONE)).unwrap();
+        let sphere = Shape::Sphere { radius: 1.0 };
+        let a = scene
+            .add(Object::new(sphere, Vec3::ZERO, material))
+            .unwrap();
+        let b = scene
+            .add(Object::new(sphere, Vec3::new(1.5, 0.0, 0.0), material))
+            .unwrap();
+        scene.combine(op, a, b).unwrap();
+        scene
+    }
+
+    #[test]
+    fn shape_distances() {
+        let sphere = Shape::Sphere { radius: 1.0 };
+        assert_near(sphere.distance(Vec3::new(0.0, 3.0, 0.0)), 2.0);
+        assert_near(sphere.distance(Vec3::ZERO), -1.0);
+
+        let cube = Shape::Box {
+            half: Vec3::ONE,
+            radius: 0.0,
+        };
+        assert_near(cube.distance(Vec3::new(3.0, 0.0, 0.0)), 2.0);
+        assert_near(cube.distance(Vec3::new(2.0, 2.0, 1.0)), sqrtf(2.0));
+        assert_near(cube.distance(Vec3::new(0.5, 0.0, 0.0)), -0.5);
+        let rounded = Shape::Box {
+            half: Vec3::ONE,
+            radius: 0.25,
+        };
+        assert_near(rounded.distance(Vec3::new(3.0, 0.0, 0.0)), 2.0);
+        assert_near(rounded.distance(Vec3::splat(2.0)), sqrtf(3.0) * 1.25 - 0.25);
+
+        let torus = Shape::Torus {
+            major: 2.0,
+            minor: 0.5,
+        };
+        assert_near(torus.distance(Vec3::new(2.0, 0.0, 0.0)), -0.5);
+        assert_near(torus.distance(Vec3::new(0.0, 0.0, 4.0)), 1.5);
+        assert_near(torus.distance(Vec3::new(0.0, 1.0, -2.0)), 0.5);
+
+        let plane = Shape::Plane { normal: Vec3::Y };
+        assert_near(plane.distance(Vec3::new(5.0, 2.0, -1.0)), 2.0);
+        assert_near(plane.distance(Vec3::new(0.0, -1.0, 0.0)), -1.0);
+    }
+
+    #[test]
+    fn rotated_objects() {
+        let object = Object::new(
+            Shape::Box {
+                half: Vec3::new(2.0, 0.5, 0.5),
+                radius: 0.0,
+            },
+            Vec3::new(0.0, 1.0, 0.0),
+            MaterialId(0),
+        );
+        assert_near(object.distance(Vec3::new(0.0, 4.0, 0.0)), 2.5);
+        let turned = object.rotated(Axis::Z, core::f32::consts::FRAC_PI_2);
+        assert_near(turned.distance(Vec3::new(0.0, 4.0, 0.0)), 1.0);
+    }
+
+    #[test]
+    fn combinators() {
+        let outside = Vec3::new(-2.0, 0.0, 0.0);
+        let overlap = Vec3::new(0.75, 0.0, 0.0);
+        let inside_b = Vec3::new(1.0, 0.0, 0.0);
+
+        let union = two_spheres(Op::Union);
+        assert_near(union.distance(outside), 1.0);
+        assert_near(union.distance(overlap), -0.25);
+
+        let intersect = two_spheres(Op::Intersect);
+        assert_near(intersect.distance(outside), 2.5);
+        assert_near(intersect.distance(overlap), -0.25);
+
+        let subtract = two_spheres(Op::Subtract);
+        assert_near(subtract.distance(outside), 1.0);
+        assert_near(subtract.distance(inside_b), 0.5);
+
+        // Away from the seam the smooth versions match the sharp ones; on it, the smooth union
+        // bulges out by a quarter of `k`.
+        let smooth_union = two_spheres(Op::SmoothUnion(0.4));
+        assert_near(smooth_union.distance(outside), 1.0);
+        assert_near(smooth_union.distance(overlap), -0.25 - 0.1);
+
+        let smooth_subtract = two_spheres(Op::SmoothSubtract(0.4));
+        assert_near(smooth_subtract.distance(outside), 1.0);
+        assert_near(smooth_subtract.distance(inside_b), 0.5);
+    }
+
+    #[test]
+    fn empty_scene_is_infinitely_far() {
+        assert_eq!(Scene::new().distance(Vec3::ZERO), f32::INFINITY);
+        assert_eq!(Scene::new().march(Vec3::ZERO, Vec3::Z), None);
+    }
+
+    #[test]
+    fn march_hits_a_sphere() {
+        let scene = single(Shape::Sphere { radius: 1.0 }, Vec3::new(0.0, 0.0, -5.0));
+        let hit = scene.march(Vec3::ZERO, -Vec3::Z).unwrap();
+        assert!((hit.t - 4.0).abs() < 0.01, "hit at {}", hit.t);
+        assert!(hit.position.distance(Vec3::new(0.0, 0.0, -4.0)) < 0.01);
+        assert!(hit.normal.distance(Vec3::Z) < 0.01);
+        assert_eq!(hit.material, MaterialId(0));
+
+        assert_eq!(scene.march(Vec3::ZERO, Vec3::Z), None);
+        assert_eq!(scene.march(Vec3::ZERO, Vec3::X), None);
+    }
+
+    #[test]
+    fn soft_shadow() {
+        let scene = single(Shape::Sphere { radius: 1.0 }, Vec3::ZERO);
+        let below = Vec3::new(0.0, -3.0, 0.0);
+        assert_eq!(scene.soft_shadow(below, Vec3::Y, 10.0), 0.0);
+        // The light is between the point and the sphere.
+        assert_eq!(scene.soft_shadow(below, Vec3::Y, 1.0), 1.0);
+        // Well clear of the sphere.
+        assert_eq!(
+            scene.soft_shadow(Vec3::new(3.0, -3.0, 0.0), Vec3::Y, 10.0),
+            1.0
+        );
+        // Just missing it, in the penumbra.
+        let grazing = scene.soft_shadow(Vec3::new(1.05, -3.0, 0.0), Vec3::Y, 10.0);
+        assert!(grazing > 0.0 && grazing < 1.0, "{grazing}");
+    }
+
+    #[test]
+    fn full_scene_refuses_more() {
+        let mut scene = Scene::new();
+        let light = Light {
+            position: Vec3::ZERO,
+            color: Vec3::ONE,
+        };
+        for _ in 0..MAX_LIGHTS {
+            assert_eq!(scene.add_light(light), Some(()));
+        }
+        assert_eq!(scene.add_light(light), None);
+        assert_eq!(scene.lights().len(), MAX_LIGHTS);
+
+        for _ in 0..MAX_MATERIALS {
+            assert!(scene.add_material(Material::new(Vec3::ONE)).is_some());
+        }
+        assert_eq!(scene.add_material(Material::new(Vec3::ONE)), None);
+
+        let sphere = Object::new(Shape::Sphere { radius: 1.0 }, Vec3::ZERO, MaterialId(0));
+        for _ in 0..MAX_NODES {
+            assert!(scene.add(sphere).is_some());
+        }
+        assert_eq!(scene.add(sphere), None);
+        assert_eq!(scene.combine(Op::Union, NodeId(0), NodeId(1)), None);
+    }
+}
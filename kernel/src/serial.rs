@@ -1,30 +1,368 @@
-use core::fmt::Result;
-use core::fmt::Write;
+use core::fmt::{self, Write};
+use lazy_static::lazy_static;
+use pc_keyboard::{DecodedKey, KeyCode};
+use spin::Mutex;
 use uart_16550::SerialPort;
+use x86_64::instructions::interrupts::without_interrupts;
 
-pub struct SerialWriter {
-    pub serial_port: SerialPort,
-}
+use crate::console;
+use crate::interupts::{self, InterruptIndex};
+
+/// I/O port base of COM1.
+pub const COM1: u16 = 0x3f8;
 
-impl SerialWriter {
-    pub fn new() -> SerialWriter {
-        let mut serial_port: SerialPort = unsafe { SerialPort::new(0x3f8) };
+lazy_static! {
+    /// COM1, set up on first use. `init` also enables the receive interrupt and the OUT2
+    /// interrupt line, so only the PIC needs unmasking for input to arrive.
+    pub static ref SERIAL1: Mutex<SerialPort> = {
+        let mut serial_port = unsafe { SerialPort::new(COM1) };
         serial_port.init();
-        SerialWriter { serial_port }
+        Mutex::new(serial_port)
+    };
+}
+
+static SERIAL_INPUT: Mutex<SerialInput> = Mutex::new(SerialInput::new());
+
+/// Backend of `serial_print!`/`serial_println!`.
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    without_interrupts(|| {
+        let _ = SERIAL1.lock().write_fmt(args);
+    });
+}
+
+/// Writes to COM1 without taking `SERIAL1`'s lock, for the panic handler: the panic may have
+/// struck while the lock was held. The output can end up in the middle of whatever was being
+/// written at the time.
+pub fn write_unlocked(args: fmt::Arguments) {
+    // Only the transmit path is used, which needs no state beyond the port address.
+    let mut port = unsafe { SerialPort::new(COM1) };
+    let _ = port.write_fmt(args);
+}
+
+/// Turns on COM1 input. Bytes typed into the serial line are then decoded and fed to the
+/// active console the same way keyboard input is.
+pub fn init_input() {
+    lazy_static::initialize(&SERIAL1);
+    interupts::unmask_irq(InterruptIndex::Com1);
+}
+
+/// Drains the UART receive FIFO. Called from the COM1 interrupt.
+pub fn handle_interrupt() {
+    let mut port = SERIAL1.lock();
+    let mut input = SERIAL_INPUT.lock();
+    while let Ok(byte) = port.try_receive() {
+        if let Some(key) = input.decode(byte) {
+            console::push_key(key);
+        }
+        if let Some(key) = input.take_pending() {
+            console::push_key(key);
+        }
     }
 }
 
-impl Default for SerialWriter {
-    fn default() -> Self {
-        Self::new()
+/// Lets a lone Escape through once it is clear no key sequence follows. Called from the timer
+/// interrupt.
+pub fn handle_tick() {
+    if let Some(key) = SERIAL_INPUT.lock().tick() {
+        console::push_key(key);
     }
 }
 
-impl Write for SerialWriter {
-    fn write_str(&mut self, s: &str) -> Result {
-        for byte in s.bytes() {
-            self.serial_port.send(byte);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputState {
+    Ground,
+    Escape,
+    /// `ESC [` followed by a numeric parameter.
+    Csi(u16),
+    /// Past the `;` of a CSI sequence, where only modifier parameters follow.
+    CsiModifiers(u16),
+    /// `ESC O`, which xterm uses for arrows in application mode and for F1-F4.
+    Ss3,
+    /// Inside a UTF-8 sequence, with the continuation bytes still missing and the bits so far.
+    Utf8(u8, u32),
+}
+
+/// Turns what a terminal emulator sends (UTF-8 text and VT100 key sequences) into the keys
+/// the keyboard driver would have produced.
+///
+/// A lone Escape press cannot be told apart from the start of a key sequence until the next
+/// byte arrives. It is reported then, or once `ESCAPE_TICKS` timer ticks pass without one.
+struct SerialInput {
+    state: InputState,
+    /// Key decoded from the byte that ended an Escape, waiting behind it.
+    pending: Option<DecodedKey>,
+    /// Timer ticks since the Escape that `InputState::Escape` is waiting after.
+    escape_ticks: u8,
+}
+
+/// Ticks a lone Escape waits for the rest of a key sequence. Two, because the first can come
+/// straight after it.
+const ESCAPE_TICKS: u8 = 2;
+
+impl SerialInput {
+    const fn new() -> SerialInput {
+        SerialInput {
+            state: InputState::Ground,
+            pending: None,
+            escape_ticks: 0,
+        }
+    }
+
+    /// The key that goes after the one `decode` just returned, if any.
+    fn take_pending(&mut self) -> Option<DecodedKey> {
+        self.pending.take()
+    }
+
+    /// Reports an Escape that no key sequence followed within `ESCAPE_TICKS` ticks.
+    fn tick(&mut self) -> Option<DecodedKey> {
+        if self.state != InputState::Escape {
+            return None;
+        }
+        self.escape_ticks += 1;
+        if self.escape_ticks < ESCAPE_TICKS {
+            return None;
         }
-        Ok(())
+        self.state = InputState::Ground;
+        Some(DecodedKey::Unicode('\x1b'))
+    }
+
+    fn decode(&mut self, byte: u8) -> Option<DecodedKey> {
+        match self.state {
+            InputState::Ground => self.ground(byte),
+            InputState::Escape => {
+                self.state = InputState::Ground;
+                match byte {
+                    b'[' => {
+                        self.state = InputState::Csi(0);
+                        None
+                    }
+                    b'O' => {
+                        self.state = InputState::Ss3;
+                        None
+                    }
+                    // Not a key sequence after all: Escape, then whatever this byte is.
+                    _ => {
+                        self.pending = self.ground(byte);
+                        Some(DecodedKey::Unicode('\x1b'))
+                    }
+                }
+            }
+            InputState::Csi(param) => {
+                self.state = InputState::Ground;
+                match byte {
+                    b'0'..=b'9' => {
+                        let param = param
+                            .saturating_mul(10)
+                            .saturating_add(u16::from(byte - b'0'));
+                        self.state = InputState::Csi(param);
+                        None
+                    }
+                    b';' => {
+                        self.state = InputState::CsiModifiers(param);
+                        None
+                    }
+                    _ => csi_key(param, byte),
+                }
+            }
+            // Modifiers (`ESC [ 1 ; 2 A` is Shift+Up) are ignored.
+            InputState::CsiModifiers(param) => match byte {
+                b'0'..=b'9' | b';' => None,
+                _ => {
+                    self.state = InputState::Ground;
+                    csi_key(param, byte)
+                }
+            },
+            InputState::Ss3 => {
+                self.state = InputState::Ground;
+                let key = match byte {
+                    b'P' => Some(KeyCode::F1),
+                    b'Q' => Some(KeyCode::F2),
+                    b'R' => Some(KeyCode::F3),
+                    b'S' => Some(KeyCode::F4),
+                    _ => final_key(byte),
+                };
+                key.map(DecodedKey::RawKey)
+            }
+            InputState::Utf8(remaining, value) => {
+                if byte & 0xC0 != 0x80 {
+                    // Truncated sequence: drop it and start over with this byte.
+                    self.state = InputState::Ground;
+                    return self.ground(byte);
+                }
+                let value = (value << 6) | u32::from(byte & 0x3F);
+                if remaining > 1 {
+                    self.state = InputState::Utf8(remaining - 1, value);
+                    return None;
+                }
+                self.state = InputState::Ground;
+                Some(DecodedKey::Unicode(
+                    char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER),
+                ))
+            }
+        }
+    }
+
+    fn ground(&mut self, byte: u8) -> Option<DecodedKey> {
+        let key = match byte {
+            0x1b => {
+                self.state = InputState::Escape;
+                self.escape_ticks = 0;
+                return None;
+            }
+            // Terminals send CR for Enter and DEL for Backspace; the keyboard gives LF and BS.
+            b'\r' => '\n',
+            0x7f => '\x08',
+            0x00..=0x7f => byte as char,
+            0xC0..=0xDF => {
+                self.state = InputState::Utf8(1, u32::from(byte & 0x1F));
+                return None;
+            }
+            0xE0..=0xEF => {
+                self.state = InputState::Utf8(2, u32::from(byte & 0x0F));
+                return None;
+            }
+            0xF0..=0xF7 => {
+                self.state = InputState::Utf8(3, u32::from(byte & 0x07));
+                return None;
+            }
+            _ => char::REPLACEMENT_CHARACTER,
+        };
+        Some(DecodedKey::Unicode(key))
+    }
+}
+
+/// Key for a CSI sequence with parameter `param` and final byte `byte`.
+fn csi_key(param: u16, byte: u8) -> Option<DecodedKey> {
+    match (byte, param) {
+        // Delete decodes to DEL on the keyboard too.
+        (b'~', 3) => Some(DecodedKey::Unicode('\x7f')),
+        (b'~', _) => tilde_key(param).map(DecodedKey::RawKey),
+        _ => final_key(byte).map(DecodedKey::RawKey),
+    }
+}
+
+/// Key for the final byte of `ESC [ A` style sequences.
+fn final_key(byte: u8) -> Option<KeyCode> {
+    match byte {
+        b'A' => Some(KeyCode::ArrowUp),
+        b'B' => Some(KeyCode::ArrowDown),
+        b'C' => Some(KeyCode::ArrowRight),
+        b'D' => Some(KeyCode::ArrowLeft),
+        b'H' => Some(KeyCode::Home),
+        b'F' => Some(KeyCode::End),
+        _ => None,
+    }
+}
+
+/// Key for `ESC [ <param> ~` sequences.
+fn tilde_key(param: u16) -> Option<KeyCode> {
+    match param {
+        1 | 7 => Some(KeyCode::Home),
+        2 => Some(KeyCode::Insert),
+        4 | 8 => Some(KeyCode::End),
+        5 => Some(KeyCode::PageUp),
+        6 => Some(KeyCode::PageDown),
+        11 => Some(KeyCode::F1),
+        12 => Some(KeyCode::F2),
+        13 => Some(KeyCode::F3),
+        14 => Some(KeyCode::F4),
+        15 => Some(KeyCode::F5),
+        17 => Some(KeyCode::F6),
+        18 => Some(KeyCode::F7),
+        19 => Some(KeyCode::F8),
+        20 => Some(KeyCode::F9),
+        21 => Some(KeyCode::F10),
+        23 => Some(KeyCode::F11),
+        24 => Some(KeyCode::F12),
+        _ => None,
+    }
+}
+
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => ($crate::serial::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keys `bytes` decode to, pending ones included.
+    fn decode_all(input: &mut SerialInput, bytes: &[u8]) -> Vec<DecodedKey> {
+        let mut keys = Vec::new();
+        for &byte in bytes {
+            keys.extend(input.decode(byte));
+            keys.extend(input.take_pending());
+        }
+        keys
+    }
+
+    #[test]
+    fn escape_then_plain_byte_keeps_both() {
+        let mut input = SerialInput::new();
+        assert_eq!(
+            decode_all(&mut input, b"\x1bq"),
+            [DecodedKey::Unicode('\x1b'), DecodedKey::Unicode('q')]
+        );
+        assert_eq!(input.state, InputState::Ground);
+    }
+
+    #[test]
+    fn escape_then_escape_sequence() {
+        let mut input = SerialInput::new();
+        assert_eq!(
+            decode_all(&mut input, b"\x1b\x1b[A"),
+            [
+                DecodedKey::Unicode('\x1b'),
+                DecodedKey::RawKey(KeyCode::ArrowUp)
+            ]
+        );
+    }
+
+    #[test]
+    fn key_sequences() {
+        let mut input = SerialInput::new();
+        assert_eq!(
+            decode_all(&mut input, b"\x1b[A\x1bOP\x1b[5~\x1b[1;2B\x1b[3~"),
+            [
+                DecodedKey::RawKey(KeyCode::ArrowUp),
+                DecodedKey::RawKey(KeyCode::F1),
+                DecodedKey::RawKey(KeyCode::PageUp),
+                DecodedKey::RawKey(KeyCode::ArrowDown),
+                DecodedKey::Unicode('\x7f'),
+            ]
+        );
+    }
+
+    #[test]
+    fn text() {
+        let mut input = SerialInput::new();
+        assert_eq!(
+            decode_all(&mut input, "a\r\x7fé€".as_bytes()),
+            [
+                DecodedKey::Unicode('a'),
+                DecodedKey::Unicode('\n'),
+                DecodedKey::Unicode('\x08'),
+                DecodedKey::Unicode('é'),
+                DecodedKey::Unicode('€'),
+            ]
+        );
+    }
+
+    #[test]
+    fn lone_escape_is_flushed_by_ticks() {
+        let mut input = SerialInput::new();
+        assert_eq!(decode_all(&mut input, b"\x1b"), []);
+        assert_eq!(input.tick(), None);
+        assert_eq!(input.tick(), Some(DecodedKey::Unicode('\x1b')));
+        assert_eq!(input.tick(), None);
+        assert_eq!(decode_all(&mut input, b"["), [DecodedKey::Unicode('[')]);
     }
 }
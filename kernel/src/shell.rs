@@ -0,0 +1,380 @@
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use crate::console::{self, KERNEL_CONSOLE};
+use crate::framebuffer::{backbuffer, clip_to_backbuffer, run_bouncy_circles, Renderer};
+use crate::interupts::{self, InterruptIndex, TIMER_HZ};
+use crate::{framebuffer_console, logger, print, println, screenshot, snake, tetris, util};
+
+/// Longest command line, short enough to fit on one 80-column line after the prompt.
+pub const MAX_LINE: usize = 72;
+
+/// Number of previous command lines kept for Up/Down.
+pub const HISTORY_LEN: usize = 16;
+
+const PROMPT: &str = "> ";
+
+struct Command {
+    name: &'static str,
+    help: &'static str,
+    run: fn(&mut Shell, &str),
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        help: "list the commands",
+        run: help,
+    },
+    Command {
+        name: "echo",
+        help: "print the rest of the line",
+        run: echo,
+    },
+    Command {
+        name: "clear",
+        help: "clear the screen",
+        run: clear,
+    },
+    Command {
+        name: "uptime",
+        help: "time since boot",
+        run: uptime,
+    },
+    Command {
+        name: "mem",
+        help: "physical memory map summary",
+        run: mem,
+    },
+    Command {
+        name: "irq",
+        help: "interrupt counts per IRQ line",
+        run: irq,
+    },
+    Command {
+        name: "dmesg",
+        help: "print the kernel log",
+        run: dmesg,
+    },
+    Command {
+        name: "demo",
+        help: "fly around the raymarched scene demo until Escape is pressed",
+        run: demo,
+    },
+    Command {
+        name: "screenshot",
+        help: "send the screen over serial as a PPM",
+        run: screenshot,
+    },
+    Command {
+        name: "snake",
+        help: "play Snake",
+        run: snake,
+    },
+    Command {
+        name: "tetris",
+        help: "play Tetris",
+        run: tetris,
+    },
+    Command {
+        name: "reboot",
+        help: "restart the machine",
+        run: reboot,
+    },
+    Command {
+        name: "panic",
+        help: "trigger a kernel panic",
+        run: panic,
+    },
+    Command {
+        name: "pagefault",
+        help: "write to an unmapped address",
+        run: pagefault,
+    },
+];
+
+/// One line of input, kept as characters so editing never splits a UTF-8 sequence.
+#[derive(Clone, Copy)]
+struct Line {
+    chars: [char; MAX_LINE],
+    len: usize,
+}
+
+impl Line {
+    const EMPTY: Line = Line {
+        chars: ['\0'; MAX_LINE],
+        len: 0,
+    };
+
+    fn as_slice(&self) -> &[char] {
+        &self.chars[..self.len]
+    }
+
+    /// The line as UTF-8, written into `buffer`.
+    fn encode<'a>(&self, buffer: &'a mut [u8; MAX_LINE * 4]) -> &'a str {
+        let mut len = 0;
+        for c in self.as_slice() {
+            len += c.encode_utf8(&mut buffer[len..]).len();
+        }
+        core::str::from_utf8(&buffer[..len]).unwrap_or_default()
+    }
+}
+
+/// Line-editing command shell on the kernel console. Input comes from the console's key queue,
+/// so it can be driven from the PS/2 keyboard or the serial line alike.
+pub struct Shell {
+    memory_regions: &'static MemoryRegions,
+    line: Line,
+    cursor: usize,
+    history: [Line; HISTORY_LEN],
+    history_len: usize,
+    /// Index into `history` while browsing it with Up/Down; `history_len` means the line being typed.
+    history_index: usize,
+}
+
+impl Shell {
+    pub fn new(memory_regions: &'static MemoryRegions) -> Shell {
+        Shell {
+            memory_regions,
+            line: Line::EMPTY,
+            cursor: 0,
+            history: [Line::EMPTY; HISTORY_LEN],
+            history_len: 0,
+            history_index: 0,
+        }
+    }
+
+    pub fn run(&mut self) -> ! {
+        println!("Type 'help' for a list of commands.");
+        loop {
+            self.read_line();
+            let mut buffer = [0; MAX_LINE * 4];
+            let line = self.line;
+            self.execute(line.encode(&mut buffer));
+        }
+    }
+
+    fn execute(&mut self, line: &str) {
+        let line = line.trim();
+        let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+        if name.is_empty() {
+            return;
+        }
+
+        match COMMANDS.iter().find(|command| command.name == name) {
+            Some(command) => (command.run)(self, args.trim_start()),
+            None => println!("{}: command not found", name),
+        }
+    }
+
+    /// Reads one line into `self.line`, handling editing keys and history, and records it.
+    fn read_line(&mut self) {
+        self.line = Line::EMPTY;
+        self.cursor = 0;
+        self.history_index = self.history_len;
+        print!("{}", PROMPT);
+
+        loop {
+            match console::wait_key(KERNEL_CONSOLE) {
+                DecodedKey::Unicode('\n') => break,
+                DecodedKey::Unicode('\x08') => {
+                    if self.cursor > 0 {
+                        self.cursor -= 1;
+                        self.remove_at_cursor();
+                    }
+                }
+                DecodedKey::Unicode('\x7f') => self.remove_at_cursor(),
+                DecodedKey::Unicode(c) if !c.is_control() => self.insert(c),
+                DecodedKey::RawKey(KeyCode::ArrowLeft) => {
+                    self.cursor = self.cursor.saturating_sub(1)
+                }
+                DecodedKey::RawKey(KeyCode::ArrowRight) => {
+                    self.cursor = (self.cursor + 1).min(self.line.len)
+                }
+                DecodedKey::RawKey(KeyCode::Home) => self.cursor = 0,
+                DecodedKey::RawKey(KeyCode::End) => self.cursor = self.line.len,
+                DecodedKey::RawKey(KeyCode::ArrowUp) => self.recall(-1),
+                DecodedKey::RawKey(KeyCode::ArrowDown) => self.recall(1),
+                _ => continue,
+            }
+            self.redraw_line();
+        }
+        println!();
+
+        if self.line.len > 0 {
+            self.remember();
+        }
+    }
+
+    fn insert(&mut self, c: char) {
+        if self.line.len == MAX_LINE {
+            return;
+        }
+        self.line
+            .chars
+            .copy_within(self.cursor..self.line.len, self.cursor + 1);
+        self.line.chars[self.cursor] = c;
+        self.line.len += 1;
+        self.cursor += 1;
+    }
+
+    fn remove_at_cursor(&mut self) {
+        if self.cursor >= self.line.len {
+            return;
+        }
+        self.line
+            .chars
+            .copy_within(self.cursor + 1..self.line.len, self.cursor);
+        self.line.len -= 1;
+    }
+
+    /// Moves `step` entries through the history, ending on an empty line past the newest entry.
+    fn recall(&mut self, step: isize) {
+        let index = self.history_index as isize + step;
+        if index < 0 || index > self.history_len as isize {
+            return;
+        }
+        self.history_index = index as usize;
+        self.line = if self.history_index < self.history_len {
+            self.history[self.history_index]
+        } else {
+            Line::EMPTY
+        };
+        self.cursor = self.line.len;
+    }
+
+    fn remember(&mut self) {
+        // Don't fill the history with repeats of the same command.
+        if self.history_len > 0
+            && self.history[self.history_len - 1].as_slice() == self.line.as_slice()
+        {
+            return;
+        }
+        if self.history_len == HISTORY_LEN {
+            self.history.copy_within(1.., 0);
+            self.history_len -= 1;
+        }
+        self.history[self.history_len] = self.line;
+        self.history_len += 1;
+    }
+
+    /// Reprints the prompt and line, then moves the cursor back to its place in the line.
+    fn redraw_line(&self) {
+        print!("\r{}", PROMPT);
+        for c in self.line.as_slice() {
+            print!("{}", c);
+        }
+        print!("\x1b[K");
+        let back = self.line.len - self.cursor;
+        if back > 0 {
+            print!("\x1b[{}D", back);
+        }
+    }
+}
+
+fn help(_shell: &mut Shell, _args: &str) {
+    for command in COMMANDS {
+        println!("  {:<10} {}", command.name, command.help);
+    }
+}
+
+fn echo(_shell: &mut Shell, args: &str) {
+    println!("{}", args);
+}
+
+fn clear(_shell: &mut Shell, _args: &str) {
+    print!("\x1b[2J\x1b[H");
+}
+
+fn uptime(_shell: &mut Shell, _args: &str) {
+    let ticks = interupts::timer_ticks();
+    let seconds = ticks / u64::from(TIMER_HZ);
+    println!(
+        "up {}:{:02}:{:02} ({} ticks)",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        ticks
+    );
+}
+
+fn mem(shell: &mut Shell, _args: &str) {
+    let mut usable = 0;
+    let mut reserved = 0;
+    for region in shell.memory_regions.iter() {
+        let size = region.end - region.start;
+        match region.kind {
+            MemoryRegionKind::Usable => usable += size,
+            _ => reserved += size,
+        }
+    }
+    println!(
+        "{} regions, {} KiB usable, {} KiB reserved",
+        shell.memory_regions.len(),
+        usable / 1024,
+        reserved / 1024
+    );
+}
+
+fn irq(_shell: &mut Shell, _args: &str) {
+    for irq in InterruptIndex::ALL {
+        println!(
+            "  IRQ{:<2} {:?}: {}",
+            irq.irq_line(),
+            irq,
+            interupts::irq_count(irq)
+        );
+    }
+}
+
+fn dmesg(_shell: &mut Shell, _args: &str) {
+    logger::dmesg(|line| println!("{}", line));
+}
+
+fn demo(_shell: &mut Shell, _args: &str) {
+    match framebuffer_console::take_framebuffer() {
+        Some(framebuffer) => {
+            run_bouncy_circles(framebuffer);
+            framebuffer_console::restore_framebuffer(framebuffer);
+        }
+        None => println!("demo: no framebuffer"),
+    }
+}
+
+fn screenshot(_shell: &mut Shell, _args: &str) {
+    match framebuffer_console::take_framebuffer() {
+        Some(framebuffer) => {
+            // The console draws into the backbuffer and presents it after every write, so the
+            // backbuffer holds what is on screen without reading back from video memory.
+            let info = clip_to_backbuffer(framebuffer.info());
+            if let Some(buffer) = unsafe { backbuffer(info.byte_len) } {
+                screenshot::send(&Renderer::new(buffer, info));
+            }
+            framebuffer_console::restore_framebuffer(framebuffer);
+            println!("screenshot: sent over serial");
+        }
+        None => println!("screenshot: no framebuffer"),
+    }
+}
+
+fn snake(_shell: &mut Shell, _args: &str) {
+    snake::run(KERNEL_CONSOLE);
+    print!("\x1b[2J\x1b[H");
+}
+
+fn tetris(_shell: &mut Shell, _args: &str) {
+    tetris::run(KERNEL_CONSOLE);
+    print!("\x1b[2J\x1b[H");
+}
+
+fn reboot(_shell: &mut Shell, _args: &str) {
+    util::reboot();
+}
+
+fn panic(_shell: &mut Shell, _args: &str) {
+    panic!("panic requested from the shell");
+}
+
+fn pagefault(_shell: &mut Shell, _args: &str) {
+    interupts::trigger_page_fault();
+}
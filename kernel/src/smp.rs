@@ -0,0 +1,346 @@
+//! Starting the application processors (APs), the CPUs besides the one the bootloader ran on.
+//!
+//! An AP wakes up in real mode at a page below 1 MiB, so the boot CPU copies a trampoline there
+//! that switches to long mode and jumps to `ap_entry` on a stack of its own. The bootloader
+//! leaves that first megabyte alone for this. The APs are woken with an INIT and two STARTUP
+//! IPIs sent to all of them at once, so there is no need to find them in the ACPI tables.
+
+use core::arch::global_asm;
+use core::mem::offset_of;
+use core::ptr;
+
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use log::warn;
+use spin::Once;
+use x86_64::registers::control::{Cr3, Cr3Flags};
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::paging::{PageTableFlags, PhysFrame};
+
+use crate::{fpu, gdt, interupts, parallel};
+
+/// Most CPUs the kernel brings up, counting the boot CPU. Any more stay halted.
+pub const MAX_CPUS: usize = 16;
+
+const AP_STACK_SIZE: usize = 64 * 1024;
+
+const PAGE_SIZE: u64 = 4096;
+/// The STARTUP IPI gives the AP a page number, so the trampoline has to be below this.
+const LOW_MEMORY_END: u64 = 0x10_0000;
+/// The trampoline, then the PML4, PDPT and page directory it turns paging on with.
+const LOW_PAGES: u64 = 4;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+const LAPIC_SVR: usize = 0xF0;
+const LAPIC_ICR_LOW: usize = 0x300;
+const LAPIC_ICR_HIGH: usize = 0x310;
+const LAPIC_LVT_LINT0: usize = 0x350;
+const LAPIC_LVT_LINT1: usize = 0x360;
+const SVR_ENABLE: u32 = 1 << 8;
+const LVT_EXTINT: u32 = 0x700;
+const LVT_NMI: u32 = 0x400;
+/// INIT and STARTUP IPIs to every CPU but this one. STARTUP takes the page number to start at.
+const ICR_INIT: u32 = 0x000C_4500;
+const ICR_STARTUP: u32 = 0x000C_4600;
+const ICR_PENDING: u32 = 1 << 12;
+
+/// The trampoline's GDT: 32-bit code, data, then 64-bit code.
+const CODE32_SELECTOR: u16 = 0x08;
+const DATA_SELECTOR: u16 = 0x10;
+const CODE64_SELECTOR: u16 = 0x18;
+/// Protection, math coprocessor, extension type and numeric error, with caching back on after
+/// INIT turned it off. Then paging and write protection on top.
+const CR0_PROTECTED: u32 = 0x0000_0033;
+const CR0_PAGING: u32 = 0x8001_0033;
+/// PAE, and SSE so that `ap_entry` can run compiled code before `fpu::init`.
+const CR4: u32 = 0x0000_0620;
+/// Long mode and no-execute pages, which the kernel's page tables use.
+const EFER_BITS: u32 = 0x0000_0900;
+
+/// Filled in by the boot CPU after copying the trampoline, which reads it relative to where it
+/// runs. Addresses are physical until paging is on.
+#[repr(C)]
+struct TrampolineData {
+    /// Handed out to the APs in the order they get here, starting at 1.
+    next_cpu: u32,
+    max_cpus: u32,
+    page_table: u32,
+    /// Far pointer to the 32-bit code.
+    protected_mode_offset: u32,
+    protected_mode_selector: u16,
+    /// Operand of `lgdt`.
+    gdt_limit: u16,
+    gdt_base: u32,
+    /// Far pointer to the 64-bit code.
+    long_mode_offset: u32,
+    long_mode_selector: u16,
+    _padding: u16,
+    stacks: u64,
+    stack_size: u64,
+    entry: u64,
+}
+
+#[repr(C, align(16))]
+struct Stacks([[u8; AP_STACK_SIZE]; MAX_CPUS - 1]);
+
+/// One stack per AP, indexed by CPU number minus one.
+static mut AP_STACKS: Stacks = Stacks([[0; AP_STACK_SIZE]; MAX_CPUS - 1]);
+
+/// What CR3 holds on the boot CPU, for the APs to switch to once they are in kernel code.
+static KERNEL_PAGE_TABLE: Once<(PhysFrame, Cr3Flags)> = Once::new();
+
+global_asm!(
+    r#"
+    .pushsection .rodata.ap_trampoline, "a"
+    .balign 16
+    .global ap_trampoline_start
+    .global ap_trampoline_protected_mode
+    .global ap_trampoline_long_mode
+    .global ap_trampoline_gdt
+    .global ap_trampoline_gdt_end
+    .global ap_trampoline_data
+    .global ap_trampoline_end
+
+    .code16
+ap_trampoline_start:
+    cli
+    cld
+    mov %cs, %ax
+    mov %ax, %ds
+    xor %ebx, %ebx
+    mov %ax, %bx
+    shl $4, %ebx
+    lgdtl (ap_trampoline_data - ap_trampoline_start + {gdt_limit})
+    mov ${cr0_protected}, %eax
+    mov %eax, %cr0
+    ljmpl *(ap_trampoline_data - ap_trampoline_start + {protected_mode_offset})
+
+    .code32
+ap_trampoline_protected_mode:
+    mov ${data_selector}, %ax
+    mov %ax, %ds
+    mov %ax, %es
+    mov %ax, %ss
+    mov ${cr4}, %eax
+    mov %eax, %cr4
+    mov (ap_trampoline_data - ap_trampoline_start + {page_table})(%ebx), %eax
+    mov %eax, %cr3
+    mov $0xC0000080, %ecx
+    rdmsr
+    or ${efer_bits}, %eax
+    wrmsr
+    mov ${cr0_paging}, %eax
+    mov %eax, %cr0
+    ljmpl *(ap_trampoline_data - ap_trampoline_start + {long_mode_offset})(%ebx)
+
+    .code64
+ap_trampoline_long_mode:
+    mov %ebx, %ebx
+    mov $1, %eax
+    lock xadd %eax, (ap_trampoline_data - ap_trampoline_start + {next_cpu})(%rbx)
+    cmp (ap_trampoline_data - ap_trampoline_start + {max_cpus})(%rbx), %eax
+    jae 2f
+    mov %eax, %edi
+    imul (ap_trampoline_data - ap_trampoline_start + {stack_size})(%rbx), %rax
+    add (ap_trampoline_data - ap_trampoline_start + {stacks})(%rbx), %rax
+    mov %rax, %rsp
+    xor %ebp, %ebp
+    call *(ap_trampoline_data - ap_trampoline_start + {entry})(%rbx)
+2:
+    cli
+    hlt
+    jmp 2b
+
+    .balign 8
+ap_trampoline_gdt:
+    .quad 0
+    .quad 0x00cf9a000000ffff
+    .quad 0x00cf92000000ffff
+    .quad 0x00af9a000000ffff
+ap_trampoline_gdt_end:
+
+    .balign 16
+ap_trampoline_data:
+    .space {data_size}
+ap_trampoline_end:
+    .popsection
+    "#,
+    gdt_limit = const offset_of!(TrampolineData, gdt_limit),
+    protected_mode_offset = const offset_of!(TrampolineData, protected_mode_offset),
+    long_mode_offset = const offset_of!(TrampolineData, long_mode_offset),
+    page_table = const offset_of!(TrampolineData, page_table),
+    next_cpu = const offset_of!(TrampolineData, next_cpu),
+    max_cpus = const offset_of!(TrampolineData, max_cpus),
+    stacks = const offset_of!(TrampolineData, stacks),
+    stack_size = const offset_of!(TrampolineData, stack_size),
+    entry = const offset_of!(TrampolineData, entry),
+    data_size = const size_of::<TrampolineData>(),
+    data_selector = const DATA_SELECTOR,
+    cr0_protected = const CR0_PROTECTED,
+    cr0_paging = const CR0_PAGING,
+    cr4 = const CR4,
+    efer_bits = const EFER_BITS,
+    options(att_syntax)
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_protected_mode: u8;
+    static ap_trampoline_long_mode: u8;
+    static ap_trampoline_gdt: u8;
+    static ap_trampoline_gdt_end: u8;
+    static ap_trampoline_data: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// Offset of a trampoline label from its start.
+fn trampoline_offset(label: *const u8) -> u64 {
+    label as u64 - (&raw const ap_trampoline_start) as u64
+}
+
+/// Starts the APs and waits for them to check in with `parallel`. Returns how many CPUs are
+/// online, counting this one. Needs the physical memory mapping and the timer interrupt.
+pub fn start_aps(memory_regions: &MemoryRegions, physical_memory_offset: u64) -> usize {
+    let Some(low) = low_memory(memory_regions) else {
+        warn!("smp: no free memory below 1 MiB for the AP trampoline");
+        return parallel::online_cpus();
+    };
+    let virt = |phys: u64| (physical_memory_offset + phys) as *mut u8;
+
+    let length = trampoline_offset(&raw const ap_trampoline_end);
+    let (pml4, pdpt, pd) = (low + PAGE_SIZE, low + 2 * PAGE_SIZE, low + 3 * PAGE_SIZE);
+    let kernel_page_table = *KERNEL_PAGE_TABLE.call_once(Cr3::read);
+    let table_flags = (PageTableFlags::PRESENT | PageTableFlags::WRITABLE).bits();
+    unsafe {
+        ptr::copy_nonoverlapping(&raw const ap_trampoline_start, virt(low), length as usize);
+
+        // The kernel's mappings, plus the first 2 MiB identity mapped so the trampoline keeps
+        // running where it is when paging comes on.
+        let kernel_pml4 = virt(kernel_page_table.0.start_address().as_u64()) as *const u64;
+        ptr::copy_nonoverlapping(kernel_pml4, virt(pml4) as *mut u64, 512);
+        ptr::write_bytes(virt(pdpt), 0, PAGE_SIZE as usize);
+        ptr::write_bytes(virt(pd), 0, PAGE_SIZE as usize);
+        *(virt(pml4) as *mut u64) = pdpt | table_flags;
+        *(virt(pdpt) as *mut u64) = pd | table_flags;
+        *(virt(pd) as *mut u64) = table_flags | PageTableFlags::HUGE_PAGE.bits();
+
+        let base = low as u32;
+        let data = TrampolineData {
+            next_cpu: 1,
+            max_cpus: MAX_CPUS as u32,
+            page_table: pml4 as u32,
+            protected_mode_offset: base
+                + trampoline_offset(&raw const ap_trampoline_protected_mode) as u32,
+            protected_mode_selector: CODE32_SELECTOR,
+            gdt_limit: (trampoline_offset(&raw const ap_trampoline_gdt_end)
+                - trampoline_offset(&raw const ap_trampoline_gdt)
+                - 1) as u16,
+            gdt_base: base + trampoline_offset(&raw const ap_trampoline_gdt) as u32,
+            long_mode_offset: base + trampoline_offset(&raw const ap_trampoline_long_mode) as u32,
+            long_mode_selector: CODE64_SELECTOR,
+            _padding: 0,
+            stacks: (&raw const AP_STACKS) as u64,
+            stack_size: AP_STACK_SIZE as u64,
+            entry: ap_entry as *const () as u64,
+        };
+        let data_address = low + trampoline_offset(&raw const ap_trampoline_data);
+        ptr::write_volatile(virt(data_address) as *mut TrampolineData, data);
+    }
+
+    let apic = LocalApic::new(physical_memory_offset);
+    apic.enable();
+    apic.send_ipi(ICR_INIT);
+    wait_ticks(2);
+    for _ in 0..2 {
+        apic.send_ipi(ICR_STARTUP | (low / PAGE_SIZE) as u32);
+        wait_ticks(1);
+    }
+
+    // There is no telling how many APs there are, so wait until no more turn up.
+    let mut online = parallel::online_cpus();
+    loop {
+        wait_ticks(6);
+        let now = parallel::online_cpus();
+        if now == online {
+            return online;
+        }
+        online = now;
+    }
+}
+
+/// Start of `LOW_PAGES` free pages below 1 MiB, leaving out page 0 with the real mode
+/// interrupt table.
+fn low_memory(memory_regions: &MemoryRegions) -> Option<u64> {
+    memory_regions
+        .iter()
+        .filter(|region| region.kind == MemoryRegionKind::Usable)
+        .find_map(|region| {
+            let start = region.start.max(PAGE_SIZE).next_multiple_of(PAGE_SIZE);
+            let end = start + LOW_PAGES * PAGE_SIZE;
+            (end <= region.end && end <= LOW_MEMORY_END).then_some(start)
+        })
+}
+
+fn wait_ticks(ticks: u64) {
+    let start = interupts::timer_ticks();
+    while interupts::timer_ticks() < start + ticks {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Where the trampoline leaves each AP: in long mode on its own stack, with interrupts off.
+/// `cpu` counts from 1 in the order the APs got there.
+extern "sysv64" fn ap_entry(cpu: u32) -> ! {
+    fpu::init();
+    gdt::init_ap(cpu as usize);
+    interupts::init();
+    // The trampoline's page tables were only needed to get here.
+    if let Some(&(frame, flags)) = KERNEL_PAGE_TABLE.get() {
+        unsafe { Cr3::write(frame, flags) };
+    }
+    parallel::ap_main()
+}
+
+/// This CPU's local APIC, through the physical memory mapping.
+struct LocalApic {
+    registers: *mut u8,
+}
+
+impl LocalApic {
+    fn new(physical_memory_offset: u64) -> LocalApic {
+        let base = unsafe { Msr::new(IA32_APIC_BASE).read() } & APIC_BASE_MASK;
+        LocalApic {
+            registers: (physical_memory_offset + base) as *mut u8,
+        }
+    }
+
+    fn read(&self, register: usize) -> u32 {
+        unsafe { ptr::read_volatile(self.registers.add(register) as *const u32) }
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        unsafe { ptr::write_volatile(self.registers.add(register) as *mut u32, value) }
+    }
+
+    /// Software-enables the APIC if the firmware didn't, routing the PIC through LINT0 and
+    /// NMIs through LINT1 the way the firmware would have.
+    fn enable(&self) {
+        if self.read(LAPIC_SVR) & SVR_ENABLE != 0 {
+            return;
+        }
+        self.write(LAPIC_LVT_LINT0, LVT_EXTINT);
+        self.write(LAPIC_LVT_LINT1, LVT_NMI);
+        self.write(
+            LAPIC_SVR,
+            SVR_ENABLE | u32::from(interupts::SPURIOUS_VECTOR),
+        );
+    }
+
+    fn send_ipi(&self, command: u32) {
+        self.write(LAPIC_ICR_HIGH, 0);
+        self.write(LAPIC_ICR_LOW, command);
+        while self.read(LAPIC_ICR_LOW) & ICR_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use crate::console;
+use crate::interupts;
+use crate::random;
+use crate::tui::{self, Rect, TextCanvas, Window, DEFAULT_STYLE};
+use crate::vga_text_mode::{ColorData, VGAColorCode};
+use crate::vga_text_mode_drawing::point;
+
+/// Size of the playing field in cells, small enough to fit the 80x25 VGA screen with its border.
+pub const ARENA_WIDTH: usize = 40;
+pub const ARENA_HEIGHT: usize = 20;
+
+/// Timer ticks between moves at the start. Every few pieces of food make the snake faster.
+const START_STEP_TICKS: u64 = 8;
+const MIN_STEP_TICKS: u64 = 3;
+const FOOD_PER_SPEEDUP: usize = 4;
+
+/// Segments the snake grows by for each piece of food.
+const GROWTH_PER_FOOD: usize = 3;
+
+const MAX_LENGTH: usize = ARENA_WIDTH * ARENA_HEIGHT;
+
+const FLOOR: ColorData = ColorData::new(VGAColorCode::Black, VGAColorCode::Black, false);
+const BODY: ColorData = ColorData::new(VGAColorCode::Green, VGAColorCode::Black, false);
+const HEAD: ColorData = ColorData::new(VGAColorCode::LightGreen, VGAColorCode::Black, false);
+const FOOD: ColorData = ColorData::new(VGAColorCode::LightRed, VGAColorCode::Black, false);
+const FOOD_GLYPH: u8 = 0x03; // ♥
+const BORDER_GLYPH: u8 = 0xCD; // ═
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    /// The direction an arrow or WASD key asks for.
+    fn from_key(key: DecodedKey) -> Option<Direction> {
+        match key {
+            DecodedKey::RawKey(KeyCode::ArrowUp) | DecodedKey::Unicode('w' | 'W') => {
+                Some(Direction::Up)
+            }
+            DecodedKey::RawKey(KeyCode::ArrowDown) | DecodedKey::Unicode('s' | 'S') => {
+                Some(Direction::Down)
+            }
+            DecodedKey::RawKey(KeyCode::ArrowLeft) | DecodedKey::Unicode('a' | 'A') => {
+                Some(Direction::Left)
+            }
+            DecodedKey::RawKey(KeyCode::ArrowRight) | DecodedKey::Unicode('d' | 'D') => {
+                Some(Direction::Right)
+            }
+            _ => None,
+        }
+    }
+
+    fn opposite(self) -> Direction {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
+
+    /// The neighbouring cell in this direction, or `None` past the edge of the arena.
+    fn step(self, (x, y): (usize, usize)) -> Option<(usize, usize)> {
+        let (x, y) = match self {
+            Direction::Up => (x, y.checked_sub(1)?),
+            Direction::Down => (x, y + 1),
+            Direction::Left => (x.checked_sub(1)?, y),
+            Direction::Right => (x + 1, y),
+        };
+        (x < ARENA_WIDTH && y < ARENA_HEIGHT).then_some((x, y))
+    }
+}
+
+struct Snake {
+    /// Ring of segments from the tail to the head.
+    body: [(u8, u8); MAX_LENGTH],
+    tail: usize,
+    len: usize,
+    direction: Direction,
+    /// Where the next move goes. Only one turn is taken per move, so a quick Up, Left cannot
+    /// reverse the snake into itself.
+    next_direction: Direction,
+    /// Segments still to be added for food eaten.
+    growth: usize,
+    food: (usize, usize),
+    score: usize,
+}
+
+impl Snake {
+    fn new() -> Snake {
+        let mut snake = Snake {
+            body: [(0, 0); MAX_LENGTH],
+            tail: 0,
+            len: 0,
+            direction: Direction::Right,
+            next_direction: Direction::Right,
+            growth: 0,
+            food: (0, 0),
+            score: 0,
+        };
+        for x in 0..4 {
+            snake.push((ARENA_WIDTH / 4 + x, ARENA_HEIGHT / 2));
+        }
+        snake.place_food();
+        snake
+    }
+
+    fn segment(&self, index: usize) -> (usize, usize) {
+        let (x, y) = self.body[(self.tail + index) % MAX_LENGTH];
+        (x as usize, y as usize)
+    }
+
+    fn head(&self) -> (usize, usize) {
+        self.segment(self.len - 1)
+    }
+
+    fn push(&mut self, (x, y): (usize, usize)) {
+        self.body[(self.tail + self.len) % MAX_LENGTH] = (x as u8, y as u8);
+        self.len += 1;
+    }
+
+    fn pop_tail(&mut self) -> (usize, usize) {
+        let tail = self.segment(0);
+        self.tail = (self.tail + 1) % MAX_LENGTH;
+        self.len -= 1;
+        tail
+    }
+
+    /// Whether the snake covers `cell`, not counting the first `skip` segments from the tail.
+    fn covers(&self, cell: (usize, usize), skip: usize) -> bool {
+        (skip..self.len).any(|index| self.segment(index) == cell)
+    }
+
+    fn turn(&mut self, direction: Direction) {
+        if direction != self.direction.opposite() {
+            self.next_direction = direction;
+        }
+    }
+
+    fn step_ticks(&self) -> u64 {
+        let speedups = (self.score / FOOD_PER_SPEEDUP) as u64;
+        START_STEP_TICKS
+            .saturating_sub(speedups)
+            .max(MIN_STEP_TICKS)
+    }
+
+    /// Puts the food on a free cell, searching from a random one. Returns false if the snake
+    /// fills the whole arena.
+    fn place_food(&mut self) -> bool {
+        let start = random::pseudo_rand_in_range_u32(0..MAX_LENGTH as u32) as usize;
+        for offset in 0..MAX_LENGTH {
+            let index = (start + offset) % MAX_LENGTH;
+            let cell = (index % ARENA_WIDTH, index / ARENA_WIDTH);
+            if !self.covers(cell, 0) {
+                self.food = cell;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Moves the snake one cell and draws what changed. Returns false when it hits a wall or
+    /// itself, or when there is no room left for food.
+    fn advance(&mut self, canvas: &mut dyn TextCanvas, arena: Rect) -> bool {
+        self.direction = self.next_direction;
+        let Some(head) = self.direction.step(self.head()) else {
+            return false;
+        };
+        // The tail moves out of the way unless the snake is growing.
+        let skip = usize::from(self.growth == 0);
+        if self.covers(head, skip) || self.len == MAX_LENGTH {
+            return false;
+        }
+
+        let (x, y) = self.head();
+        point(canvas, arena.col + x, arena.row + y, BODY);
+        if self.growth == 0 {
+            let (x, y) = self.pop_tail();
+            canvas.put(b' ', FLOOR, arena.col + x, arena.row + y);
+        } else {
+            self.growth -= 1;
+        }
+        self.push(head);
+        point(canvas, arena.col + head.0, arena.row + head.1, HEAD);
+
+        if head == self.food {
+            self.score += 1;
+            self.growth += GROWTH_PER_FOOD;
+            if !self.place_food() {
+                return false;
+            }
+            self.draw_food(canvas, arena);
+        }
+        true
+    }
+
+    fn draw_food(&self, canvas: &mut dyn TextCanvas, arena: Rect) {
+        let (x, y) = self.food;
+        canvas.put(FOOD_GLYPH, FOOD, arena.col + x, arena.row + y);
+    }
+
+    /// Score and pause state, set into the bottom edge of the window.
+    fn draw_status(&self, canvas: &mut dyn TextCanvas, window: Rect, paused: bool) {
+        let row = window.row + window.height - 1;
+        let edge = Rect::new(window.col + 1, row, window.width - 2, 1);
+        tui::fill(canvas, edge, BORDER_GLYPH, DEFAULT_STYLE.border);
+        tui::draw_fmt(
+            canvas,
+            format_args!(" Score {} ", self.score),
+            DEFAULT_STYLE.title,
+            window.col + 2,
+            row,
+            window.width / 2,
+        );
+        let label = if paused {
+            " Paused "
+        } else {
+            " P pause, Esc quit "
+        };
+        tui::draw_text(
+            canvas,
+            label,
+            DEFAULT_STYLE.title,
+            window.col + window.width - 2 - label.len(),
+            row,
+            window.width / 2,
+        );
+    }
+
+    fn draw(&self, canvas: &mut dyn TextCanvas, window: Window) {
+        window.draw(canvas, &DEFAULT_STYLE);
+        let arena = window.inner();
+        tui::fill(canvas, arena, b' ', FLOOR);
+        for index in 0..self.len {
+            let (x, y) = self.segment(index);
+            let color = if index == self.len - 1 { HEAD } else { BODY };
+            point(canvas, arena.col + x, arena.row + y, color);
+        }
+        self.draw_food(canvas, arena);
+        self.draw_status(canvas, window.rect, false);
+    }
+}
+
+/// The window the arena sits in, centred on `canvas`.
+fn window(canvas: &dyn TextCanvas) -> Window<'static> {
+    Window::new(
+        Rect::centered(ARENA_WIDTH + 2, ARENA_HEIGHT + 2, canvas),
+        Some("Snake"),
+    )
+}
+
+/// Plays Snake on virtual console `console` until Escape is pressed.
+pub fn run(console: usize) {
+    random::seed(interupts::timer_ticks());
+    console::with_canvas(console, |canvas| {
+        let (cols, rows) = canvas.size();
+        tui::fill(canvas, Rect::new(0, 0, cols, rows), b' ', FLOOR);
+    });
+
+    while let Some(score) = play(console) {
+        if !game_over(console, score) {
+            return;
+        }
+    }
+}
+
+/// One game. Returns the score, or `None` if the player quit.
+fn play(console: usize) -> Option<usize> {
+    let mut snake = Snake::new();
+    console::with_canvas(console, |canvas| {
+        let window = window(canvas);
+        snake.draw(canvas, window);
+    });
+
+    let mut paused = false;
+    let mut next_step = interupts::timer_ticks() + snake.step_ticks();
+    loop {
+        while let Some(key) = console::read_key(console) {
+            match key {
+                DecodedKey::Unicode('\x1b') => return None,
+                DecodedKey::Unicode('p' | 'P') => {
+                    paused = !paused;
+                    console::with_canvas(console, |canvas| {
+                        let window = window(canvas);
+                        snake.draw_status(canvas, window.rect, paused);
+                    });
+                }
+                key => {
+                    if let Some(direction) = Direction::from_key(key) {
+                        snake.turn(direction);
+                    }
+                }
+            }
+        }
+
+        let now = interupts::timer_ticks();
+        if !paused && now >= next_step {
+            next_step = now + snake.step_ticks();
+            let alive = console::with_canvas(console, |canvas| {
+                let window = window(canvas);
+                let alive = snake.advance(canvas, window.inner());
+                snake.draw_status(canvas, window.rect, false);
+                alive
+            });
+            if !alive {
+                return Some(snake.score);
+            }
+        }
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Shows the final score. Returns true if the player wants another game.
+fn game_over(console: usize, score: usize) -> bool {
+    console::with_canvas(console, |canvas| {
+        let window = Window::new(Rect::centered(24, 7, canvas), Some("Game over"));
+        window.draw(canvas, &DEFAULT_STYLE);
+        let inner = window.inner();
+        tui::draw_fmt(
+            canvas,
+            format_args!("Score: {}", score),
+            DEFAULT_STYLE.title,
+            inner.col + 2,
+            inner.row + 1,
+            inner.width - 2,
+        );
+        tui::draw_text(
+            canvas,
+            "Enter: play again",
+            DEFAULT_STYLE.text,
+            inner.col + 2,
+            inner.row + 3,
+            inner.width - 2,
+        );
+        tui::draw_text(
+            canvas,
+            "Esc: quit",
+            DEFAULT_STYLE.text,
+            inner.col + 2,
+            inner.row + 4,
+            inner.width - 2,
+        );
+    });
+
+    loop {
+        match console::wait_key(console) {
+            DecodedKey::Unicode('\n') => return true,
+            DecodedKey::Unicode('\x1b') => return false,
+            _ => {}
+        }
+    }
+}
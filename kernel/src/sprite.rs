@@ -0,0 +1,102 @@
+use crate::framebuffer::{ClipRect, Color, Renderer, Rgba};
+
+/// An RGBA picture in memory, rows top to bottom with no padding between them.
+#[derive(Debug, Clone, Copy)]
+pub struct Image<'a> {
+    width: usize,
+    height: usize,
+    pixels: &'a [Rgba],
+}
+
+impl<'a> Image<'a> {
+    /// `None` if `pixels` holds fewer than `width * height` pixels.
+    pub fn new(width: usize, height: usize, pixels: &'a [Rgba]) -> Option<Image<'a>> {
+        let len = width.checked_mul(height)?;
+        Some(Image {
+            width,
+            height,
+            pixels: pixels.get(..len)?,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &'a [Rgba] {
+        self.pixels
+    }
+
+    pub fn row(&self, y: usize) -> &'a [Rgba] {
+        &self.pixels[y * self.width..][..self.width]
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Rgba {
+        self.row(y)[x]
+    }
+}
+
+/// An image as drawn on screen: blown up by a whole-number factor and optionally with one
+/// colour treated as transparent, on top of whatever alpha the image carries.
+#[derive(Debug, Clone, Copy)]
+pub struct Sprite<'a> {
+    pub image: Image<'a>,
+    /// Pixels of this colour are skipped, whatever their alpha.
+    pub color_key: Option<Color>,
+    /// Each image pixel becomes a `scale` by `scale` block.
+    pub scale: i32,
+}
+
+impl<'a> Sprite<'a> {
+    pub const fn new(image: Image<'a>) -> Sprite<'a> {
+        Sprite {
+            image,
+            color_key: None,
+            scale: 1,
+        }
+    }
+
+    /// Size on screen in pixels.
+    pub fn size(&self) -> (i32, i32) {
+        let scale = self.scale.max(1);
+        (
+            self.image.width as i32 * scale,
+            self.image.height as i32 * scale,
+        )
+    }
+}
+
+impl Renderer<'_> {
+    /// Blends `image` onto the screen with its top left corner at `x`, `y`.
+    pub fn draw_image(&mut self, image: &Image, x: i32, y: i32) {
+        self.draw_sprite(&Sprite::new(*image), x, y);
+    }
+
+    /// Blends `sprite` onto the screen with its top left corner at `x`, `y`, clipped to the
+    /// clip rectangle.
+    pub fn draw_sprite(&mut self, sprite: &Sprite, x: i32, y: i32) {
+        let scale = sprite.scale.max(1);
+        let (w, h) = sprite.size();
+        let area = ClipRect::new(x, y, w, h).intersect(self.clip());
+        if area.is_empty() {
+            return;
+        }
+
+        let first_col = area.x - x;
+        for screen_y in area.y..area.y + area.h {
+            let row = sprite.image.row(((screen_y - y) / scale) as usize);
+            let pixels = (first_col..first_col + area.w).map(|col| {
+                let pixel = row[(col / scale) as usize];
+                match sprite.color_key {
+                    Some(key) if pixel.rgb() == key => Rgba::TRANSPARENT,
+                    _ => pixel,
+                }
+            });
+            self.blend_row(area.x, screen_y, pixels);
+        }
+    }
+}
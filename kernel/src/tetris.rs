@@ -0,0 +1,460 @@
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use crate::console;
+use crate::interupts;
+use crate::random;
+use crate::tui::{self, Rect, TextCanvas, Window, DEFAULT_STYLE};
+use crate::vga_text_mode::{ColorData, VGAColorCode};
+use crate::vga_text_mode_drawing::point;
+
+/// Size of the well in cells. Each cell is two columns wide so the blocks come out square-ish.
+pub const WELL_WIDTH: usize = 10;
+pub const WELL_HEIGHT: usize = 20;
+
+/// Width of the score and preview panels to the right of the well.
+const SIDE_WIDTH: usize = 16;
+
+/// Timer ticks per row of fall on level 0, and how much faster each level gets.
+const START_FALL_TICKS: u64 = 30;
+const FALL_TICKS_PER_LEVEL: u64 = 3;
+const MIN_FALL_TICKS: u64 = 2;
+const LINES_PER_LEVEL: usize = 10;
+
+/// Points for clearing 1 to 4 lines at once, multiplied by the level plus one.
+const LINE_POINTS: [usize; 5] = [0, 100, 300, 500, 800];
+
+/// The seven tetrominoes in their four rotations. Each is a 4x4 grid, one nibble per row with
+/// the top row in the high nibble and the leftmost cell in the high bit of each nibble.
+const PIECES: [[u16; 4]; 7] = [
+    [0x0F00, 0x2222, 0x00F0, 0x4444], // I
+    [0x8E00, 0x6440, 0x0E20, 0x44C0], // J
+    [0x2E00, 0x4460, 0x0E80, 0xC440], // L
+    [0x6600, 0x6600, 0x6600, 0x6600], // O
+    [0x6C00, 0x4620, 0x06C0, 0x8C40], // S
+    [0x4E00, 0x4640, 0x0E40, 0x4C40], // T
+    [0xC600, 0x2640, 0x0C60, 0x4C80], // Z
+];
+
+const PIECE_COLORS: [VGAColorCode; 7] = [
+    VGAColorCode::LightCyan,
+    VGAColorCode::LightBlue,
+    VGAColorCode::Brown,
+    VGAColorCode::Yellow,
+    VGAColorCode::LightGreen,
+    VGAColorCode::Pink,
+    VGAColorCode::LightRed,
+];
+
+/// Column offsets tried, in order, when a rotation does not fit where the piece is.
+const WALL_KICKS: [isize; 5] = [0, -1, 1, -2, 2];
+
+const EMPTY: ColorData = ColorData::new(VGAColorCode::DarkGray, VGAColorCode::Black, false);
+const EMPTY_GLYPH: u8 = 0xFA; // ·
+
+/// Cell value for an empty cell; filled cells hold the piece kind plus one.
+const FREE: u8 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Piece {
+    kind: usize,
+    rotation: usize,
+    x: isize,
+    y: isize,
+}
+
+impl Piece {
+    fn spawn(kind: usize) -> Piece {
+        Piece {
+            kind,
+            rotation: 0,
+            x: (WELL_WIDTH as isize - 4) / 2,
+            y: 0,
+        }
+    }
+
+    /// The well cells the piece covers.
+    fn cells(self) -> impl Iterator<Item = (isize, isize)> {
+        let mask = PIECES[self.kind][self.rotation];
+        (0..16)
+            .filter(move |bit| mask & (0x8000 >> bit) != 0)
+            .map(move |bit| (self.x + bit % 4, self.y + bit / 4))
+    }
+
+    fn moved(self, dx: isize, dy: isize) -> Piece {
+        Piece {
+            x: self.x + dx,
+            y: self.y + dy,
+            ..self
+        }
+    }
+}
+
+struct Tetris {
+    well: [[u8; WELL_WIDTH]; WELL_HEIGHT],
+    piece: Piece,
+    next: usize,
+    score: usize,
+    lines: usize,
+    /// What is on screen, so only changed cells get drawn.
+    shown: [[Option<u8>; WELL_WIDTH]; WELL_HEIGHT],
+}
+
+impl Tetris {
+    fn new() -> Tetris {
+        Tetris {
+            well: [[FREE; WELL_WIDTH]; WELL_HEIGHT],
+            piece: Piece::spawn(random_kind()),
+            next: random_kind(),
+            score: 0,
+            lines: 0,
+            shown: [[None; WELL_WIDTH]; WELL_HEIGHT],
+        }
+    }
+
+    fn level(&self) -> usize {
+        self.lines / LINES_PER_LEVEL
+    }
+
+    fn fall_ticks(&self) -> u64 {
+        let speedup = self.level() as u64 * FALL_TICKS_PER_LEVEL;
+        START_FALL_TICKS.saturating_sub(speedup).max(MIN_FALL_TICKS)
+    }
+
+    fn fits(&self, piece: Piece) -> bool {
+        piece.cells().all(|(x, y)| {
+            (0..WELL_WIDTH as isize).contains(&x)
+                && y < WELL_HEIGHT as isize
+                // Cells above the well are allowed while a piece enters it.
+                && (y < 0 || self.well[y as usize][x as usize] == FREE)
+        })
+    }
+
+    fn try_move(&mut self, dx: isize, dy: isize) -> bool {
+        let moved = self.piece.moved(dx, dy);
+        if !self.fits(moved) {
+            return false;
+        }
+        self.piece = moved;
+        true
+    }
+
+    fn rotate(&mut self) {
+        let rotated = Piece {
+            rotation: (self.piece.rotation + 1) % 4,
+            ..self.piece
+        };
+        for dx in WALL_KICKS {
+            if self.fits(rotated.moved(dx, 0)) {
+                self.piece = rotated.moved(dx, 0);
+                return;
+            }
+        }
+    }
+
+    /// Drops the piece straight to the bottom, two points per row, and locks it.
+    fn hard_drop(&mut self) -> bool {
+        while self.try_move(0, 1) {
+            self.score += 2;
+        }
+        self.lock()
+    }
+
+    /// Moves the piece down a row, locking it if it cannot go further. Returns false once a new
+    /// piece no longer fits, which ends the game.
+    fn fall(&mut self) -> bool {
+        self.try_move(0, 1) || self.lock()
+    }
+
+    /// Adds the piece to the well, clears full lines and brings in the next piece.
+    fn lock(&mut self) -> bool {
+        for (x, y) in self.piece.cells() {
+            if y < 0 {
+                // Locked sticking out of the top.
+                return false;
+            }
+            self.well[y as usize][x as usize] = self.piece.kind as u8 + 1;
+        }
+
+        let mut cleared = 0;
+        for row in 0..WELL_HEIGHT {
+            if self.well[row].iter().all(|&cell| cell != FREE) {
+                self.well.copy_within(0..row, 1);
+                self.well[0] = [FREE; WELL_WIDTH];
+                cleared += 1;
+            }
+        }
+        self.score += LINE_POINTS[cleared] * (self.level() + 1);
+        self.lines += cleared;
+
+        self.piece = Piece::spawn(self.next);
+        self.next = random_kind();
+        self.fits(self.piece)
+    }
+
+    /// Draws the well cells that changed since the last call.
+    fn draw_well(&mut self, canvas: &mut dyn TextCanvas, well: Rect) {
+        let mut cells = self.well;
+        for (x, y) in self.piece.cells() {
+            if y >= 0 {
+                cells[y as usize][x as usize] = self.piece.kind as u8 + 1;
+            }
+        }
+
+        for (y, row) in cells.iter().enumerate() {
+            for (x, &cell) in row.iter().enumerate() {
+                if self.shown[y][x] == Some(cell) {
+                    continue;
+                }
+                self.shown[y][x] = Some(cell);
+                draw_cell(canvas, well.col + 2 * x, well.row + y, cell);
+            }
+        }
+    }
+
+    /// Next piece, score and controls, in panels right of the well.
+    fn draw_side(&self, canvas: &mut dyn TextCanvas, side: Rect, paused: bool) {
+        let style = &DEFAULT_STYLE;
+        let preview = Window::panel(Rect::new(side.col, side.row, side.width, 6), Some("Next"));
+        preview.draw(canvas, style);
+        let inner = preview.inner();
+        let mask = PIECES[self.next][0];
+        for bit in 0..16 {
+            let (x, y) = (bit % 4, bit / 4);
+            let col = inner.col + (inner.width - 8) / 2 + 2 * x;
+            if mask & (0x8000 >> bit) != 0 {
+                point(canvas, col, inner.row + y, piece_color(self.next));
+                point(canvas, col + 1, inner.row + y, piece_color(self.next));
+            }
+        }
+
+        let stats = Window::panel(
+            Rect::new(side.col, side.row + 6, side.width, 6),
+            Some("Score"),
+        );
+        stats.draw(canvas, style);
+        let inner = stats.inner();
+        let width = inner.width - 2;
+        for (row, (label, value)) in [
+            ("Score", self.score),
+            ("Lines", self.lines),
+            ("Level", self.level()),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            tui::draw_fmt(
+                canvas,
+                format_args!("{:<6}{:>w$}", label, value, w = width - 6),
+                style.text,
+                inner.col + 1,
+                inner.row + row,
+                width,
+            );
+        }
+        if paused {
+            tui::draw_text(
+                canvas,
+                "Paused",
+                style.title,
+                inner.col + 1,
+                inner.row + 3,
+                width,
+            );
+        }
+
+        let keys = Window::panel(
+            Rect::new(side.col, side.row + 12, side.width, side.height - 12),
+            Some("Keys"),
+        );
+        keys.draw(canvas, style);
+        let inner = keys.inner();
+        for (row, line) in [
+            "←→  move",
+            "↑   rotate",
+            "↓   soft drop",
+            "Spc hard drop",
+            "P   pause",
+            "Esc quit",
+        ]
+        .into_iter()
+        .enumerate()
+        .take(inner.height)
+        {
+            tui::draw_text(
+                canvas,
+                line,
+                style.text,
+                inner.col + 1,
+                inner.row + row,
+                width,
+            );
+        }
+    }
+}
+
+fn random_kind() -> usize {
+    random::pseudo_rand_in_range_u32(0..PIECES.len() as u32) as usize
+}
+
+fn piece_color(kind: usize) -> ColorData {
+    ColorData::new(PIECE_COLORS[kind], VGAColorCode::Black, false)
+}
+
+fn draw_cell(canvas: &mut dyn TextCanvas, col: usize, row: usize, cell: u8) {
+    if cell == FREE {
+        canvas.put(b' ', EMPTY, col, row);
+        canvas.put(EMPTY_GLYPH, EMPTY, col + 1, row);
+    } else {
+        let color = piece_color(cell as usize - 1);
+        point(canvas, col, row, color);
+        point(canvas, col + 1, row, color);
+    }
+}
+
+/// Where the well window and the side panels go, centred on `canvas`.
+fn layout(canvas: &dyn TextCanvas) -> (Window<'static>, Rect) {
+    let height = WELL_HEIGHT + 2;
+    let well_width = 2 * WELL_WIDTH + 2;
+    let area = Rect::centered(well_width + 1 + SIDE_WIDTH, height, canvas);
+    let well = Window::new(
+        Rect::new(area.col, area.row, well_width, height),
+        Some("Tetris"),
+    );
+    let side = Rect::new(area.col + well_width + 1, area.row, SIDE_WIDTH, height);
+    (well, side)
+}
+
+/// Plays Tetris on virtual console `console` until Escape is pressed.
+pub fn run(console: usize) {
+    random::seed(interupts::timer_ticks());
+    console::with_canvas(console, |canvas| {
+        let (cols, rows) = canvas.size();
+        tui::fill(canvas, Rect::new(0, 0, cols, rows), b' ', EMPTY);
+    });
+
+    while let Some(score) = play(console) {
+        if !game_over(console, score) {
+            return;
+        }
+    }
+}
+
+/// One game. Returns the score, or `None` if the player quit.
+fn play(console: usize) -> Option<usize> {
+    let mut game = Tetris::new();
+    console::with_canvas(console, |canvas| {
+        let (well, side) = layout(canvas);
+        well.draw(canvas, &DEFAULT_STYLE);
+        game.draw_well(canvas, well.inner());
+        game.draw_side(canvas, side, false);
+    });
+
+    let mut paused = false;
+    let mut next_fall = interupts::timer_ticks() + game.fall_ticks();
+    loop {
+        let mut alive = true;
+        let mut changed = false;
+        while let Some(key) = console::read_key(console) {
+            if paused {
+                match key {
+                    DecodedKey::Unicode('\x1b') => return None,
+                    DecodedKey::Unicode('p' | 'P') => {
+                        paused = false;
+                        changed = true;
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            match key {
+                DecodedKey::Unicode('\x1b') => return None,
+                DecodedKey::Unicode('p' | 'P') => paused = true,
+                DecodedKey::RawKey(KeyCode::ArrowLeft) | DecodedKey::Unicode('a' | 'A') => {
+                    game.try_move(-1, 0);
+                }
+                DecodedKey::RawKey(KeyCode::ArrowRight) | DecodedKey::Unicode('d' | 'D') => {
+                    game.try_move(1, 0);
+                }
+                DecodedKey::RawKey(KeyCode::ArrowUp) | DecodedKey::Unicode('w' | 'W') => {
+                    game.rotate();
+                }
+                DecodedKey::RawKey(KeyCode::ArrowDown) | DecodedKey::Unicode('s' | 'S') => {
+                    if game.try_move(0, 1) {
+                        game.score += 1;
+                    }
+                }
+                DecodedKey::Unicode(' ') => {
+                    alive = game.hard_drop();
+                    next_fall = interupts::timer_ticks() + game.fall_ticks();
+                }
+                _ => continue,
+            }
+            changed = true;
+        }
+
+        let now = interupts::timer_ticks();
+        if !paused && now >= next_fall {
+            alive = alive && game.fall();
+            next_fall = now + game.fall_ticks();
+            changed = true;
+        }
+
+        if changed {
+            console::with_canvas(console, |canvas| {
+                let (well, side) = layout(canvas);
+                game.draw_well(canvas, well.inner());
+                game.draw_side(canvas, side, paused);
+            });
+        }
+        if !alive {
+            return Some(game.score);
+        }
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Shows the final score. Returns true if the player wants another game.
+fn game_over(console: usize, score: usize) -> bool {
+    console::with_canvas(console, |canvas| {
+        let (well, _) = layout(canvas);
+        let inner = well.inner();
+        let window = Window::new(
+            Rect::new(inner.col, inner.row + inner.height / 2 - 4, inner.width, 7),
+            Some("Game over"),
+        );
+        window.draw(canvas, &DEFAULT_STYLE);
+        let inner = window.inner();
+        tui::draw_fmt(
+            canvas,
+            format_args!("Score: {}", score),
+            DEFAULT_STYLE.title,
+            inner.col + 1,
+            inner.row + 1,
+            inner.width - 2,
+        );
+        tui::draw_text(
+            canvas,
+            "Enter: again",
+            DEFAULT_STYLE.text,
+            inner.col + 1,
+            inner.row + 3,
+            inner.width - 2,
+        );
+        tui::draw_text(
+            canvas,
+            "Esc: quit",
+            DEFAULT_STYLE.text,
+            inner.col + 1,
+            inner.row + 4,
+            inner.width - 2,
+        );
+    });
+
+    loop {
+        match console::wait_key(console) {
+            DecodedKey::Unicode('\n') => return true,
+            DecodedKey::Unicode('\x1b') => return false,
+            _ => {}
+        }
+    }
+}
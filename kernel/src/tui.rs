@@ -0,0 +1,464 @@
+use core::fmt::{self, Write};
+
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use crate::console;
+use crate::cp437;
+use crate::vga_text_mode::{ColorData, VGAColorCode};
+
+/// A grid of character cells the TUI widgets draw into.
+pub trait TextCanvas {
+    /// Columns and rows.
+    fn size(&self) -> (usize, usize);
+    /// Puts code page 437 glyph `byte` at `col`, `row`. Cells off the canvas are ignored.
+    fn put(&mut self, byte: u8, color: ColorData, col: usize, row: usize);
+}
+
+/// Colours the widgets are drawn in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    pub border: ColorData,
+    pub title: ColorData,
+    pub text: ColorData,
+    /// The selected menu item and the button of a message box.
+    pub highlight: ColorData,
+    pub bar_filled: ColorData,
+    pub bar_empty: ColorData,
+}
+
+/// White on blue, like the BIOS setup screens.
+pub const DEFAULT_STYLE: Style = Style {
+    border: ColorData::new(VGAColorCode::White, VGAColorCode::Blue, false),
+    title: ColorData::new(VGAColorCode::Yellow, VGAColorCode::Blue, false),
+    text: ColorData::new(VGAColorCode::LightGray, VGAColorCode::Blue, false),
+    highlight: ColorData::new(VGAColorCode::Black, VGAColorCode::LightGray, false),
+    bar_filled: ColorData::new(VGAColorCode::LightGreen, VGAColorCode::Blue, false),
+    bar_empty: ColorData::new(VGAColorCode::DarkGray, VGAColorCode::Blue, false),
+};
+
+const FULL_BLOCK: u8 = 0xDB; // █
+const LIGHT_SHADE: u8 = 0xB0; // ░
+
+/// Cell rectangle in columns and rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub col: usize,
+    pub row: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(col: usize, row: usize, width: usize, height: usize) -> Rect {
+        Rect {
+            col,
+            row,
+            width,
+            height,
+        }
+    }
+
+    /// A `width` by `height` rectangle in the middle of `canvas`, shrunk to fit if needed.
+    pub fn centered(width: usize, height: usize, canvas: &dyn TextCanvas) -> Rect {
+        let (cols, rows) = canvas.size();
+        let width = width.min(cols);
+        let height = height.min(rows);
+        Rect::new((cols - width) / 2, (rows - height) / 2, width, height)
+    }
+
+    /// The rectangle inside a one-cell border.
+    pub fn inner(&self) -> Rect {
+        Rect::new(
+            self.col + 1,
+            self.row + 1,
+            self.width.saturating_sub(2),
+            self.height.saturating_sub(2),
+        )
+    }
+}
+
+/// Line style of a box outline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Border {
+    Single,
+    Double,
+}
+
+impl Border {
+    /// Top left, top right, bottom left, bottom right, horizontal, vertical, and the pieces
+    /// either side of a title.
+    const fn glyphs(self) -> [u8; 8] {
+        match self {
+            // ┌ ┐ └ ┘ ─ │ ┤ ├
+            Border::Single => [0xDA, 0xBF, 0xC0, 0xD9, 0xC4, 0xB3, 0xB4, 0xC3],
+            // ╔ ╗ ╚ ╝ ═ ║ ╡ ╞
+            Border::Double => [0xC9, 0xBB, 0xC8, 0xBC, 0xCD, 0xBA, 0xB5, 0xC6],
+        }
+    }
+}
+
+/// Fills `rect` with `byte`.
+pub fn fill(canvas: &mut dyn TextCanvas, rect: Rect, byte: u8, color: ColorData) {
+    for row in rect.row..rect.row + rect.height {
+        for col in rect.col..rect.col + rect.width {
+            canvas.put(byte, color, col, row);
+        }
+    }
+}
+
+/// Writes `text` starting at `col`, `row`, cut off after `max_width` cells. Returns the number
+/// of cells written.
+pub fn draw_text(
+    canvas: &mut dyn TextCanvas,
+    text: &str,
+    color: ColorData,
+    col: usize,
+    row: usize,
+    max_width: usize,
+) -> usize {
+    let mut written = 0;
+    for c in text.chars().take(max_width) {
+        canvas.put(cp437::from_char(c), color, col + written, row);
+        written += 1;
+    }
+    written
+}
+
+/// `draw_text` for formatted text, e.g. `format_args!("Score {}", score)`.
+pub fn draw_fmt(
+    canvas: &mut dyn TextCanvas,
+    args: fmt::Arguments,
+    color: ColorData,
+    col: usize,
+    row: usize,
+    max_width: usize,
+) -> usize {
+    struct CanvasWriter<'a> {
+        canvas: &'a mut dyn TextCanvas,
+        color: ColorData,
+        col: usize,
+        row: usize,
+        room: usize,
+    }
+
+    impl Write for CanvasWriter<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let written = draw_text(self.canvas, s, self.color, self.col, self.row, self.room);
+            self.col += written;
+            self.room -= written;
+            Ok(())
+        }
+    }
+
+    let mut writer = CanvasWriter {
+        canvas,
+        color,
+        col,
+        row,
+        room: max_width,
+    };
+    let _ = writer.write_fmt(args);
+    max_width - writer.room
+}
+
+/// Draws the outline of `rect`.
+pub fn draw_box(canvas: &mut dyn TextCanvas, rect: Rect, border: Border, color: ColorData) {
+    if rect.width < 2 || rect.height < 2 {
+        return;
+    }
+    let [top_left, top_right, bottom_left, bottom_right, horizontal, vertical, ..] =
+        border.glyphs();
+    let right = rect.col + rect.width - 1;
+    let bottom = rect.row + rect.height - 1;
+
+    for col in rect.col + 1..right {
+        canvas.put(horizontal, color, col, rect.row);
+        canvas.put(horizontal, color, col, bottom);
+    }
+    for row in rect.row + 1..bottom {
+        canvas.put(vertical, color, rect.col, row);
+        canvas.put(vertical, color, right, row);
+    }
+    canvas.put(top_left, color, rect.col, rect.row);
+    canvas.put(top_right, color, right, rect.row);
+    canvas.put(bottom_left, color, rect.col, bottom);
+    canvas.put(bottom_right, color, right, bottom);
+}
+
+/// A filled, bordered box with an optional title set into its top edge. Windows have a double
+/// border and a centred title; panels, meant for grouping things inside a window, have a single
+/// border and the title on the left.
+#[derive(Debug, Clone, Copy)]
+pub struct Window<'a> {
+    pub rect: Rect,
+    pub title: Option<&'a str>,
+    pub border: Border,
+    pub center_title: bool,
+}
+
+impl<'a> Window<'a> {
+    pub const fn new(rect: Rect, title: Option<&'a str>) -> Window<'a> {
+        Window {
+            rect,
+            title,
+            border: Border::Double,
+            center_title: true,
+        }
+    }
+
+    pub const fn panel(rect: Rect, title: Option<&'a str>) -> Window<'a> {
+        Window {
+            rect,
+            title,
+            border: Border::Single,
+            center_title: false,
+        }
+    }
+
+    /// Where the contents go.
+    pub fn inner(&self) -> Rect {
+        self.rect.inner()
+    }
+
+    pub fn draw(&self, canvas: &mut dyn TextCanvas, style: &Style) {
+        fill(canvas, self.rect.inner(), b' ', style.text);
+        draw_box(canvas, self.rect, self.border, style.border);
+
+        let Some(title) = self.title else {
+            return;
+        };
+        // Room for the corners, the pieces either side and a space of padding each way.
+        let room = self.rect.width.saturating_sub(6);
+        let len = title.chars().count().min(room);
+        if len == 0 {
+            return;
+        }
+        let col = if self.center_title {
+            self.rect.col + (self.rect.width - len - 4) / 2
+        } else {
+            self.rect.col + 1
+        };
+        let [.., left, right] = self.border.glyphs();
+        canvas.put(left, style.border, col, self.rect.row);
+        canvas.put(b' ', style.title, col + 1, self.rect.row);
+        draw_text(canvas, title, style.title, col + 2, self.rect.row, len);
+        canvas.put(b' ', style.title, col + 2 + len, self.rect.row);
+        canvas.put(right, style.border, col + 3 + len, self.rect.row);
+    }
+}
+
+/// A horizontal bar showing how far along something is, followed by the percentage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressBar {
+    pub value: usize,
+    pub max: usize,
+}
+
+impl ProgressBar {
+    pub const fn new(max: usize) -> ProgressBar {
+        ProgressBar { value: 0, max }
+    }
+
+    pub fn set(&mut self, value: usize) {
+        self.value = value.min(self.max);
+    }
+
+    /// Percentage done, 100 for an empty range.
+    pub fn percent(&self) -> usize {
+        match self.max {
+            0 => 100,
+            max => self.value.min(max) * 100 / max,
+        }
+    }
+
+    /// Draws the bar `width` cells wide at `col`, `row`, the last five of them for the percentage.
+    pub fn draw(
+        &self,
+        canvas: &mut dyn TextCanvas,
+        style: &Style,
+        col: usize,
+        row: usize,
+        width: usize,
+    ) {
+        let bar = width.saturating_sub(5);
+        let filled = bar * self.percent() / 100;
+        for x in 0..bar {
+            if x < filled {
+                canvas.put(FULL_BLOCK, style.bar_filled, col + x, row);
+            } else {
+                canvas.put(LIGHT_SHADE, style.bar_empty, col + x, row);
+            }
+        }
+
+        let mut label = *b" 100%";
+        let percent = self.percent();
+        label[1] = if percent >= 100 { b'1' } else { b' ' };
+        label[2] = if percent >= 10 {
+            b'0' + (percent / 10 % 10) as u8
+        } else {
+            b' '
+        };
+        label[3] = b'0' + (percent % 10) as u8;
+        for (x, &byte) in label.iter().enumerate().take(width - bar) {
+            canvas.put(byte, style.text, col + bar + x, row);
+        }
+    }
+}
+
+/// What a key did to a menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuEvent {
+    /// The key was not for the menu.
+    Ignored,
+    /// The selection moved and the menu needs redrawing.
+    Moved,
+    /// Enter was pressed on the item at this index.
+    Chosen(usize),
+    /// Escape was pressed.
+    Cancelled,
+}
+
+/// A vertical list of items, one of them selected, driven by Up/Down, Home/End, Enter and Escape.
+#[derive(Debug, Clone, Copy)]
+pub struct Menu<'a> {
+    pub items: &'a [&'a str],
+    pub selected: usize,
+}
+
+impl<'a> Menu<'a> {
+    pub const fn new(items: &'a [&'a str]) -> Menu<'a> {
+        Menu { items, selected: 0 }
+    }
+
+    /// Cells needed to show every item with a space either side.
+    pub fn width(&self) -> usize {
+        self.items
+            .iter()
+            .map(|item| item.chars().count() + 2)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn handle_key(&mut self, key: DecodedKey) -> MenuEvent {
+        let last = self.items.len().saturating_sub(1);
+        let selected = match key {
+            DecodedKey::Unicode('\n') => return MenuEvent::Chosen(self.selected),
+            DecodedKey::Unicode('\x1b') => return MenuEvent::Cancelled,
+            // Wrap around at either end.
+            DecodedKey::RawKey(KeyCode::ArrowUp) => {
+                if self.selected == 0 {
+                    last
+                } else {
+                    self.selected - 1
+                }
+            }
+            DecodedKey::RawKey(KeyCode::ArrowDown) => {
+                if self.selected >= last {
+                    0
+                } else {
+                    self.selected + 1
+                }
+            }
+            DecodedKey::RawKey(KeyCode::Home) => 0,
+            DecodedKey::RawKey(KeyCode::End) => last,
+            _ => return MenuEvent::Ignored,
+        };
+        self.selected = selected;
+        MenuEvent::Moved
+    }
+
+    /// Draws the items into `rect`, one per row, scrolled so the selected one is visible.
+    pub fn draw(&self, canvas: &mut dyn TextCanvas, style: &Style, rect: Rect) {
+        let first = (self.selected + 1).saturating_sub(rect.height);
+        for row in 0..rect.height {
+            let color = if first + row == self.selected {
+                style.highlight
+            } else {
+                style.text
+            };
+            fill(
+                canvas,
+                Rect::new(rect.col, rect.row + row, rect.width, 1),
+                b' ',
+                color,
+            );
+            if let Some(item) = self.items.get(first + row) {
+                let width = rect.width.saturating_sub(2);
+                draw_text(canvas, item, color, rect.col + 1, rect.row + row, width);
+            }
+        }
+    }
+}
+
+/// Shows `items` in a window on virtual console `console` and lets the user pick one with the
+/// arrow keys and Enter. Returns `None` if Escape was pressed.
+pub fn run_menu(console: usize, title: &str, items: &[&str], style: &Style) -> Option<usize> {
+    let mut menu = Menu::new(items);
+    let width = menu.width().max(title.chars().count() + 4) + 2;
+    let height = items.len() + 2;
+
+    loop {
+        console::with_canvas(console, |canvas| {
+            let window = Window::new(Rect::centered(width, height, canvas), Some(title));
+            window.draw(canvas, style);
+            menu.draw(canvas, style, window.inner());
+        });
+
+        match menu.handle_key(console::wait_key(console)) {
+            MenuEvent::Chosen(index) => return Some(index),
+            MenuEvent::Cancelled => return None,
+            MenuEvent::Moved | MenuEvent::Ignored => {}
+        }
+    }
+}
+
+/// Shows `text` in a window on virtual console `console` until Enter or Escape is pressed.
+/// Lines are split at `\n`.
+pub fn message_box(console: usize, title: &str, text: &str, style: &Style) {
+    const BUTTON: &str = "[ OK ]";
+
+    let text_width = text.lines().map(|line| line.chars().count()).max();
+    let width = text_width
+        .unwrap_or(0)
+        .max(title.chars().count() + 4)
+        .max(BUTTON.len())
+        + 4;
+    // Border, text, a blank line, the button and the border again.
+    let height = text.lines().count() + 4;
+
+    console::with_canvas(console, |canvas| {
+        let window = Window::new(Rect::centered(width, height, canvas), Some(title));
+        window.draw(canvas, style);
+        let inner = window.inner();
+        for (row, line) in text
+            .lines()
+            .enumerate()
+            .take(inner.height.saturating_sub(2))
+        {
+            draw_text(
+                canvas,
+                line,
+                style.text,
+                inner.col + 1,
+                inner.row + row,
+                inner.width.saturating_sub(2),
+            );
+        }
+        let button_col = inner.col + inner.width.saturating_sub(BUTTON.len()) / 2;
+        let button_row = inner.row + inner.height.saturating_sub(1);
+        draw_text(
+            canvas,
+            BUTTON,
+            style.highlight,
+            button_col,
+            button_row,
+            inner.width,
+        );
+    });
+
+    loop {
+        if let DecodedKey::Unicode('\n' | '\x1b') = console::wait_key(console) {
+            return;
+        }
+    }
+}
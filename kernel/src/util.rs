@@ -1,7 +1,19 @@
 use core::panic::PanicInfo;
 
+use crate::console::{self, KERNEL_CONSOLE};
+use crate::serial;
+
+#[cfg(not(test))]
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
+fn panic(info: &PanicInfo) -> ! {
+    x86_64::instructions::interrupts::disable();
+    // Not through `log`: its sinks lock the serial port and the consoles, and the panic may
+    // have happened with one of those locks held.
+    serial::write_unlocked(format_args!("[PANIC] {}\n", info));
+    console::try_print_to(
+        KERNEL_CONSOLE,
+        format_args!("\x1b[91mpanic: {}\x1b[0m\n", info),
+    );
     halt_loop()
 }
 
@@ -32,3 +44,29 @@ pub fn halt_loop() -> ! {
         x86_64::instructions::hlt();
     }
 }
+
+/// Resets the machine through the keyboard controller, falling back to a triple fault.
+pub fn reboot() -> ! {
+    use x86_64::instructions::port::Port;
+    use x86_64::instructions::tables::lidt;
+    use x86_64::structures::DescriptorTablePointer;
+    use x86_64::VirtAddr;
+
+    x86_64::instructions::interrupts::disable();
+    unsafe {
+        let mut status: Port<u8> = Port::new(0x64);
+        // Wait for the controller's input buffer to drain, then pulse the CPU reset line.
+        while status.read() & 0x02 != 0 {
+            core::hint::spin_loop();
+        }
+        status.write(0xFE);
+
+        let empty = DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::zero(),
+        };
+        lidt(&empty);
+        x86_64::instructions::interrupts::int3();
+    }
+    halt_loop()
+}
@@ -2,6 +2,9 @@ use lazy_static::lazy_static;
 use volatile::Volatile; //  prevents the compiler from optimizing away reads and writes to memory that has side-effects.
 
 use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use crate::tui::TextCanvas;
 
 lazy_static! {
     pub static ref VGA_TEXT_MODE: Mutex<VGATextMode> = Mutex::new(VGATextMode::new());
@@ -32,13 +35,62 @@ pub enum VGAColorCode {
 pub const BUFFER_HEIGHT: usize = 25;
 pub const BUFFER_WIDTH: usize = 80;
 
+// CRTC registers driving the hardware text cursor.
+const CRTC_ADDRESS_PORT: u16 = 0x3D4;
+const CRTC_DATA_PORT: u16 = 0x3D5;
+const CRTC_CURSOR_START: u8 = 0x0A;
+const CRTC_CURSOR_END: u8 = 0x0B;
+const CRTC_CURSOR_LOCATION_HIGH: u8 = 0x0E;
+const CRTC_CURSOR_LOCATION_LOW: u8 = 0x0F;
+/// Bit 5 of the cursor start register turns the cursor off.
+const CURSOR_DISABLE: u8 = 0x20;
+
+// Attribute controller registers selecting blink or bright backgrounds.
+const INPUT_STATUS_1_PORT: u16 = 0x3DA;
+const ATTRIBUTE_ADDRESS_PORT: u16 = 0x3C0;
+const ATTRIBUTE_DATA_READ_PORT: u16 = 0x3C1;
+const ATTRIBUTE_MODE_CONTROL: u8 = 0x10;
+/// Keeps the palette enabled while the attribute controller index is written.
+const ATTRIBUTE_PALETTE_SOURCE: u8 = 0x20;
+/// Bit 3 of the attribute mode control register makes bit 7 of the attribute blink.
+const ATTRIBUTE_BLINK_ENABLE: u8 = 0x08;
+
+impl VGAColorCode {
+    /// The colour for the low four bits of `value`.
+    pub const fn from_u8(value: u8) -> VGAColorCode {
+        match value & 0x0F {
+            0 => VGAColorCode::Black,
+            1 => VGAColorCode::Blue,
+            2 => VGAColorCode::Green,
+            3 => VGAColorCode::Cyan,
+            4 => VGAColorCode::Red,
+            5 => VGAColorCode::Magenta,
+            6 => VGAColorCode::Brown,
+            7 => VGAColorCode::LightGray,
+            8 => VGAColorCode::DarkGray,
+            9 => VGAColorCode::LightBlue,
+            10 => VGAColorCode::LightGreen,
+            11 => VGAColorCode::LightCyan,
+            12 => VGAColorCode::LightRed,
+            13 => VGAColorCode::Pink,
+            14 => VGAColorCode::Yellow,
+            _ => VGAColorCode::White,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub struct VGAChar {
     pub char: u8,
-    pub color_code: VGAColorCode,
+    pub color_code: ColorData,
 }
 
+pub const BLANK: VGAChar = VGAChar {
+    char: b' ',
+    color_code: ColorData::new(VGAColorCode::Black, VGAColorCode::Black, false),
+};
+
 #[repr(transparent)]
 struct Buffer {
     /**  tldr: Volatile prevents the compiler from optimizing away reads and writes to memory that has side-effects.
@@ -53,14 +105,38 @@ pub struct VGATextMode {
     buffer: &'static mut Buffer,
 }
 
+/** A full attribute byte: foreground in bits 0-3, background in bits 4-6 and bit 7 for blink.
+ * With blinking turned off (see `VGATextMode::set_blink_enabled`) bit 7 instead selects the bright
+ * half of the palette for the background, so all 16 colours can be used as backgrounds.
+ */
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
-struct ColorData(u8);
+pub struct ColorData(u8);
 
 impl ColorData {
-    fn new(foreground: VGAColorCode, background: VGAColorCode, blink: bool) -> ColorData {
+    pub const fn new(foreground: VGAColorCode, background: VGAColorCode, blink: bool) -> ColorData {
         ColorData(((blink as u8) << 7) | ((background as u8) << 4) | (foreground as u8))
     }
+
+    pub const fn foreground(self) -> VGAColorCode {
+        VGAColorCode::from_u8(self.0)
+    }
+
+    /// The background including bit 7, i.e. as displayed when blinking is turned off.
+    pub const fn background(self) -> VGAColorCode {
+        VGAColorCode::from_u8(self.0 >> 4)
+    }
+
+    pub const fn blink(self) -> bool {
+        self.0 & 0x80 != 0
+    }
+}
+
+impl From<VGAColorCode> for ColorData {
+    /// `foreground` on a black background.
+    fn from(foreground: VGAColorCode) -> ColorData {
+        ColorData::new(foreground, VGAColorCode::Black, false)
+    }
 }
 
 impl VGATextMode {
@@ -70,13 +146,17 @@ impl VGATextMode {
         }
     }
 
-    pub fn write(&mut self, byte: u8, color: VGAColorCode, col: usize, row: usize) {
+    pub fn write(&mut self, byte: u8, color: impl Into<ColorData>, col: usize, row: usize) {
         self.buffer.chars[row][col].write(VGAChar {
             char: byte,
-            color_code: color,
+            color_code: color.into(),
         });
     }
 
+    pub fn read(&self, col: usize, row: usize) -> VGAChar {
+        self.buffer.chars[row][col].read()
+    }
+
     pub fn clear_row(&mut self, row: usize) {
         for col in 0..BUFFER_WIDTH {
             self.write(b' ', VGAColorCode::Black, col, row);
@@ -139,16 +219,35 @@ impl VGATextMode {
         self.clear_row_range(0, x);
     }
 
+    /// Shifts rows `top..bottom` up by x, dropping the top x rows of that range. The bottom x rows of the range are left blank.
+    pub fn shift_region_up_by_x(&mut self, top: usize, bottom: usize, x: usize) {
+        let x = x.min(bottom - top);
+        for row in top + x..bottom {
+            for col in 0..BUFFER_WIDTH {
+                self.buffer.chars[row - x][col].write(self.buffer.chars[row][col].read());
+            }
+        }
+        self.clear_row_range(bottom - x, bottom);
+    }
+
+    /// Shifts rows `top..bottom` down by x, dropping the bottom x rows of that range. The top x rows of the range are left blank.
+    pub fn shift_region_down_by_x(&mut self, top: usize, bottom: usize, x: usize) {
+        let x = x.min(bottom - top);
+        for row in (top + x..bottom).rev() {
+            for col in 0..BUFFER_WIDTH {
+                self.buffer.chars[row][col].write(self.buffer.chars[row - x][col].read());
+            }
+        }
+        self.clear_row_range(top, top + x);
+    }
+
     ///////////////////////////////////////////////////////
     /// SHIFTING WITH WRAP
     ///////////////////////////////////////////////////////
 
     /// Shifts the screen to the left by x columns, wrapping the leftmost x columns to the right.
     pub fn shift_left_by_x_with_wrap(&mut self, x: usize) {
-        let mut tmp_row = [VGAChar {
-            char: b' ',
-            color_code: VGAColorCode::Black,
-        }; BUFFER_WIDTH];
+        let mut tmp_row = [BLANK; BUFFER_WIDTH];
 
         for row in 0..BUFFER_HEIGHT {
             // Read the volatile data into tmp_row
@@ -170,10 +269,7 @@ impl VGATextMode {
 
     /// Shifts the screen to the right by x columns, wrapping the rightmost x columns to the left.
     pub fn shift_right_by_x_with_wrap(&mut self, x: usize) {
-        let mut tmp_row = [VGAChar {
-            char: b' ',
-            color_code: VGAColorCode::Black,
-        }; BUFFER_WIDTH];
+        let mut tmp_row = [BLANK; BUFFER_WIDTH];
 
         for row in 0..BUFFER_HEIGHT {
             for col in BUFFER_WIDTH - x..BUFFER_WIDTH {
@@ -190,10 +286,7 @@ impl VGATextMode {
 
     /// Shifts all rows up by x, wrapping the top x rows to the bottom.
     pub fn shift_up_by_x_with_wrap(&mut self, x: usize) {
-        let mut tmp_rows = [[VGAChar {
-            char: b' ',
-            color_code: VGAColorCode::Black,
-        }; BUFFER_WIDTH]; BUFFER_HEIGHT];
+        let mut tmp_rows = [[BLANK; BUFFER_WIDTH]; BUFFER_HEIGHT];
 
         for row in 0..x {
             for col in 0..BUFFER_WIDTH {
@@ -216,10 +309,7 @@ impl VGATextMode {
 
     /// Shifts all rows down by x, wrapping the bottom x rows to the top.
     pub fn shift_down_by_x_with_wrap(&mut self, x: usize) {
-        let mut tmp_rows = [[VGAChar {
-            char: b' ',
-            color_code: VGAColorCode::Black,
-        }; BUFFER_WIDTH]; BUFFER_HEIGHT];
+        let mut tmp_rows = [[BLANK; BUFFER_WIDTH]; BUFFER_HEIGHT];
 
         for row in BUFFER_HEIGHT - x..BUFFER_HEIGHT {
             for col in 0..BUFFER_WIDTH {
@@ -240,6 +330,66 @@ impl VGATextMode {
         }
     }
 
+    /// Chooses whether bit 7 of an attribute blinks the character (the BIOS default) or brightens its background.
+    pub fn set_blink_enabled(&mut self, enabled: bool) {
+        let mut status: Port<u8> = Port::new(INPUT_STATUS_1_PORT);
+        let mut address: Port<u8> = Port::new(ATTRIBUTE_ADDRESS_PORT);
+        let mut data: Port<u8> = Port::new(ATTRIBUTE_DATA_READ_PORT);
+        unsafe {
+            // Reading the input status register resets the controller's address/data flip-flop.
+            status.read();
+            address.write(ATTRIBUTE_MODE_CONTROL | ATTRIBUTE_PALETTE_SOURCE);
+            let mode = data.read();
+            let mode = if enabled {
+                mode | ATTRIBUTE_BLINK_ENABLE
+            } else {
+                mode & !ATTRIBUTE_BLINK_ENABLE
+            };
+            // The flip-flop is now in the data state, so this write goes to the register.
+            address.write(mode);
+        }
+    }
+
+    ///////////////////////////////////////////////////////
+    // HARDWARE CURSOR
+    ///////////////////////////////////////////////////////
+
+    /// Shows the hardware cursor spanning scanlines `start..=end` of the character cell (0-15 for the 8x16 font).
+    pub fn enable_cursor(&mut self, start: u8, end: u8) {
+        let start_register = Self::read_crtc(CRTC_CURSOR_START);
+        let end_register = Self::read_crtc(CRTC_CURSOR_END);
+        Self::write_crtc(CRTC_CURSOR_START, (start_register & 0xC0) | (start & 0x1F));
+        Self::write_crtc(CRTC_CURSOR_END, (end_register & 0xE0) | (end & 0x1F));
+    }
+
+    pub fn disable_cursor(&mut self) {
+        Self::write_crtc(CRTC_CURSOR_START, CURSOR_DISABLE);
+    }
+
+    pub fn set_cursor_position(&mut self, col: usize, row: usize) {
+        let position = (row * BUFFER_WIDTH + col) as u16;
+        Self::write_crtc(CRTC_CURSOR_LOCATION_LOW, (position & 0xFF) as u8);
+        Self::write_crtc(CRTC_CURSOR_LOCATION_HIGH, (position >> 8) as u8);
+    }
+
+    fn read_crtc(index: u8) -> u8 {
+        let mut address: Port<u8> = Port::new(CRTC_ADDRESS_PORT);
+        let mut data: Port<u8> = Port::new(CRTC_DATA_PORT);
+        unsafe {
+            address.write(index);
+            data.read()
+        }
+    }
+
+    fn write_crtc(index: u8, value: u8) {
+        let mut address: Port<u8> = Port::new(CRTC_ADDRESS_PORT);
+        let mut data: Port<u8> = Port::new(CRTC_DATA_PORT);
+        unsafe {
+            address.write(index);
+            data.write(value);
+        }
+    }
+
     ///////////////////////////////////////////////////////
     /// UTILS
     ///////////////////////////////////////////////////////
@@ -255,31 +405,22 @@ impl VGATextMode {
     fn clear_column_range(&mut self, start_col: usize, end_col: usize) {
         for row in 0..BUFFER_HEIGHT {
             for col in start_col..end_col {
-                self.buffer.chars[row][col].write(VGAChar {
-                    char: b' ',
-                    color_code: VGAColorCode::Black,
-                });
+                self.buffer.chars[row][col].write(BLANK);
             }
         }
     }
 
     /** Clears a section of a single row. */
-    fn clear_subrow(&mut self, start_col: usize, end_col: usize, row: usize) {
+    pub fn clear_subrow(&mut self, start_col: usize, end_col: usize, row: usize) {
         for col in start_col..end_col {
-            self.buffer.chars[row][col].write(VGAChar {
-                char: b' ',
-                color_code: VGAColorCode::Black,
-            });
+            self.buffer.chars[row][col].write(BLANK);
         }
     }
 
     /**  Clears a section of a single column. */
     fn clear_subcolumn(&mut self, start_row: usize, end_row: usize, col: usize) {
         for row in start_row..end_row {
-            self.buffer.chars[row][col].write(VGAChar {
-                char: b' ',
-                color_code: VGAColorCode::Black,
-            });
+            self.buffer.chars[row][col].write(BLANK);
         }
     }
 
@@ -287,10 +428,7 @@ impl VGATextMode {
     fn clear_rect(&mut self, start_row: usize, end_row: usize, start_col: usize, end_col: usize) {
         for row in start_row..end_row {
             for col in start_col..end_col {
-                self.buffer.chars[row][col].write(VGAChar {
-                    char: b' ',
-                    color_code: VGAColorCode::Black,
-                });
+                self.buffer.chars[row][col].write(BLANK);
             }
         }
     }
@@ -301,3 +439,15 @@ impl Default for VGATextMode {
         Self::new()
     }
 }
+
+impl TextCanvas for VGATextMode {
+    fn size(&self) -> (usize, usize) {
+        (BUFFER_WIDTH, BUFFER_HEIGHT)
+    }
+
+    fn put(&mut self, byte: u8, color: ColorData, col: usize, row: usize) {
+        if col < BUFFER_WIDTH && row < BUFFER_HEIGHT {
+            self.write(byte, color, col, row);
+        }
+    }
+}
@@ -1,9 +1,35 @@
-use crate::vga_text_mode::{VGAColorCode, VGATextMode, BUFFER_HEIGHT, BUFFER_WIDTH, VGA_TEXT_MODE};
+use crate::tui::TextCanvas;
+use crate::vga_text_mode::{ColorData, VGAColorCode, BUFFER_HEIGHT, BUFFER_WIDTH, VGA_TEXT_MODE};
 
 pub const BLOCK: u8 = 0xDB;
 
 pub fn draw_line(x1: usize, y1: usize, x2: usize, y2: usize, byte: u8, color: VGAColorCode) {
-    let mut vga = VGA_TEXT_MODE.lock();
+    line(&mut *VGA_TEXT_MODE.lock(), x1, y1, x2, y2, byte, color);
+}
+
+pub fn draw_circle(cx: usize, cy: usize, r: usize, byte: u8, color: VGAColorCode) {
+    circle(&mut *VGA_TEXT_MODE.lock(), cx, cy, r, byte, color);
+}
+
+pub fn draw_point(x: usize, y: usize, color: VGAColorCode) {
+    if x >= BUFFER_WIDTH || y >= BUFFER_HEIGHT {
+        return;
+    }
+
+    point(&mut *VGA_TEXT_MODE.lock(), x, y, color);
+}
+
+/// `draw_line` on any text canvas, e.g. a console's cell grid.
+pub fn line(
+    canvas: &mut dyn TextCanvas,
+    x1: usize,
+    y1: usize,
+    x2: usize,
+    y2: usize,
+    byte: u8,
+    color: impl Into<ColorData>,
+) {
+    let color = color.into();
     let mut x = x1 as isize;
     let mut y = y1 as isize;
 
@@ -27,7 +53,7 @@ pub fn draw_line(x1: usize, y1: usize, x2: usize, y2: usize, byte: u8, color: VG
     if dy <= dx {
         let mut mut_y = dy1 - dx; // dy*2 - dx
         for _ in x1..=x2 {
-            vga.write(byte, color, x as usize, y as usize);
+            canvas.put(byte, color, x as usize, y as usize);
             if mut_y >= 0 {
                 y += if_cond_y;
                 mut_y -= dx1;
@@ -38,7 +64,7 @@ pub fn draw_line(x1: usize, y1: usize, x2: usize, y2: usize, byte: u8, color: VG
     } else {
         let mut mut_x = dx1 - dy; // dx*2 - dy
         for _ in y1..=y2 {
-            vga.write(byte, color, x as usize, y as usize);
+            canvas.put(byte, color, x as usize, y as usize);
             if mut_x >= 0 {
                 x += if_cond_x;
                 mut_x -= dy1;
@@ -49,13 +75,22 @@ pub fn draw_line(x1: usize, y1: usize, x2: usize, y2: usize, byte: u8, color: VG
     }
 }
 
-pub fn draw_circle(cx: usize, cy: usize, r: usize, byte: u8, color: VGAColorCode) {
-    let mut vga = VGA_TEXT_MODE.lock();
-
-    // Nested function to safely write to the VGA buffer
-    let safe_write = |vga: &mut VGATextMode, x: isize, y: isize| {
-        if x >= 0 && y >= 0 && x < BUFFER_WIDTH as isize && y < BUFFER_HEIGHT as isize {
-            vga.write(byte, color, x as usize, y as usize);
+/// `draw_circle` on any text canvas. Parts off the canvas are clipped.
+pub fn circle(
+    canvas: &mut dyn TextCanvas,
+    cx: usize,
+    cy: usize,
+    r: usize,
+    byte: u8,
+    color: impl Into<ColorData>,
+) {
+    let color = color.into();
+    let (width, height) = canvas.size();
+
+    // Nested function to safely write to the canvas
+    let safe_write = |canvas: &mut dyn TextCanvas, x: isize, y: isize| {
+        if x >= 0 && y >= 0 && x < width as isize && y < height as isize {
+            canvas.put(byte, color, x as usize, y as usize);
         }
     };
 
@@ -63,15 +98,15 @@ pub fn draw_circle(cx: usize, cy: usize, r: usize, byte: u8, color: VGAColorCode
     let mut y = 0isize;
     let mut p = 1 - r as isize;
 
-    safe_write(&mut vga, cx as isize + x, cy as isize - y);
-    safe_write(&mut vga, cx as isize - x, cy as isize + y);
-    safe_write(&mut vga, cx as isize + x, cy as isize + y);
-    safe_write(&mut vga, cx as isize - x, cy as isize - y);
+    safe_write(canvas, cx as isize + x, cy as isize - y);
+    safe_write(canvas, cx as isize - x, cy as isize + y);
+    safe_write(canvas, cx as isize + x, cy as isize + y);
+    safe_write(canvas, cx as isize - x, cy as isize - y);
 
-    safe_write(&mut vga, cx as isize + y, cy as isize - x);
-    safe_write(&mut vga, cx as isize - y, cy as isize + x);
-    safe_write(&mut vga, cx as isize + y, cy as isize + x);
-    safe_write(&mut vga, cx as isize - y, cy as isize - x);
+    safe_write(canvas, cx as isize + y, cy as isize - x);
+    safe_write(canvas, cx as isize - y, cy as isize + x);
+    safe_write(canvas, cx as isize + y, cy as isize + x);
+    safe_write(canvas, cx as isize - y, cy as isize - x);
 
     while x > y {
         y += 1;
@@ -87,25 +122,21 @@ pub fn draw_circle(cx: usize, cy: usize, r: usize, byte: u8, color: VGAColorCode
             break;
         }
 
-        safe_write(&mut vga, cx as isize + x, cy as isize - y);
-        safe_write(&mut vga, cx as isize - x, cy as isize + y);
-        safe_write(&mut vga, cx as isize + x, cy as isize + y);
-        safe_write(&mut vga, cx as isize - x, cy as isize - y);
+        safe_write(canvas, cx as isize + x, cy as isize - y);
+        safe_write(canvas, cx as isize - x, cy as isize + y);
+        safe_write(canvas, cx as isize + x, cy as isize + y);
+        safe_write(canvas, cx as isize - x, cy as isize - y);
 
         if x != y {
-            safe_write(&mut vga, cx as isize + y, cy as isize - x);
-            safe_write(&mut vga, cx as isize - y, cy as isize + x);
-            safe_write(&mut vga, cx as isize + y, cy as isize + x);
-            safe_write(&mut vga, cx as isize - y, cy as isize - x);
+            safe_write(canvas, cx as isize + y, cy as isize - x);
+            safe_write(canvas, cx as isize - y, cy as isize + x);
+            safe_write(canvas, cx as isize + y, cy as isize + x);
+            safe_write(canvas, cx as isize - y, cy as isize - x);
         }
     }
 }
 
-pub fn draw_point(x: usize, y: usize, color: VGAColorCode) {
-    if x >= BUFFER_WIDTH || y >= BUFFER_HEIGHT {
-        return;
-    }
-
-    let mut vga = VGA_TEXT_MODE.lock();
-    vga.write(BLOCK, color, x, y);
+/// A solid block at `x`, `y` on any text canvas.
+pub fn point(canvas: &mut dyn TextCanvas, x: usize, y: usize, color: impl Into<ColorData>) {
+    canvas.put(BLOCK, color.into(), x, y);
 }
@@ -1,133 +1,393 @@
 use core::fmt::Write;
 
-use crate::vga_text_mode::{VGAColorCode, BUFFER_HEIGHT, BUFFER_WIDTH, VGA_TEXT_MODE};
-use core::sync::atomic::{AtomicBool, Ordering};
-use lazy_static::lazy_static;
+use crate::ansi::{AnsiParser, AnsiScreen, TabStops, DEFAULT_TAB_WIDTH};
+use crate::console::VIRTUAL_CONSOLES;
+use crate::scrollback::Scrollback;
+use crate::tui::TextCanvas;
+use crate::vga_text_mode::{
+    ColorData, VGAChar, VGAColorCode, BLANK, BUFFER_HEIGHT, BUFFER_WIDTH, VGA_TEXT_MODE,
+};
 use spin::Mutex;
 
-lazy_static! {
-    pub static ref VGA_TEXT_MODE_TERMINAL: Mutex<VGATextModeTerminal> =
-        Mutex::new(VGATextModeTerminal::new());
-}
+/// One terminal per virtual console. Only the active one draws to the VGA text buffer.
+pub static VGA_TEXT_MODE_TERMINALS: [Mutex<VGATextModeTerminal>; VIRTUAL_CONSOLES] = {
+    let mut terminals = [const { Mutex::new(VGATextModeTerminal::new(false)) }; VIRTUAL_CONSOLES];
+    // The kernel console is the one on screen at boot.
+    terminals[0] = Mutex::new(VGATextModeTerminal::new(true));
+    terminals
+};
+
+/// Number of lines kept after they scroll off the top of the screen.
+pub const SCROLLBACK_LINES: usize = 200;
 
-pub static CURSOR_TOGGLE_FLAG: AtomicBool = AtomicBool::new(false);
+pub const DEFAULT_FOREGROUND: VGAColorCode = VGAColorCode::White;
+pub const DEFAULT_BACKGROUND: VGAColorCode = VGAColorCode::Black;
+
+/// Default hardware cursor: an underline on the bottom two scanlines of the cell.
+pub const DEFAULT_CURSOR_SHAPE: (u8, u8) = (14, 15);
 
 pub struct VGATextModeTerminal {
     pub col: usize,
     pub row: usize,
+    foreground: VGAColorCode,
+    background: VGAColorCode,
+    blink: bool,
+    scroll_top: usize,
+    scroll_bottom: usize,
+    ansi: AnsiParser,
+    tabs: TabStops,
+    /// Whether a word running past the right edge moves to the next line as a whole.
+    word_wrap: bool,
+    /// The terminal's own copy of its screen, so it keeps receiving output while another one is shown.
+    cells: [[VGAChar; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    scrollback: Scrollback<VGAChar, BUFFER_WIDTH, SCROLLBACK_LINES>,
+    /// How many lines the view is scrolled back into history; 0 shows the live screen.
+    view_offset: usize,
+    /// First and last scanline of the hardware cursor.
+    cursor_shape: (u8, u8),
+    /// Whether this terminal owns the VGA text buffer.
+    active: bool,
 }
 
 impl VGATextModeTerminal {
-    pub fn new() -> VGATextModeTerminal {
-        VGATextModeTerminal { col: 0, row: 0 }
+    pub const fn new(active: bool) -> VGATextModeTerminal {
+        VGATextModeTerminal {
+            col: 0,
+            row: 0,
+            foreground: DEFAULT_FOREGROUND,
+            background: DEFAULT_BACKGROUND,
+            blink: false,
+            scroll_top: 0,
+            scroll_bottom: BUFFER_HEIGHT - 1,
+            ansi: AnsiParser::new(),
+            tabs: TabStops::new(DEFAULT_TAB_WIDTH),
+            word_wrap: false,
+            cells: [[BLANK; BUFFER_WIDTH]; BUFFER_HEIGHT],
+            scrollback: Scrollback::new(BLANK),
+            view_offset: 0,
+            cursor_shape: DEFAULT_CURSOR_SHAPE,
+            active,
+        }
+    }
+
+    /// Puts this terminal on screen, redrawing the VGA text buffer from its saved cells,
+    /// or takes it off screen so it stops touching the hardware.
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+        if active {
+            self.redraw();
+            self.update_cursor();
+        }
+    }
+
+    /// True when the live screen is what the VGA text buffer shows.
+    fn is_shown(&self) -> bool {
+        self.active && self.view_offset == 0
+    }
+
+    /// Sets the colours used for everything written from now on.
+    pub fn set_color(&mut self, foreground: VGAColorCode, background: VGAColorCode) {
+        self.foreground = foreground;
+        self.background = background;
+    }
+
+    /// Sets attribute bit 7 for everything written from now on. Whether that blinks or gives a bright
+    /// background depends on `VGATextMode::set_blink_enabled`.
+    pub fn set_blink(&mut self, blink: bool) {
+        self.blink = blink;
     }
 
-    pub fn toggle_cursor(&mut self) {
-        let cursor_visible = CURSOR_TOGGLE_FLAG.load(Ordering::SeqCst);
+    pub fn attribute(&self) -> ColorData {
+        ColorData::new(self.foreground, self.background, self.blink)
+    }
+
+    /// Puts a tab stop every `width` columns, replacing any set by escape sequences.
+    pub fn set_tab_width(&mut self, width: usize) {
+        self.tabs.set_width(width);
+    }
 
-        // If cursor is to be shown, write an underscore or block at the current position
-        let character = if cursor_visible { b'_' } else { b' ' };
+    /// Enables or disables word-aware wrapping at the right edge.
+    pub fn set_word_wrap(&mut self, word_wrap: bool) {
+        self.word_wrap = word_wrap;
+    }
 
-        VGA_TEXT_MODE
-            .lock()
-            .write(character, VGAColorCode::White, self.col, self.row);
+    pub fn set_cursor_shape(&mut self, start: u8, end: u8) {
+        self.cursor_shape = (start, end);
+        self.update_cursor();
+    }
+
+    /// Moves the hardware cursor to the terminal cursor, hiding it while the view is scrolled back.
+    /// Does nothing while another terminal is on screen.
+    pub fn update_cursor(&mut self) {
+        if !self.active {
+            return;
+        }
+
+        let mut vga = VGA_TEXT_MODE.lock();
+        if self.view_offset > 0 {
+            vga.disable_cursor();
+            return;
+        }
+
+        vga.enable_cursor(self.cursor_shape.0, self.cursor_shape.1);
+        vga.set_cursor_position(self.col.min(BUFFER_WIDTH - 1), self.row);
+    }
+
+    /// Stores a cell and mirrors it to the VGA text buffer if the live screen is showing.
+    fn put(&mut self, byte: u8, attribute: ColorData, col: usize, row: usize) {
+        self.cells[row][col] = VGAChar {
+            char: byte,
+            color_code: attribute,
+        };
+        if self.is_shown() {
+            VGA_TEXT_MODE.lock().write(byte, attribute, col, row);
+        }
     }
 
-    pub fn write_byte(&mut self, byte: u8, color: VGAColorCode) {
+    pub fn write_byte(&mut self, byte: u8) {
         if self.col >= BUFFER_WIDTH {
-            self.new_line();
+            self.wrap_line();
         }
 
-        VGA_TEXT_MODE.lock().write(byte, color, self.col, self.row);
+        self.put(byte, self.attribute(), self.col, self.row);
 
         self.col += 1;
     }
 
-    pub fn write_str(&mut self, s: &str, color: VGAColorCode) {
-        for byte in s.bytes() {
-            match byte {
-                // ASCII newline
-                0x0a | 0x0d => {
-                    self.new_line();
-                }
-                // backspace
-                0x08 => {
-                    VGA_TEXT_MODE.lock().write(b' ', color, self.col, self.row);
-                    if self.col > 0 {
-                        self.col -= 1;
-                    }
-                }
-                // Regular byte
-                _ => {
-                    self.write_byte(byte, color);
-                }
-            }
+    /// Writes `s`, interpreting ANSI escape sequences along the way. Characters outside ASCII are
+    /// shown as their code page 437 glyph.
+    pub fn write_str(&mut self, s: &str) {
+        self.scroll_to_bottom();
+
+        let mut ansi = self.ansi;
+        for c in s.chars() {
+            ansi.feed_char(self, c);
         }
+        self.ansi = ansi;
+
+        self.update_cursor();
     }
 
+    /// Shifts the scroll region up by one row and blanks its bottom row.
     pub fn shift_rows_up(&mut self) {
-        VGA_TEXT_MODE.lock().shift_up_by_x(1);
+        AnsiScreen::scroll_up(self, 1);
     }
 
     pub fn clear_row(&mut self, row: usize) {
-        VGA_TEXT_MODE.lock().clear_row(row);
+        self.cells[row] = [BLANK; BUFFER_WIDTH];
+        if self.is_shown() {
+            VGA_TEXT_MODE.lock().clear_row(row);
+        }
     }
 
     pub fn new_line(&mut self) {
-        if self.row < BUFFER_HEIGHT - 1 {
-            self.row += 1;
-            self.col = 0;
-        } else {
+        self.col = 0;
+        if self.row == self.scroll_bottom {
+            if self.scroll_top == 0 {
+                self.scrollback.push(&self.cells[0]);
+            }
             self.shift_rows_up();
-            self.clear_row(BUFFER_HEIGHT - 1);
-            self.col = 0;
+        } else if self.row < BUFFER_HEIGHT - 1 {
+            self.row += 1;
+        }
+    }
+
+    /// Continues on the next line once the cursor has run off the right edge. With word wrap on,
+    /// the partial word at the end of the line is carried over instead of being split.
+    fn wrap_line(&mut self) {
+        let mut word = [BLANK; BUFFER_WIDTH];
+        let mut len = 0;
+        if self.word_wrap {
+            let line = &self.cells[self.row];
+            // A line without spaces is one long word, which has to be split anyway.
+            if let Some(space) = line.iter().rposition(|cell| cell.char == b' ') {
+                len = BUFFER_WIDTH - space - 1;
+                word[..len].copy_from_slice(&line[space + 1..]);
+                AnsiScreen::erase(self, self.row, space + 1, BUFFER_WIDTH);
+            }
+        }
+
+        self.new_line();
+        for (col, cell) in word[..len].iter().enumerate() {
+            self.put(cell.char, cell.color_code, col, self.row);
+        }
+        self.col = len;
+    }
+
+    /// Scrolls the view `lines` further back into the scrollback history.
+    pub fn scroll_view_up(&mut self, lines: usize) {
+        self.set_view_offset((self.view_offset + lines).min(self.scrollback.len()));
+    }
+
+    /// Scrolls the view `lines` back towards the live screen.
+    pub fn scroll_view_down(&mut self, lines: usize) {
+        self.set_view_offset(self.view_offset.saturating_sub(lines));
+    }
+
+    /// Returns the view to the live screen.
+    pub fn scroll_to_bottom(&mut self) {
+        self.set_view_offset(0);
+    }
+
+    fn set_view_offset(&mut self, offset: usize) {
+        if offset == self.view_offset {
+            return;
+        }
+
+        self.view_offset = offset;
+        self.redraw();
+        self.update_cursor();
+    }
+
+    /// Copies the current view, scrollback included, into the VGA text buffer.
+    pub fn redraw(&mut self) {
+        if !self.active {
+            return;
+        }
+
+        let mut vga = VGA_TEXT_MODE.lock();
+        let history = self.scrollback.len();
+        for row in 0..BUFFER_HEIGHT {
+            // Index into the scrollback followed by the live screen.
+            let line = history + row - self.view_offset;
+            let cells = match self.scrollback.get(line) {
+                Some(cells) => cells,
+                None => &self.cells[line - history],
+            };
+            for (col, cell) in cells.iter().enumerate() {
+                vga.write(cell.char, cell.color_code, col, row);
+            }
         }
     }
 
     pub fn clear_screen(&mut self) {
-        VGA_TEXT_MODE.lock().clear_screen();
+        self.scroll_to_bottom();
+        self.cells = [[BLANK; BUFFER_WIDTH]; BUFFER_HEIGHT];
+        if self.is_shown() {
+            VGA_TEXT_MODE.lock().clear_screen();
+        }
 
         self.col = 0;
         self.row = 0;
+        self.update_cursor();
     }
 }
 
 impl Default for VGATextModeTerminal {
     fn default() -> Self {
-        Self::new()
+        Self::new(false)
     }
 }
 
-impl Write for VGATextModeTerminal {
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        self.write_str(s, VGAColorCode::White);
-        Ok(())
+impl AnsiScreen for VGATextModeTerminal {
+    fn size(&self) -> (usize, usize) {
+        (BUFFER_WIDTH, BUFFER_HEIGHT)
+    }
+
+    fn cursor(&self) -> (usize, usize) {
+        (self.col.min(BUFFER_WIDTH - 1), self.row)
+    }
+
+    fn set_cursor(&mut self, col: usize, row: usize) {
+        self.col = col;
+        self.row = row;
+    }
+
+    fn default_colors(&self) -> (VGAColorCode, VGAColorCode) {
+        (DEFAULT_FOREGROUND, DEFAULT_BACKGROUND)
+    }
+
+    fn set_colors(&mut self, foreground: VGAColorCode, background: VGAColorCode) {
+        self.set_color(foreground, background);
+    }
+
+    fn set_blink(&mut self, blink: bool) {
+        VGATextModeTerminal::set_blink(self, blink);
+    }
+
+    fn erase(&mut self, row: usize, start_col: usize, end_col: usize) {
+        let attribute = self.attribute();
+        for col in start_col..end_col.min(BUFFER_WIDTH) {
+            self.put(b' ', attribute, col, row);
+        }
+    }
+
+    fn set_scroll_region(&mut self, top: usize, bottom: usize) {
+        self.scroll_top = top;
+        self.scroll_bottom = bottom;
+    }
+
+    fn scroll_up(&mut self, lines: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom + 1);
+        let lines = lines.min(bottom - top);
+        self.cells.copy_within(top + lines..bottom, top);
+        self.cells[bottom - lines..bottom].fill([BLANK; BUFFER_WIDTH]);
+        if self.is_shown() {
+            VGA_TEXT_MODE
+                .lock()
+                .shift_region_up_by_x(top, bottom, lines);
+        }
+    }
+
+    fn scroll_down(&mut self, lines: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom + 1);
+        let lines = lines.min(bottom - top);
+        self.cells.copy_within(top..bottom - lines, top + lines);
+        self.cells[top..top + lines].fill([BLANK; BUFFER_WIDTH]);
+        if self.is_shown() {
+            VGA_TEXT_MODE
+                .lock()
+                .shift_region_down_by_x(top, bottom, lines);
+        }
+    }
+
+    fn tab_stops(&mut self) -> &mut TabStops {
+        &mut self.tabs
+    }
+
+    fn print(&mut self, byte: u8) {
+        self.write_byte(byte);
+    }
+
+    fn control(&mut self, byte: u8) {
+        match byte {
+            // ASCII newline
+            0x0a => {
+                self.new_line();
+            }
+            // carriage return
+            0x0d => {
+                self.col = 0;
+            }
+            // backspace
+            0x08 => {
+                if self.col < BUFFER_WIDTH {
+                    self.put(b' ', self.attribute(), self.col, self.row);
+                }
+                if self.col > 0 {
+                    self.col -= 1;
+                }
+            }
+            _ => {}
+        }
     }
 }
 
-#[macro_export]
-macro_rules! println {
-    ($($arg:tt)*) => ({
-        use core::fmt::Write;
-        use x86_64::instructions::interrupts::without_interrupts;
-        use $crate::vga_text_mode_terminal::VGA_TEXT_MODE_TERMINAL;
-        without_interrupts(|| {
-            let mut terminal = VGA_TEXT_MODE_TERMINAL.lock();
-            let _ = write!(&mut *terminal, $($arg)*);
-            let _ = write!(&mut *terminal, "\n");
-        });
-    });
+impl TextCanvas for VGATextModeTerminal {
+    fn size(&self) -> (usize, usize) {
+        (BUFFER_WIDTH, BUFFER_HEIGHT)
+    }
+
+    fn put(&mut self, byte: u8, color: ColorData, col: usize, row: usize) {
+        if col < BUFFER_WIDTH && row < BUFFER_HEIGHT {
+            VGATextModeTerminal::put(self, byte, color, col, row);
+        }
+    }
 }
 
-#[macro_export]
-macro_rules! print {
-    ($($arg:tt)*) => ({
-        use core::fmt::Write;
-        use x86_64::instructions::interrupts::without_interrupts;
-        use $crate::vga_text_mode_terminal::VGA_TEXT_MODE_TERMINAL;
-        without_interrupts(|| {
-            let mut terminal = VGA_TEXT_MODE_TERMINAL.lock();
-            let _ = write!(&mut *terminal, $($arg)*);
-        });
-    });
+impl Write for VGATextModeTerminal {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_str(s);
+        Ok(())
+    }
 }
@@ -1,20 +1,60 @@
-use std::process::{Command, ExitCode};
+use std::io::{self, Read};
+use std::process::{Command, ExitCode, Stdio};
+
+mod screenshot;
+
+use screenshot::Extractor;
 
 fn main() -> ExitCode {
     let bios_image = env!("BIOS_IMAGE");
 
-    let status = Command::new("qemu-system-x86_64")
+    // Serial output comes through here so screenshots the kernel sends can be saved to files.
+    let child = Command::new("qemu-system-x86_64")
         .args([
             "-drive",
             &format!("format=raw,file={bios_image}"),
             "-serial",
             "stdio",
+            // The demo renders on every CPU.
+            "-smp",
+            "4",
             "-device",
             "isa-debug-exit,iobase=0xf4,iosize=0x04",
         ])
-        .status();
+        .stdout(Stdio::piped())
+        .spawn();
+
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            eprintln!("failed to run qemu-system-x86_64: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Some(mut serial) = child.stdout.take() {
+        let dir = std::env::current_dir().unwrap_or_default();
+        let mut extractor = Extractor::new(io::stdout(), dir);
+        let mut buffer = [0; 4096];
+        loop {
+            match serial.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(len) => {
+                    if let Err(err) = extractor.feed(&buffer[..len]) {
+                        eprintln!("failed to write serial output: {err}");
+                        break;
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    eprintln!("failed to read serial output: {err}");
+                    break;
+                }
+            }
+        }
+    }
 
-    match status {
+    match child.wait() {
         Ok(status) if status.success() => ExitCode::SUCCESS,
         Ok(status) => ExitCode::from(status.code().unwrap_or(1) as u8),
         Err(err) => {
@@ -0,0 +1,324 @@
+//! Picks screenshots out of the kernel's serial output. The kernel sends each one as a
+//! base64-encoded PPM between `@@SCREENSHOT BEGIN <width> <height> <bytes>` and
+//! `@@SCREENSHOT END <crc32>` lines; everything else is passed through untouched.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+const BEGIN_MARKER: &str = "@@SCREENSHOT BEGIN";
+const END_MARKER: &str = "@@SCREENSHOT END";
+
+/// A screenshot being received.
+struct Frame {
+    width: usize,
+    height: usize,
+    size: usize,
+    base64: Vec<u8>,
+}
+
+pub struct Extractor<W: Write> {
+    out: W,
+    /// Where the screenshots are written, as `screenshot-<n>.ppm`.
+    dir: PathBuf,
+    count: usize,
+    /// The current line, held back only while it could still turn out to be a marker or while
+    /// a frame is being received.
+    line: Vec<u8>,
+    frame: Option<Frame>,
+}
+
+impl<W: Write> Extractor<W> {
+    pub fn new(out: W, dir: PathBuf) -> Self {
+        Self {
+            out,
+            dir,
+            count: 0,
+            line: Vec::new(),
+            frame: None,
+        }
+    }
+
+    /// Feeds serial output, passing through what is not part of a screenshot.
+    pub fn feed(&mut self, bytes: &[u8]) -> io::Result<()> {
+        for &byte in bytes {
+            if byte == b'\n' {
+                let line = std::mem::take(&mut self.line);
+                self.end_line(&line)?;
+                continue;
+            }
+            self.line.push(byte);
+            if self.frame.is_none() && !self.could_be_marker() {
+                self.out.write_all(&self.line)?;
+                self.line.clear();
+            }
+        }
+        self.out.flush()
+    }
+
+    /// Whether the held back line is still a prefix of the begin marker.
+    fn could_be_marker(&self) -> bool {
+        let len = self.line.len().min(BEGIN_MARKER.len());
+        self.line[..len] == BEGIN_MARKER.as_bytes()[..len]
+    }
+
+    fn end_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let text = String::from_utf8_lossy(line);
+        let text = text.trim_end_matches('\r');
+
+        if let Some(frame) = &mut self.frame {
+            if let Some(crc) = text.strip_prefix(END_MARKER) {
+                let crc = u32::from_str_radix(crc.trim(), 16).ok();
+                let frame = self.frame.take().unwrap();
+                self.save(frame, crc);
+            } else if !text.is_empty() && text.bytes().all(is_base64) {
+                frame.base64.extend_from_slice(text.as_bytes());
+            } else {
+                // Something else logged in the middle of the transfer.
+                writeln!(self.out, "{}", text)?;
+            }
+            return Ok(());
+        }
+
+        match text.strip_prefix(BEGIN_MARKER).and_then(parse_begin) {
+            Some((width, height, size)) => {
+                eprintln!("[screenshot] receiving {width}x{height}...");
+                self.frame = Some(Frame {
+                    width,
+                    height,
+                    size,
+                    base64: Vec::new(),
+                });
+            }
+            None => {
+                self.out.write_all(line)?;
+                self.out.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn save(&mut self, frame: Frame, crc: Option<u32>) {
+        let Some(data) = decode_base64(&frame.base64) else {
+            eprintln!("[screenshot] dropped: bad base64");
+            return;
+        };
+        if data.len() != frame.size {
+            eprintln!(
+                "[screenshot] dropped: got {} bytes, expected {}",
+                data.len(),
+                frame.size
+            );
+            return;
+        }
+        if crc != Some(crc32(&data)) {
+            eprintln!("[screenshot] dropped: checksum mismatch");
+            return;
+        }
+
+        self.count += 1;
+        let path = self.dir.join(format!("screenshot-{}.ppm", self.count));
+        match fs::write(&path, &data) {
+            Ok(()) => eprintln!(
+                "[screenshot] saved {}x{} to {}",
+                frame.width,
+                frame.height,
+                path.display()
+            ),
+            Err(err) => eprintln!("[screenshot] failed to write {}: {err}", path.display()),
+        }
+    }
+}
+
+fn parse_begin(args: &str) -> Option<(usize, usize, usize)> {
+    let mut fields = args.split_whitespace().map(|field| field.parse().ok());
+    Some((fields.next()??, fields.next()??, fields.next()??))
+}
+
+fn is_base64(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'+' | b'/' | b'=')
+}
+
+fn decode_base64(text: &[u8]) -> Option<Vec<u8>> {
+    fn value(byte: u8) -> Option<u32> {
+        Some(match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        } as u32)
+    }
+
+    if text.len() % 4 != 0 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    for group in text.as_chunks::<4>().0 {
+        let padding = group.iter().rev().take_while(|&&byte| byte == b'=').count();
+        if padding > 2 {
+            return None;
+        }
+        let mut bits = 0;
+        for &byte in &group[..4 - padding] {
+            bits = (bits << 6) | value(byte)?;
+        }
+        bits <<= 6 * padding;
+        out.extend_from_slice(&bits.to_be_bytes()[1..4 - padding]);
+    }
+    Some(out)
+}
+
+/// The reflected CRC-32 used by zlib and PNG, matching the kernel's.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 1x1 PPM, with its base64 encoding and CRC-32.
+    const PPM: &[u8] = b"P6\n1 1\n255\n\x01\x02\x03";
+    const PPM_BASE64: &str = "UDYKMSAxCjI1NQoBAgM=";
+    const PPM_CRC: u32 = 0x72e2_9b64;
+
+    fn extractor(name: &str) -> Extractor<Vec<u8>> {
+        let dir = std::env::temp_dir().join(format!(
+            "phils-rust-os-screenshot-{}-{name}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Extractor::new(Vec::new(), dir)
+    }
+
+    fn stream(crc: u32) -> String {
+        format!(
+            "boot ok\n@@SCREENSHOT BEGIN 1 1 {}\r\n{}\n@@SCREENSHOT END {crc:08x}\nafter\n",
+            PPM.len(),
+            PPM_BASE64
+        )
+    }
+
+    #[test]
+    fn extracts_frames_split_across_chunks() {
+        let stream = stream(PPM_CRC);
+        for chunk in [1, 3, 7, 19, stream.len()] {
+            let mut extractor = extractor(&format!("chunks-{chunk}"));
+            for bytes in stream.as_bytes().chunks(chunk) {
+                extractor.feed(bytes).unwrap();
+            }
+
+            assert_eq!(extractor.out, b"boot ok\nafter\n", "chunks of {chunk}");
+            assert_eq!(extractor.count, 1);
+            let saved = fs::read(extractor.dir.join("screenshot-1.ppm")).unwrap();
+            assert_eq!(saved, PPM);
+            fs::remove_dir_all(&extractor.dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn extracts_what_the_kernel_sends() {
+        // The kernel's tests check that this is what it sends for the frame below.
+        let stream = include_str!("../kernel/images/screenshot-sample.txt");
+        let mut ppm = b"P6\n8 4\n255\n".to_vec();
+        for y in 0..4u8 {
+            for x in 0..8u8 {
+                ppm.extend([x * 32, y * 64, 0x80]);
+            }
+        }
+
+        let mut extractor = extractor("kernel");
+        extractor.feed(stream.as_bytes()).unwrap();
+        assert_eq!(extractor.out, b"\n");
+        assert_eq!(extractor.count, 1);
+        let saved = fs::read(extractor.dir.join("screenshot-1.ppm")).unwrap();
+        assert_eq!(saved, ppm);
+        fs::remove_dir_all(&extractor.dir).unwrap();
+    }
+
+    #[test]
+    fn passes_through_lines_that_only_start_like_a_marker() {
+        let mut extractor = extractor("prefix");
+        extractor.feed(b"@@SCREEN").unwrap();
+        assert!(extractor.out.is_empty());
+        extractor.feed(b"SAVER on\n@@SCREENSHOT BEGIN x\n").unwrap();
+        assert_eq!(extractor.out, b"@@SCREENSAVER on\n@@SCREENSHOT BEGIN x\n");
+        assert!(extractor.frame.is_none());
+    }
+
+    #[test]
+    fn passes_through_log_lines_inside_a_frame() {
+        let mut extractor = extractor("interleaved");
+        let stream = format!(
+            "@@SCREENSHOT BEGIN 1 1 {}\n[INFO] tick\n{PPM_BASE64}\n@@SCREENSHOT END {PPM_CRC:08x}\n",
+            PPM.len()
+        );
+        extractor.feed(stream.as_bytes()).unwrap();
+        assert_eq!(extractor.out, b"[INFO] tick\n");
+        assert_eq!(extractor.count, 1);
+        fs::remove_dir_all(&extractor.dir).unwrap();
+    }
+
+    #[test]
+    fn drops_frames_with_a_bad_checksum() {
+        let mut extractor = extractor("crc");
+        extractor.feed(stream(PPM_CRC ^ 1).as_bytes()).unwrap();
+        assert_eq!(extractor.out, b"boot ok\nafter\n");
+        assert_eq!(extractor.count, 0);
+        assert!(!extractor.dir.join("screenshot-1.ppm").exists());
+    }
+
+    #[test]
+    fn drops_frames_of_the_wrong_size() {
+        let mut extractor = extractor("size");
+        let stream = format!(
+            "@@SCREENSHOT BEGIN 1 1 {}\n{PPM_BASE64}\n@@SCREENSHOT END {PPM_CRC:08x}\n",
+            PPM.len() + 1
+        );
+        extractor.feed(stream.as_bytes()).unwrap();
+        assert_eq!(extractor.count, 0);
+    }
+
+    #[test]
+    fn decodes_base64() {
+        assert_eq!(decode_base64(b"").unwrap(), b"");
+        assert_eq!(decode_base64(b"TWFu").unwrap(), b"Man");
+        assert_eq!(decode_base64(b"TWE=").unwrap(), b"Ma");
+        assert_eq!(decode_base64(b"TQ==").unwrap(), b"M");
+        assert_eq!(decode_base64(PPM_BASE64.as_bytes()).unwrap(), PPM);
+        assert_eq!(decode_base64(b"+/+/").unwrap(), [0xfb, 0xff, 0xbf]);
+    }
+
+    #[test]
+    fn rejects_malformed_base64() {
+        assert_eq!(decode_base64(b"TWF"), None);
+        assert_eq!(decode_base64(b"T==="), None);
+        assert_eq!(decode_base64(b"TW-u"), None);
+        assert_eq!(decode_base64(b"TW=u"), None);
+    }
+
+    #[test]
+    fn crc32_matches_zlib() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(PPM), PPM_CRC);
+    }
+
+    #[test]
+    fn parses_begin_arguments() {
+        assert_eq!(parse_begin(" 640 480 921615"), Some((640, 480, 921615)));
+        assert_eq!(parse_begin(" 640 480"), None);
+        assert_eq!(parse_begin(" 640 x 1"), None);
+    }
+}